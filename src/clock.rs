@@ -0,0 +1,68 @@
+use chrono::{DateTime, Duration, Utc};
+use serenity::async_trait;
+use tokio::sync::watch;
+
+// The source of the current time. Everything that depends on the day, the week or the
+// scheduled jobs asks the clock, so the time can be controlled with `FakeClock`.
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    // Waits until the clock reaches the deadline
+    async fn sleep_until(&self, deadline: DateTime<Utc>);
+
+    async fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration).await
+    }
+}
+
+// The clock of the system
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn sleep_until(&self, deadline: DateTime<Utc>) {
+        if let Ok(duration) = (deadline - Utc::now()).to_std() {
+            tokio::time::sleep(duration).await;
+        }
+    }
+}
+
+// A clock that only moves when it is told to. The tasks sleeping on it wake up
+// as soon as it is set or advanced past their deadline.
+pub struct FakeClock {
+    now: watch::Sender<DateTime<Utc>>,
+}
+
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        FakeClock {
+            now: watch::Sender::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.now.send_replace(now);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+#[async_trait]
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    async fn sleep_until(&self, deadline: DateTime<Utc>) {
+        let mut receiver = self.now.subscribe();
+        // The sender lives as long as the clock, so waiting cannot fail
+        let _ = receiver.wait_for(|now| *now >= deadline).await;
+    }
+}
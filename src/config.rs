@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use tracing::info;
@@ -16,9 +17,31 @@ pub struct EnvConfig {
     pub discord_guild: u64,
     pub attendance_channel: u64,
     pub lotto_channel: u64,
+    // Bonus points credited when the attendance streak reaches the number of days
+    #[serde(default = "default_streak_bonus")]
+    pub streak_bonus: BTreeMap<i32, i32>,
+    // The channel for the reports, error notifications and exchange batches
+    #[serde(default = "default_admin_channel")]
+    pub admin_channel: u64,
+    // The days before a user can change the registered wallet again
+    #[serde(default = "default_wallet_cooldown_days")]
+    pub wallet_cooldown_days: i64,
+}
+
+fn default_wallet_cooldown_days() -> i64 {
+    7
+}
+
+fn default_admin_channel() -> u64 {
+    1054296641651347486
+}
+
+fn default_streak_bonus() -> BTreeMap<i32, i32> {
+    BTreeMap::from([(7, 300), (30, 1500), (100, 5000)])
 }
 
 impl Config {
+    #[allow(clippy::new_ret_no_self)]
     pub async fn new(file_path: &str) -> Result<EnvConfig, Box<dyn std::error::Error>> {
         let contents = fs::read_to_string(file_path)?;
         let config: Self = serde_yaml::from_str(&contents)?;
@@ -37,10 +60,10 @@ impl Config {
 
         env::set_var("DISCORD_TOKEN", &env_config.discord_token);
         env::set_var("MONGO_URI", &env_config.mongo_uri);
-        env::set_var("DISCORD_GUILD", &env_config.discord_guild.to_string());
+        env::set_var("DISCORD_GUILD", env_config.discord_guild.to_string());
         env::set_var(
             "ATTENDANCE_CHANNEL",
-            &env_config.attendance_channel.to_string(),
+            env_config.attendance_channel.to_string(),
         );
 
         Ok(env_config)
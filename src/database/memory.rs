@@ -0,0 +1,1409 @@
+use bson::oid::ObjectId;
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use serenity::async_trait;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::clock::Clock;
+use crate::util::{
+    calculate_lotto_points, draw_lotto_numbers, generate_lotto_seed, lotto_commitment,
+};
+
+use super::models::{
+    Activity, ActivityType, AttendanceReward, AttendanceStreak, CatalogItem, Exchange,
+    ExchangeOutcome, ExchangeStatus, FulfilmentResult, FulfilmentStatus, JobFence, JobState,
+    LedgerEntry, LottoDraw, LottoEntryOutcome, LottoGuess, LottoRound, LottoRoundSummary,
+    LottoRules, PointsAdjustment, PointsDrift, PointsReason, RankedUser, UserWallet, WalletAudit,
+    WalletUpdate,
+};
+use super::store::{Store, StoreResult};
+
+// Keeps everything the bot stores in memory with the same rules as `MongoDB`,
+// so the commands and the scheduled jobs can be run without a database
+pub struct MemoryStore {
+    clock: Arc<dyn Clock>,
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    catalog: BTreeMap<String, CatalogItem>,
+    users: BTreeMap<String, User>,
+    ledger: Vec<LedgerEntry>,
+    wallet_audits: Vec<WalletAudit>,
+    exchanges: Vec<Exchange>,
+    activities: Vec<Activity>,
+    lotto_rules: Vec<LottoRules>,
+    draws: Vec<LottoDraw>,
+    guesses: Vec<LottoGuess>,
+    jobs: BTreeMap<String, JobState>,
+    // The token of the last recorded run of every job
+    job_tokens: HashMap<String, i64>,
+    leases: HashMap<String, JobLease>,
+}
+
+// The fields of a user document
+#[derive(Default)]
+struct User {
+    user_name: Option<String>,
+    points: i32,
+    streak: AttendanceStreak,
+    last_attend_date: Option<NaiveDate>,
+    wallet: Option<UserWallet>,
+    lotto_favorite: Option<Vec<i32>>,
+    lotto_mention: bool,
+}
+
+struct JobLease {
+    owner: String,
+    token: i64,
+    expires_at: chrono::DateTime<Utc>,
+}
+
+impl MemoryStore {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        MemoryStore {
+            clock,
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+
+    // Stores lotto rules, which take over from their effective date
+    pub fn add_lotto_rules(&self, rules: LottoRules) {
+        self.state().lotto_rules.push(rules);
+    }
+
+    // A panic in another call leaves the state as consistent as a failed write would
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl MemoryState {
+    fn user(&mut self, user_id: &str) -> &mut User {
+        self.users.entry(user_id.to_string()).or_default()
+    }
+
+    fn user_points(&self, user_id: &str) -> i32 {
+        self.users
+            .get(user_id)
+            .map(|user| user.points)
+            .unwrap_or_default()
+    }
+
+    // A credit stops at the maximum points and a penalty stops at zero
+    fn apply_capped_points(
+        &mut self,
+        user_id: &str,
+        user_name: Option<&str>,
+        points: i32,
+    ) -> PointsAdjustment {
+        let user = self.user(user_id);
+        if user.user_name.is_none() {
+            user.user_name = user_name.map(String::from);
+        }
+
+        let adjustment = PointsAdjustment::capped(user.points, points);
+        user.points = adjustment.balance;
+        adjustment
+    }
+
+    fn add_ledger_entry(
+        &mut self,
+        user_id: &str,
+        delta: i32,
+        reason: PointsReason,
+        source_id: Option<String>,
+        now: chrono::DateTime<Utc>,
+    ) {
+        self.ledger.push(LedgerEntry {
+            id: Some(ObjectId::new()),
+            user_id: user_id.to_string(),
+            delta,
+            reason,
+            source_id,
+            created_at: now,
+        });
+    }
+
+    fn count_activities(
+        &self,
+        dc_id: u64,
+        activity: ActivityType,
+        since: chrono::DateTime<Utc>,
+    ) -> usize {
+        self.activities
+            .iter()
+            .filter(|a| a.dc_id == dc_id && a.activity == Some(activity) && a.created_at >= since)
+            .count()
+    }
+
+    fn lotto_rules_at(&self, at: chrono::DateTime<Utc>) -> LottoRules {
+        self.lotto_rules
+            .iter()
+            .filter(|rules| rules.effective_from <= at)
+            .max_by_key(|rules| rules.effective_from)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Fails unless the run still holds the lease of the fence
+    fn hold_fence(&self, fence: Option<JobFence>, now: chrono::DateTime<Utc>) -> StoreResult<()> {
+        let fence = match fence {
+            Some(fence) => fence,
+            None => return Ok(()),
+        };
+        match self.leases.get(fence.job) {
+            Some(lease) if lease.token == fence.token && lease.expires_at > now => Ok(()),
+            _ => Err(format!("The lease of {} is lost", fence).into()),
+        }
+    }
+
+    fn lotto_draw(&mut self, round: LottoRound) -> Option<&mut LottoDraw> {
+        self.draws.iter_mut().find(|draw| draw.round == round)
+    }
+
+    // Only the first call of the week stores its seed, so the commitment never changes
+    fn open_weekly_draw(&mut self, now: chrono::DateTime<Utc>) -> LottoDraw {
+        let round = LottoRound::at(now);
+        if let Some(draw) = self.lotto_draw(round) {
+            return draw.clone();
+        }
+
+        let seed = generate_lotto_seed();
+        let draw = LottoDraw {
+            id: Some(ObjectId::new()),
+            round,
+            numbers: Vec::new(),
+            commitment: Some(lotto_commitment(&seed)),
+            seed: Some(seed),
+            drawn_at: None,
+            rules: Some(self.lotto_rules_at(round.starts_at())),
+            jackpot: 0,
+            jackpot_winners: None,
+            jackpot_prize: None,
+            jackpot_rollover: None,
+            date: now,
+        };
+        self.draws.push(draw.clone());
+        draw
+    }
+
+    fn lotto_round_summary(&self, draw: LottoDraw) -> LottoRoundSummary {
+        let mut summary = LottoRoundSummary {
+            draw,
+            entries: 0,
+            winners: Default::default(),
+            points_paid: 0,
+        };
+        for guess in self
+            .guesses
+            .iter()
+            .filter(|g| g.round == summary.draw.round)
+        {
+            summary.entries += 1;
+            summary.points_paid += guess.points.unwrap_or_default() as i64;
+            if let Some(matches) = guess.matched_count.filter(|matches| *matches > 0) {
+                *summary.winners.entry(matches as u32).or_default() += 1;
+            }
+        }
+
+        summary
+    }
+
+    fn job(&mut self, name: &str) -> &mut JobState {
+        self.jobs
+            .entry(name.to_string())
+            .or_insert_with(|| JobState {
+                name: name.to_string(),
+                ..Default::default()
+            })
+    }
+}
+
+// Takes a page of the items, all of them if the page size is 0
+fn page<T>(items: Vec<T>, page: u64, page_size: u64) -> Vec<T> {
+    let items = items.into_iter().skip((page * page_size) as usize);
+    match page_size {
+        0 => items.collect(),
+        page_size => items.take(page_size as usize).collect(),
+    }
+}
+
+// Takes the first items, all of them if the limit is not positive
+fn limit<T>(items: Vec<T>, limit: i64) -> Vec<T> {
+    match usize::try_from(limit) {
+        Ok(limit) if limit > 0 => items.into_iter().take(limit).collect(),
+        _ => items,
+    }
+}
+
+fn is_counted_exchange(exchange: &Exchange) -> bool {
+    !matches!(
+        exchange.status,
+        ExchangeStatus::Rejected | ExchangeStatus::Refunded
+    )
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn open_catalog(&self) -> StoreResult<bool> {
+        let mut state = self.state();
+        if !state.catalog.is_empty() {
+            return Ok(false);
+        }
+
+        let ticket = CatalogItem {
+            id: "ticket".to_string(),
+            name: "Tournament ticket".to_string(),
+            price: 1000,
+            ..Default::default()
+        };
+        state.catalog.insert(ticket.id.clone(), ticket);
+
+        Ok(true)
+    }
+
+    async fn get_catalog_item(&self, id: &str) -> StoreResult<Option<CatalogItem>> {
+        Ok(self.state().catalog.get(id).cloned())
+    }
+
+    async fn get_catalog_items(&self, active_only: bool) -> StoreResult<Vec<CatalogItem>> {
+        let now = self.clock.now();
+        // The catalog is ordered by the ID, so the stable sort keeps it for equal prices
+        let mut items: Vec<CatalogItem> = self
+            .state()
+            .catalog
+            .values()
+            .filter(|item| !active_only || item.is_active(now))
+            .cloned()
+            .collect();
+        items.sort_by_key(|item| item.price);
+
+        Ok(items)
+    }
+
+    async fn set_catalog_item(&self, item: &CatalogItem) -> StoreResult<()> {
+        self.state().catalog.insert(item.id.clone(), item.clone());
+
+        Ok(())
+    }
+
+    async fn add_exchange_record(&self, exchange: Exchange) -> StoreResult<ExchangeOutcome> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let user_id = exchange.dc_id.to_string();
+
+        let item = match state.catalog.get(&exchange.item) {
+            Some(item) if item.is_active(now) => item.clone(),
+            _ => return Ok(ExchangeOutcome::Unavailable),
+        };
+
+        // Sum the quantity of the item the user exchanged since Monday
+        if let Some(limit) = item.weekly_limit {
+            let today = now.date_naive();
+            let monday = (today - Duration::days(today.weekday().num_days_from_monday() as i64))
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc();
+            let exchanged: i64 = state
+                .exchanges
+                .iter()
+                .filter(|e| {
+                    e.dc_id == exchange.dc_id
+                        && e.item == item.id
+                        && is_counted_exchange(e)
+                        && e.created_at >= monday
+                })
+                .map(|e| e.quantity)
+                .sum();
+            if exchanged + exchange.quantity > limit {
+                return Ok(ExchangeOutcome::LimitReached((limit - exchanged).max(0)));
+            }
+        }
+
+        if item.stock.is_some_and(|stock| stock < exchange.quantity) {
+            return Ok(ExchangeOutcome::OutOfStock);
+        }
+
+        // A total beyond any balance is rejected like any other unaffordable exchange
+        let required_points = match item.total_price(exchange.quantity) {
+            Some(required_points) => required_points,
+            None => return Ok(ExchangeOutcome::NotEnoughPoints),
+        };
+        if !state.users.contains_key(&user_id) || state.user_points(&user_id) < required_points {
+            return Ok(ExchangeOutcome::NotEnoughPoints);
+        }
+
+        // Every check passed, so the changes are applied together
+        if let Some(stock) = state
+            .catalog
+            .get_mut(&item.id)
+            .and_then(|item| item.stock.as_mut())
+        {
+            *stock -= exchange.quantity;
+        }
+        let user = state.user(&user_id);
+        user.points -= required_points;
+        let balance = user.points;
+
+        let id = exchange.id.unwrap_or_default();
+        state.exchanges.push(Exchange {
+            id: Some(id),
+            points: Some(required_points),
+            ..exchange
+        });
+        state.add_ledger_entry(
+            &user_id,
+            -required_points,
+            PointsReason::Exchange,
+            Some(id.to_hex()),
+            now,
+        );
+
+        Ok(ExchangeOutcome::Accepted(PointsAdjustment {
+            balance,
+            applied: -required_points,
+        }))
+    }
+
+    async fn get_user_records(&self, dc_id: u64) -> StoreResult<Vec<Exchange>> {
+        let mut records: Vec<Exchange> = self
+            .state()
+            .exchanges
+            .iter()
+            .filter(|exchange| exchange.dc_id == dc_id)
+            .cloned()
+            .collect();
+        records.sort_by_key(|exchange| Reverse(exchange.updated_at));
+
+        Ok(limit(records, 8))
+    }
+
+    async fn get_exchanges_by_status(
+        &self,
+        statuses: &[ExchangeStatus],
+        limit_count: i64,
+    ) -> StoreResult<Vec<Exchange>> {
+        let mut records: Vec<Exchange> = self
+            .state()
+            .exchanges
+            .iter()
+            .filter(|exchange| statuses.contains(&exchange.status))
+            .cloned()
+            .collect();
+        records.sort_by_key(|exchange| exchange.created_at);
+
+        Ok(limit(records, limit_count))
+    }
+
+    async fn update_exchange_status(
+        &self,
+        id: ObjectId,
+        from: &[ExchangeStatus],
+        to: ExchangeStatus,
+        reject_reason: Option<String>,
+    ) -> StoreResult<Option<Exchange>> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let exchange = match state
+            .exchanges
+            .iter_mut()
+            .find(|exchange| exchange.id == Some(id) && from.contains(&exchange.status))
+        {
+            Some(exchange) => exchange,
+            None => return Ok(None),
+        };
+
+        exchange.status = to;
+        if reject_reason.is_some() {
+            exchange.reject_reason = reject_reason;
+        }
+        exchange.updated_at = now;
+
+        Ok(Some(exchange.clone()))
+    }
+
+    async fn refund_exchange(&self, id: ObjectId) -> StoreResult<Option<Exchange>> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let exchange =
+            match state.exchanges.iter_mut().find(|exchange| {
+                exchange.id == Some(id) && exchange.status == ExchangeStatus::Rejected
+            }) {
+                Some(exchange) => {
+                    exchange.status = ExchangeStatus::Refunded;
+                    exchange.updated_at = now;
+                    exchange.clone()
+                }
+                None => return Ok(None),
+            };
+
+        // Put the items back in stock, unless the stock is unlimited
+        if let Some(stock) = state
+            .catalog
+            .get_mut(&exchange.item)
+            .and_then(|item| item.stock.as_mut())
+        {
+            *stock += exchange.quantity;
+        }
+
+        // The refund gives back exactly what was spent, regardless of the maximum points
+        let user_id = exchange.dc_id.to_string();
+        let points = exchange.refund_points();
+        state.user(&user_id).points += points;
+        state.add_ledger_entry(
+            &user_id,
+            points,
+            PointsReason::Refund,
+            Some(id.to_hex()),
+            now,
+        );
+
+        Ok(Some(exchange))
+    }
+
+    async fn update_all_submitted_to_processing(
+        &self,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Vec<Exchange>> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        state.hold_fence(fence, now)?;
+        let mut batch = Vec::new();
+        for exchange in state.exchanges.iter_mut() {
+            if exchange.status == ExchangeStatus::Submitted {
+                exchange.status = ExchangeStatus::Processing;
+                exchange.updated_at = now;
+                batch.push(exchange.clone());
+            }
+        }
+        batch.sort_by_key(|exchange| exchange.created_at);
+
+        Ok(batch)
+    }
+
+    async fn get_exchanges_between(
+        &self,
+        from: chrono::DateTime<Utc>,
+        to: chrono::DateTime<Utc>,
+    ) -> StoreResult<Vec<Exchange>> {
+        let mut records: Vec<Exchange> = self
+            .state()
+            .exchanges
+            .iter()
+            .filter(|exchange| {
+                from <= exchange.created_at
+                    && exchange.created_at < to
+                    && is_counted_exchange(exchange)
+            })
+            .cloned()
+            .collect();
+        records.sort_by_key(|exchange| exchange.created_at);
+
+        Ok(records)
+    }
+
+    async fn apply_fulfilment_result(
+        &self,
+        id: ObjectId,
+        result: &FulfilmentResult,
+    ) -> StoreResult<Option<Exchange>> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let exchange = match state.exchanges.iter_mut().find(|exchange| {
+            exchange.id == Some(id) && exchange.status == ExchangeStatus::Processing
+        }) {
+            Some(exchange) => exchange,
+            None => return Ok(None),
+        };
+
+        match result.status {
+            FulfilmentStatus::Completed => {
+                exchange.status = ExchangeStatus::Completed;
+                exchange.tx_hash = result.tx_hash.clone();
+                exchange.fulfilment_error = None;
+            }
+            FulfilmentStatus::Failed => {
+                exchange.fulfilment_error = Some(
+                    result
+                        .error
+                        .clone()
+                        .unwrap_or_else(|| "Unknown error".to_string()),
+                );
+            }
+        }
+        exchange.updated_at = now;
+
+        Ok(Some(exchange.clone()))
+    }
+
+    async fn get_user_points(&self, user_id: &str) -> StoreResult<i32> {
+        Ok(self.state().user_points(user_id))
+    }
+
+    async fn adjust_user_points(
+        &self,
+        user_id: &str,
+        user_name: Option<&str>,
+        points: i32,
+        reason: PointsReason,
+        source_id: Option<String>,
+    ) -> StoreResult<PointsAdjustment> {
+        let now = self.clock.now();
+        let mut state = self.state();
+
+        let adjustment = if points < 0 && reason.is_spend() {
+            // A spend is only applied if the balance covers it
+            match state.users.get_mut(user_id) {
+                Some(user) if user.points >= -points => {
+                    user.points += points;
+                    PointsAdjustment {
+                        balance: user.points,
+                        applied: points,
+                    }
+                }
+                _ => PointsAdjustment {
+                    balance: state.user_points(user_id),
+                    applied: 0,
+                },
+            }
+        } else {
+            state.apply_capped_points(user_id, user_name, points)
+        };
+
+        // Record the change that was actually applied in the points ledger
+        if adjustment.applied != 0 {
+            state.add_ledger_entry(user_id, adjustment.applied, reason, source_id, now);
+        }
+
+        Ok(adjustment)
+    }
+
+    async fn add_ledger_entry(&self, entry: LedgerEntry) -> StoreResult<()> {
+        self.state().ledger.push(LedgerEntry {
+            id: Some(entry.id.unwrap_or_default()),
+            ..entry
+        });
+
+        Ok(())
+    }
+
+    async fn open_points_ledger(&self) -> StoreResult<u64> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let unopened: Vec<(String, i32)> = state
+            .users
+            .iter()
+            .filter(|(user_id, user)| {
+                user.points != 0 && !state.ledger.iter().any(|entry| &entry.user_id == *user_id)
+            })
+            .map(|(user_id, user)| (user_id.clone(), user.points))
+            .collect();
+
+        for (user_id, points) in &unopened {
+            state.add_ledger_entry(user_id, *points, PointsReason::Opening, None, now);
+        }
+
+        Ok(unopened.len() as u64)
+    }
+
+    async fn reconcile_user_points(&self, user_id: &str) -> StoreResult<PointsDrift> {
+        let state = self.state();
+        let ledger_points = state
+            .ledger
+            .iter()
+            .filter(|entry| entry.user_id == user_id)
+            .map(|entry| entry.delta as i64)
+            .sum();
+
+        Ok(PointsDrift {
+            ledger_points,
+            stored_points: state.user_points(user_id),
+        })
+    }
+
+    async fn reconcile_all_points(&self) -> StoreResult<Vec<(String, PointsDrift)>> {
+        let user_ids: Vec<String> = self.state().users.keys().cloned().collect();
+
+        let mut drifts = Vec::new();
+        for user_id in user_ids {
+            let drift = self.reconcile_user_points(&user_id).await?;
+            if drift.drift() != 0 {
+                drifts.push((user_id, drift));
+            }
+        }
+
+        Ok(drifts)
+    }
+
+    async fn get_user_streak(&self, user_id: &str) -> StoreResult<AttendanceStreak> {
+        let yesterday = self.clock.now().date_naive() - Duration::days(1);
+        let state = self.state();
+        let user = match state.users.get(user_id) {
+            Some(user) => user,
+            None => return Ok(AttendanceStreak::default()),
+        };
+
+        // The streak is broken if the user did not check in yesterday or today
+        let mut streak = user.streak;
+        if user.last_attend_date.is_none_or(|date| date < yesterday) {
+            streak.current = 0;
+        }
+
+        Ok(streak)
+    }
+
+    async fn record_attendance(
+        &self,
+        activity: Activity,
+        user_name: Option<&str>,
+        streak_bonus: &BTreeMap<i32, i32>,
+    ) -> StoreResult<Option<AttendanceReward>> {
+        let now = self.clock.now();
+        let today = now.date_naive();
+        let user_id = activity.dc_id.to_string();
+        let mut state = self.state();
+
+        // The attendance can only be checked once per day
+        let today_start = today.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        if state.count_activities(activity.dc_id, ActivityType::Attend, today_start) > 0 {
+            return Ok(None);
+        }
+        let points = activity.reward;
+        state.activities.push(activity);
+
+        let user = state.users.entry(user_id.clone()).or_insert_with(|| User {
+            user_name: user_name.map(String::from),
+            ..Default::default()
+        });
+        let current = match user.last_attend_date {
+            Some(date) if date == today => user.streak.current,
+            Some(date) if date == today - Duration::days(1) => user.streak.current + 1,
+            _ => 1,
+        };
+        user.streak = AttendanceStreak {
+            current,
+            best: user.streak.best.max(current),
+        };
+        user.last_attend_date = Some(today);
+        let streak = user.streak;
+
+        let points = state
+            .apply_capped_points(&user_id, user_name, points)
+            .applied;
+        if points != 0 {
+            state.add_ledger_entry(&user_id, points, PointsReason::Attend, None, now);
+        }
+        let bonus = match streak_bonus.get(&streak.current) {
+            Some(&bonus) if bonus > 0 => {
+                let bonus = state
+                    .apply_capped_points(&user_id, user_name, bonus)
+                    .applied;
+                if bonus != 0 {
+                    state.add_ledger_entry(&user_id, bonus, PointsReason::StreakBonus, None, now);
+                }
+                bonus
+            }
+            _ => 0,
+        };
+
+        Ok(Some(AttendanceReward {
+            streak,
+            points,
+            bonus,
+        }))
+    }
+
+    async fn get_user_wallet(&self, user_id: &str) -> StoreResult<Option<UserWallet>> {
+        Ok(self
+            .state()
+            .users
+            .get(user_id)
+            .and_then(|user| user.wallet.clone()))
+    }
+
+    async fn set_user_wallet(
+        &self,
+        user_id: &str,
+        user_name: &str,
+        address: &str,
+        verified: bool,
+        cooldown: Duration,
+    ) -> StoreResult<WalletUpdate> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let user = state.user(user_id);
+
+        // Registering the same wallet again is always allowed, e.g. to verify it
+        let previous = user.wallet.clone();
+        let wallet = match &previous {
+            Some(wallet) if wallet.address == address => UserWallet {
+                address: address.to_string(),
+                verified: wallet.verified || verified,
+                updated_at: wallet.updated_at,
+            },
+            Some(wallet) if wallet.updated_at > now - cooldown => {
+                return Ok(WalletUpdate::Cooldown)
+            }
+            _ => UserWallet {
+                address: address.to_string(),
+                verified,
+                updated_at: now,
+            },
+        };
+        user.wallet = Some(wallet);
+        user.user_name = Some(user_name.to_string());
+
+        let changed = match &previous {
+            Some(wallet) => wallet.address != address || (verified && !wallet.verified),
+            None => true,
+        };
+        if !changed {
+            return Ok(WalletUpdate::Unchanged);
+        }
+
+        let audit = WalletAudit {
+            id: Some(ObjectId::new()),
+            user_id: user_id.to_string(),
+            user_name: user_name.to_string(),
+            previous_address: previous.map(|wallet| wallet.address),
+            address: address.to_string(),
+            verified,
+            created_at: now,
+        };
+        state.wallet_audits.push(audit.clone());
+
+        Ok(WalletUpdate::Changed(audit))
+    }
+
+    async fn get_wallet_audits(
+        &self,
+        user_id: &str,
+        limit_count: i64,
+    ) -> StoreResult<Vec<WalletAudit>> {
+        // The audits are stored oldest first
+        let audits: Vec<WalletAudit> = self
+            .state()
+            .wallet_audits
+            .iter()
+            .rev()
+            .filter(|audit| audit.user_id == user_id)
+            .cloned()
+            .collect();
+
+        Ok(limit(audits, limit_count))
+    }
+
+    async fn get_top_users(&self, limit_count: i64) -> StoreResult<Vec<RankedUser>> {
+        // The users are ordered by the ID, so the stable sort keeps it for equal points
+        let mut users: Vec<RankedUser> = self
+            .state()
+            .users
+            .iter()
+            .filter(|(_, user)| user.points > 0)
+            .map(|(user_id, user)| RankedUser {
+                dc_id: user_id.clone(),
+                user_name: user.user_name.clone(),
+                points: user.points,
+            })
+            .collect();
+        users.sort_by_key(|user| Reverse(user.points));
+
+        Ok(limit(users, limit_count))
+    }
+
+    async fn get_user_rank(&self, user_id: &str) -> StoreResult<Option<(u64, i32)>> {
+        let state = self.state();
+        let points = match state.users.get(user_id) {
+            Some(user) if user.points > 0 => user.points,
+            _ => return Ok(None),
+        };
+        let ahead = state.users.values().filter(|u| u.points > points).count() as u64;
+
+        Ok(Some((ahead + 1, points)))
+    }
+
+    async fn clean_documents(&self) -> StoreResult<u64> {
+        let about_five_weeks_ago = self.clock.now() - Duration::weeks(5);
+        let mut state = self.state();
+        let count = state.activities.len();
+        state
+            .activities
+            .retain(|activity| activity.created_at >= about_five_weeks_ago);
+
+        Ok((count - state.activities.len()) as u64)
+    }
+
+    async fn count_old_documents(&self) -> StoreResult<u64> {
+        let about_five_weeks_ago = self.clock.now() - Duration::weeks(5);
+        let count = self
+            .state()
+            .activities
+            .iter()
+            .filter(|activity| activity.created_at < about_five_weeks_ago)
+            .count();
+
+        Ok(count as u64)
+    }
+
+    async fn add_react_poll_activity(&self, new_activity: Activity) -> StoreResult<bool> {
+        let today = self.clock.now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+        let mut state = self.state();
+
+        // A user can vote in two polls per day
+        if state.count_activities(new_activity.dc_id, ActivityType::Poll, today.and_utc()) >= 2 {
+            return Ok(false);
+        }
+
+        // And once in every poll, regardless of the day
+        let has_same_message_id = state.activities.iter().any(|activity| {
+            activity.dc_id == new_activity.dc_id
+                && activity.activity == Some(ActivityType::Poll)
+                && activity.message_id == new_activity.message_id
+        });
+        if has_same_message_id {
+            return Ok(false);
+        }
+
+        state.activities.push(new_activity);
+
+        Ok(true)
+    }
+
+    async fn add_reaction_activity(&self, activity: Activity) -> StoreResult<bool> {
+        let today = self.clock.now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+        let mut state = self.state();
+
+        let reaction = activity.activity.unwrap();
+        let record_count = state.count_activities(activity.dc_id, reaction, today.and_utc());
+        match reaction {
+            ActivityType::React if record_count > 4 => return Ok(false),
+            ActivityType::Receive if record_count > 9 => return Ok(false),
+            _ => {}
+        }
+        state.activities.push(activity);
+
+        Ok(true)
+    }
+
+    async fn add_weekly_draw(&self) -> StoreResult<LottoDraw> {
+        let now = self.clock.now();
+
+        Ok(self.state().open_weekly_draw(now))
+    }
+
+    // The rounds are always stored by their ISO week, so there is nothing to move
+    async fn open_lotto_rounds(&self) -> StoreResult<u64> {
+        Ok(0)
+    }
+
+    async fn get_lotto_rules(&self, at: chrono::DateTime<Utc>) -> StoreResult<LottoRules> {
+        let rules = self.state().lotto_rules_at(at);
+        rules.validate().map_err(|e| {
+            format!(
+                "Invalid lotto rules from {}: {}",
+                rules.effective_from.date_naive(),
+                e
+            )
+        })?;
+
+        Ok(rules)
+    }
+
+    async fn get_lotto_round(&self, round: LottoRound) -> StoreResult<Option<LottoDraw>> {
+        Ok(self.state().lotto_draw(round).cloned())
+    }
+
+    async fn get_undrawn_lotto_rounds(&self, before: LottoRound) -> StoreResult<Vec<LottoRound>> {
+        let mut rounds: Vec<LottoRound> = self
+            .state()
+            .draws
+            .iter()
+            .filter(|draw| draw.round < before && draw.numbers.is_empty())
+            .map(|draw| draw.round)
+            .collect();
+        rounds.sort();
+
+        Ok(rounds)
+    }
+
+    async fn close_lotto_round(
+        &self,
+        round: LottoRound,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Option<LottoDraw>> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        state.hold_fence(fence, now)?;
+        let draw = match state.lotto_draw(round) {
+            Some(draw) => {
+                // The rounds from before the commitment were drawn when they opened
+                if draw.numbers.is_empty() {
+                    let seed = match &draw.seed {
+                        Some(seed) => seed,
+                        None => {
+                            return Err(
+                                format!("Lotto round {} has no seed to draw from", round).into()
+                            )
+                        }
+                    };
+                    draw.numbers = draw_lotto_numbers(seed, draw.rules().digits);
+                    draw.drawn_at = Some(now);
+                }
+                draw.clone()
+            }
+            None => return Ok(None),
+        };
+
+        // The winners who matched all digits split the top prize and the jackpot evenly
+        let rules = draw.rules();
+        let winners = state
+            .guesses
+            .iter()
+            .filter(|guess| guess.round == round && guess.numbers == draw.numbers)
+            .count() as i32;
+        let jackpot_prize = if winners > 0 {
+            draw.jackpot_pool() / winners
+        } else {
+            0
+        };
+
+        // Only the unscored guesses are scored
+        for guess in state
+            .guesses
+            .iter_mut()
+            .filter(|guess| guess.round == round && guess.matched_count.is_none())
+        {
+            let (matches, mut points) =
+                calculate_lotto_points(&guess.numbers, &draw.numbers, &rules);
+            if matches == rules.digits {
+                points = jackpot_prize;
+            }
+            guess.matched_count = Some(matches as i32);
+            guess.is_any_matched = Some(matches > 0);
+            guess.points = Some(points);
+            guess.updated_at = now;
+        }
+
+        // The jackpot is carried over to the current round once,
+        // or only the remainder of the split if somebody won it
+        if draw.jackpot_winners.is_some() {
+            return Ok(Some(draw));
+        }
+        let rollover = if winners > 0 {
+            draw.jackpot_pool() - jackpot_prize * winners
+        } else {
+            draw.jackpot
+        };
+        let current = state.open_weekly_draw(now);
+        if rollover > 0 && current.id != draw.id {
+            if let Some(current) = state.lotto_draw(current.round) {
+                current.jackpot += rollover;
+            }
+        }
+
+        let settled = state.lotto_draw(round).map(|draw| {
+            draw.jackpot_winners = Some(winners);
+            draw.jackpot_prize = Some(jackpot_prize);
+            draw.jackpot_rollover = Some(rollover);
+            draw.clone()
+        });
+
+        Ok(settled)
+    }
+
+    async fn add_lotto_guess(
+        &self,
+        guess: LottoGuess,
+        fee: i32,
+        jackpot_contribution: i32,
+        max_entries: u64,
+    ) -> StoreResult<LottoEntryOutcome> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let user_id = guess.dc_id.to_string();
+
+        // The round only takes entries until its numbers are drawn
+        if state
+            .lotto_draw(guess.round)
+            .is_none_or(|draw| !draw.numbers.is_empty())
+        {
+            return Ok(LottoEntryOutcome::RoundClosed);
+        }
+
+        // If the user has made the maximum number of guesses this week, reject the entry
+        let count = state
+            .guesses
+            .iter()
+            .filter(|g| g.round == guess.round && g.dc_id == guess.dc_id)
+            .count() as u64;
+        if count >= max_entries {
+            return Ok(LottoEntryOutcome::LimitReached);
+        }
+
+        // A free entry is accepted without a balance
+        if fee != 0
+            && state
+                .users
+                .get(&user_id)
+                .is_none_or(|user| user.points < fee)
+        {
+            return Ok(LottoEntryOutcome::NotEnoughPoints);
+        }
+        let user = state.user(&user_id);
+        user.points -= fee;
+        let balance = user.points;
+
+        let guess_id = guess.id.unwrap_or_default();
+        let round = guess.round;
+        state.guesses.push(LottoGuess {
+            id: Some(guess_id),
+            ..guess
+        });
+
+        // Part of the fee grows the jackpot of the round
+        if jackpot_contribution > 0 {
+            if let Some(draw) = state.lotto_draw(round) {
+                draw.jackpot += jackpot_contribution;
+            }
+        }
+
+        if fee != 0 {
+            state.add_ledger_entry(
+                &user_id,
+                -fee,
+                PointsReason::LottoFee,
+                Some(guess_id.to_hex()),
+                now,
+            );
+        }
+
+        Ok(LottoEntryOutcome::Accepted(PointsAdjustment {
+            balance,
+            applied: -fee,
+        }))
+    }
+
+    async fn get_lotto_guesses(
+        &self,
+        round: LottoRound,
+        dm_sent: Option<bool>,
+    ) -> StoreResult<Vec<LottoGuess>> {
+        Ok(self
+            .state()
+            .guesses
+            .iter()
+            .filter(|guess| {
+                guess.round == round
+                    && guess.is_any_matched == Some(true)
+                    && dm_sent.is_none_or(|dm_sent| guess.dm_sent == Some(dm_sent))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn pay_lotto_prize(
+        &self,
+        id: ObjectId,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Option<PointsAdjustment>> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        state.hold_fence(fence, now)?;
+
+        // The guesses notified before the paid state existed were paid with the notification
+        let guess = match state.guesses.iter_mut().find(|guess| {
+            guess.id == Some(id)
+                && guess.points.is_some()
+                && guess.paid != Some(true)
+                && guess.dm_sent != Some(true)
+        }) {
+            Some(guess) => {
+                guess.paid = Some(true);
+                guess.updated_at = now;
+                guess.clone()
+            }
+            None => return Ok(None),
+        };
+
+        let points = guess.points.unwrap_or_default();
+        if points <= 0 {
+            return Ok(Some(PointsAdjustment {
+                balance: 0,
+                applied: 0,
+            }));
+        }
+
+        let user_id = guess.dc_id.to_string();
+        let adjustment = state.apply_capped_points(&user_id, guess.dc_username.as_deref(), points);
+        if adjustment.applied != 0 {
+            state.add_ledger_entry(
+                &user_id,
+                adjustment.applied,
+                PointsReason::LottoPrize,
+                Some(id.to_hex()),
+                now,
+            );
+        }
+
+        Ok(Some(adjustment))
+    }
+
+    async fn add_lotto_dm_failure(&self, id: ObjectId, _error: &str) -> StoreResult<i32> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let attempts = match state.guesses.iter_mut().find(|guess| guess.id == Some(id)) {
+            Some(guess) => {
+                let attempts = guess.dm_attempts.unwrap_or_default() + 1;
+                guess.dm_attempts = Some(attempts);
+                guess.updated_at = now;
+                attempts
+            }
+            None => 0,
+        };
+
+        Ok(attempts)
+    }
+
+    async fn update_dm_sent_flag(&self, id: ObjectId) -> StoreResult<()> {
+        let now = self.clock.now();
+        if let Some(guess) = self
+            .state()
+            .guesses
+            .iter_mut()
+            .find(|guess| guess.id == Some(id))
+        {
+            guess.dm_sent = Some(true);
+            guess.updated_at = now;
+        }
+
+        Ok(())
+    }
+
+    async fn get_lotto_draw_history(
+        &self,
+        page_number: u64,
+        page_size: u64,
+    ) -> StoreResult<(Vec<LottoRoundSummary>, u64)> {
+        let state = self.state();
+        let mut draws: Vec<LottoDraw> = state
+            .draws
+            .iter()
+            .filter(|draw| !draw.numbers.is_empty())
+            .cloned()
+            .collect();
+        draws.sort_by_key(|draw| Reverse(draw.round));
+        let total = draws.len() as u64;
+
+        let summaries = page(draws, page_number, page_size)
+            .into_iter()
+            .map(|draw| state.lotto_round_summary(draw))
+            .collect();
+
+        Ok((summaries, total))
+    }
+
+    async fn get_user_lotto_history(
+        &self,
+        dc_id: u64,
+        page_number: u64,
+        page_size: u64,
+    ) -> StoreResult<(Vec<LottoGuess>, u64)> {
+        let mut guesses: Vec<LottoGuess> = self
+            .state()
+            .guesses
+            .iter()
+            .filter(|guess| guess.dc_id == dc_id)
+            .cloned()
+            .collect();
+        guesses.sort_by_key(|guess| Reverse(guess.created_at));
+        let total = guesses.len() as u64;
+
+        Ok((page(guesses, page_number, page_size), total))
+    }
+
+    async fn get_lotto_favorite(&self, user_id: &str) -> StoreResult<Option<Vec<i32>>> {
+        Ok(self
+            .state()
+            .users
+            .get(user_id)
+            .and_then(|user| user.lotto_favorite.clone()))
+    }
+
+    async fn set_lotto_favorite(&self, user_id: &str, numbers: &[i32]) -> StoreResult<()> {
+        self.state().user(user_id).lotto_favorite = Some(numbers.to_vec());
+
+        Ok(())
+    }
+
+    async fn set_lotto_mention(&self, user_id: &str, enabled: bool) -> StoreResult<()> {
+        self.state().user(user_id).lotto_mention = enabled;
+
+        Ok(())
+    }
+
+    async fn get_lotto_mention_users(&self, dc_ids: &[u64]) -> StoreResult<HashSet<u64>> {
+        let state = self.state();
+
+        Ok(dc_ids
+            .iter()
+            .copied()
+            .filter(|dc_id| {
+                state
+                    .users
+                    .get(&dc_id.to_string())
+                    .is_some_and(|user| user.lotto_mention)
+            })
+            .collect())
+    }
+
+    async fn get_user_lotto_guesses(
+        &self,
+        round: LottoRound,
+        dc_id: u64,
+    ) -> StoreResult<Vec<LottoGuess>> {
+        let previous = round.previous();
+        let guesses = self
+            .state()
+            .guesses
+            .iter()
+            .filter(|guess| {
+                guess.dc_id == dc_id && (guess.round == round || guess.round == previous)
+            })
+            .cloned()
+            .collect();
+
+        Ok(limit(guesses, 8))
+    }
+
+    async fn get_job_state(&self, name: &str) -> StoreResult<Option<JobState>> {
+        Ok(self.state().jobs.get(name).cloned())
+    }
+
+    async fn get_job_states(&self) -> StoreResult<Vec<JobState>> {
+        Ok(self.state().jobs.values().cloned().collect())
+    }
+
+    async fn set_job_paused(&self, name: &str, paused: bool) -> StoreResult<()> {
+        self.state().job(name).paused = Some(paused);
+
+        Ok(())
+    }
+
+    async fn skip_job_run(
+        &self,
+        name: &str,
+        scheduled_at: chrono::DateTime<Utc>,
+    ) -> StoreResult<()> {
+        let mut state = self.state();
+        let job = state.job(name);
+        job.last_scheduled_at = Some(scheduled_at);
+        job.last_result = Some("Skipped, the job is paused".to_string());
+        job.last_succeeded = None;
+
+        Ok(())
+    }
+
+    async fn set_job_next_run(
+        &self,
+        name: &str,
+        next_run_at: chrono::DateTime<Utc>,
+    ) -> StoreResult<()> {
+        self.state().job(name).next_run_at = Some(next_run_at);
+
+        Ok(())
+    }
+
+    async fn record_job_run(
+        &self,
+        name: &str,
+        token: i64,
+        scheduled_at: Option<chrono::DateTime<Utc>>,
+        result: &Result<String, String>,
+    ) -> StoreResult<bool> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        if state
+            .job_tokens
+            .get(name)
+            .is_some_and(|last_token| *last_token > token)
+        {
+            return Ok(false);
+        }
+        state.job_tokens.insert(name.to_string(), token);
+
+        let job = state.job(name);
+        let (succeeded, message) = match result {
+            Ok(summary) => (true, summary),
+            Err(error) => (false, error),
+        };
+        job.last_succeeded = Some(succeeded);
+        job.last_result = Some(message.clone());
+        job.last_run_at = Some(now);
+        if scheduled_at.is_some() {
+            job.last_scheduled_at = scheduled_at;
+        }
+
+        Ok(true)
+    }
+
+    async fn acquire_job_lease(
+        &self,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> StoreResult<Option<i64>> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let lease = state
+            .leases
+            .entry(name.to_string())
+            .or_insert_with(|| JobLease {
+                owner: owner.to_string(),
+                token: 0,
+                expires_at: now,
+            });
+        if lease.expires_at > now {
+            return Ok(None);
+        }
+
+        lease.owner = owner.to_string();
+        lease.token += 1;
+        lease.expires_at = now + ttl;
+
+        Ok(Some(lease.token))
+    }
+
+    async fn renew_job_lease(
+        &self,
+        name: &str,
+        owner: &str,
+        token: i64,
+        ttl: Duration,
+    ) -> StoreResult<bool> {
+        let now = self.clock.now();
+        match self.state().leases.get_mut(name) {
+            Some(lease)
+                if lease.owner == owner && lease.token == token && lease.expires_at > now =>
+            {
+                lease.expires_at = now + ttl;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // The lease is kept, so the next token still grows
+    async fn release_job_lease(&self, name: &str, owner: &str, token: i64) -> StoreResult<()> {
+        let now = self.clock.now();
+        if let Some(lease) = self.state().leases.get_mut(name) {
+            if lease.owner == owner && lease.token == token {
+                lease.expires_at = now;
+            }
+        }
+
+        Ok(())
+    }
+}
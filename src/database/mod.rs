@@ -1,2 +1,4 @@
+pub mod memory;
 pub mod models;
 pub mod mongo;
+pub mod store;
@@ -1,14 +1,17 @@
-use chrono::Utc;
-use mongodb::bson::{doc, oid::ObjectId};
+use chrono::{Datelike, Duration, NaiveDate, Utc, Weekday};
+use mongodb::bson::{doc, oid::ObjectId, Document};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[derive(Serialize, PartialEq, Deserialize, Clone, Copy, Debug, Default)]
 pub enum ExchangeStatus {
     #[default]
     Submitted,
     Processing,
     Completed,
+    Rejected,
+    Refunded,
 }
 
 impl fmt::Display for ExchangeStatus {
@@ -17,23 +20,102 @@ impl fmt::Display for ExchangeStatus {
             ExchangeStatus::Submitted => write!(f, "Submitted"),
             ExchangeStatus::Processing => write!(f, "Processing"),
             ExchangeStatus::Completed => write!(f, "Completed"),
+            ExchangeStatus::Rejected => write!(f, "Rejected"),
+            ExchangeStatus::Refunded => write!(f, "Refunded"),
         }
     }
 }
 
+// An item of the catalog that can be exchanged for points
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CatalogItem {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub name: String,
+    // The points for one item
+    pub price: i32,
+    // The quantity a user can exchange per week (Monday to Sunday, UTC), unlimited if missing
+    #[serde(
+        rename = "weeklyLimit",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub weekly_limit: Option<i64>,
+    // The remaining quantity, unlimited if missing
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub stock: Option<i64>,
+    #[serde(
+        rename = "startsAt",
+        skip_serializing_if = "Option::is_none",
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional"
+    )]
+    pub starts_at: Option<chrono::DateTime<Utc>>,
+    #[serde(
+        rename = "endsAt",
+        skip_serializing_if = "Option::is_none",
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional"
+    )]
+    pub ends_at: Option<chrono::DateTime<Utc>>,
+}
+
+impl CatalogItem {
+    pub fn is_active(&self, now: chrono::DateTime<Utc>) -> bool {
+        self.starts_at.is_none_or(|starts_at| starts_at <= now)
+            && self.ends_at.is_none_or(|ends_at| now < ends_at)
+    }
+
+    // The points for the quantity, or None if they do not fit in a balance
+    pub fn total_price(&self, quantity: i64) -> Option<i32> {
+        (self.price as i64)
+            .checked_mul(quantity)
+            .and_then(|total| i32::try_from(total).ok())
+    }
+}
+
+pub enum ExchangeOutcome {
+    Accepted(PointsAdjustment),
+    NotEnoughPoints,
+    // The item is not in the catalog or outside its active window
+    Unavailable,
+    OutOfStock,
+    // The weekly limit of the user would be exceeded, with the quantity still allowed
+    LimitReached(i64),
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Exchange {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
-    #[serde(rename = "dcId", skip_deserializing)]
+    #[serde(rename = "dcId", default)]
     pub dc_id: u64,
     #[serde(rename = "dcUsername")]
     pub dc_username: String,
     #[serde(rename = "walletAddress")]
     pub wallet_address: Option<String>,
+    // The ID of the catalog item
     pub item: String,
     pub quantity: i64,
+    // The points subtracted for the exchange, missing on records from before it was stored
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub points: Option<i32>,
     pub status: ExchangeStatus,
+    #[serde(
+        rename = "rejectReason",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub reject_reason: Option<String>,
+    // The transaction that delivered the items to the wallet
+    #[serde(rename = "txHash", skip_serializing_if = "Option::is_none", default)]
+    pub tx_hash: Option<String>,
+    #[serde(
+        rename = "fulfilmentError",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub fulfilment_error: Option<String>,
     #[serde(rename = "createdAt", skip_deserializing)]
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: chrono::DateTime<Utc>,
@@ -42,6 +124,32 @@ pub struct Exchange {
     pub updated_at: chrono::DateTime<Utc>,
 }
 
+impl Exchange {
+    // The points to give back when the exchange is refunded
+    pub fn refund_points(&self) -> i32 {
+        self.points.unwrap_or(self.quantity as i32 * 1000)
+    }
+}
+
+#[derive(Serialize, PartialEq, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FulfilmentStatus {
+    Completed,
+    Failed,
+}
+
+// The delivery result of an exchange reported by the fulfilment
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FulfilmentResult {
+    #[serde(rename = "exchangeId")]
+    pub exchange_id: String,
+    pub status: FulfilmentStatus,
+    #[serde(rename = "txHash", default)]
+    pub tx_hash: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
 #[derive(Serialize, PartialEq, Deserialize, Clone, Copy, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum ActivityType {
@@ -88,18 +196,276 @@ pub struct Activity {
     pub created_at: chrono::DateTime<Utc>,
 }
 
+// A lotto round is an ISO week, identified by the ISO year and the week number, so the week 1
+// that starts in late December and the week 53 belong to the right year
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LottoRound {
+    pub year: i32,
+    #[serde(rename = "weekNumber")]
+    pub week: u32,
+}
+
+impl LottoRound {
+    pub fn at(time: chrono::DateTime<Utc>) -> Self {
+        let week = time.iso_week();
+        LottoRound {
+            year: week.year(),
+            week: week.week(),
+        }
+    }
+
+    pub fn previous(&self) -> Self {
+        Self::at(self.starts_at() - Duration::weeks(1))
+    }
+
+    // Monday 00:00 (UTC+0) of the week
+    pub fn starts_at(&self) -> chrono::DateTime<Utc> {
+        NaiveDate::from_isoywd_opt(self.year, self.week, Weekday::Mon)
+            .unwrap_or_default()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    // Matches the documents of the round in the lotto collections
+    pub fn filter(&self) -> Document {
+        doc! {
+            "year": self.year,
+            "weekNumber": self.week
+        }
+    }
+}
+
+impl fmt::Display for LottoRound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-W{:02}", self.year, self.week)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct LottoDraw {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
+    // The winning numbers, empty until the round is closed
+    #[serde(default)]
     pub numbers: Vec<i32>,
-    pub year: i32,
-    #[serde(rename = "weekNumber")]
-    pub week_number: u32,
+    // The keccak256 hash of the seed, published when the round opens
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub commitment: Option<String>,
+    // The secret the numbers are derived from, revealed with the results
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub seed: Option<String>,
+    #[serde(
+        rename = "drawnAt",
+        skip_serializing_if = "Option::is_none",
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional"
+    )]
+    pub drawn_at: Option<chrono::DateTime<Utc>>,
+    // The rules when the round opened, so the digits and the prizes never change within a round
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rules: Option<LottoRules>,
+    // The share of the fees and the rollover, paid on top of the prize for matching all digits
+    #[serde(default)]
+    pub jackpot: i32,
+    // The number of winners who matched all digits, set when the jackpot is settled
+    #[serde(
+        rename = "jackpotWinners",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub jackpot_winners: Option<i32>,
+    // The points paid to each of those winners
+    #[serde(
+        rename = "jackpotPrize",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub jackpot_prize: Option<i32>,
+    // The points carried over to the next round
+    #[serde(
+        rename = "jackpotRollover",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub jackpot_rollover: Option<i32>,
+    #[serde(flatten)]
+    pub round: LottoRound,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub date: chrono::DateTime<Utc>,
 }
 
+impl LottoDraw {
+    // The rounds from before the rules were stored follow the original rules
+    pub fn rules(&self) -> LottoRules {
+        self.rules.clone().unwrap_or_default()
+    }
+
+    // The prize for matching all digits together with the jackpot
+    pub fn jackpot_pool(&self) -> i32 {
+        self.rules().top_prize() + self.jackpot
+    }
+}
+
+// The rules of the lotto, in effect from the date until newer rules take over
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LottoRules {
+    #[serde(rename = "effectiveFrom")]
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub effective_from: chrono::DateTime<Utc>,
+    // The points charged for an entry
+    pub fee: i32,
+    #[serde(rename = "entriesPerWeek")]
+    pub entries_per_week: u64,
+    // The number of single-digit numbers to choose
+    pub digits: u32,
+    pub prizes: Vec<LottoPrize>,
+    // The percentage of every fee that goes into the jackpot
+    #[serde(rename = "jackpotShare", default = "default_jackpot_share")]
+    pub jackpot_share: i32,
+    #[serde(rename = "freePeriods", default)]
+    pub free_periods: Vec<FreePeriod>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LottoPrize {
+    pub matches: u32,
+    pub points: i32,
+    // A reward given on top of the points, e.g. a badge
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub reward: Option<String>,
+}
+
+// A promotion with free entries
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FreePeriod {
+    #[serde(rename = "startsAt")]
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub starts_at: chrono::DateTime<Utc>,
+    #[serde(rename = "endsAt")]
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub ends_at: chrono::DateTime<Utc>,
+    // The entries per week during the period, the usual number if missing
+    #[serde(
+        rename = "entriesPerWeek",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub entries_per_week: Option<u64>,
+}
+
+fn default_jackpot_share() -> i32 {
+    50
+}
+
+// Every digit is an option of `/lotto pick` and a line of the results, so the digits are kept
+// well within the 25 options of a command and the 25 fields of an embed
+pub const MAX_LOTTO_DIGITS: u32 = 10;
+
+impl Default for LottoRules {
+    fn default() -> Self {
+        let prize = |matches, points, reward: Option<&str>| LottoPrize {
+            matches,
+            points,
+            reward: reward.map(String::from),
+        };
+
+        LottoRules {
+            effective_from: chrono::DateTime::<Utc>::UNIX_EPOCH,
+            fee: 200,
+            entries_per_week: 5,
+            digits: 4,
+            prizes: vec![
+                prize(1, 400, None),
+                prize(2, 1000, None),
+                prize(3, 5000, Some("Achievement Badges (Level 1)")),
+                prize(4, 100000, Some("Achievement Badges (Level 2)")),
+            ],
+            free_periods: Vec::new(),
+            jackpot_share: default_jackpot_share(),
+        }
+    }
+}
+
+impl LottoRules {
+    // Checks the rules stored by the admins before they are used for a round
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_LOTTO_DIGITS).contains(&self.digits) {
+            return Err(format!(
+                "the digits must be between 1 and {}, not {}",
+                MAX_LOTTO_DIGITS, self.digits
+            ));
+        }
+        if self.fee < 0 {
+            return Err(format!("the fee must not be negative, not {}", self.fee));
+        }
+        if self.entries_per_week == 0 {
+            return Err("at least one entry per week must be allowed".to_string());
+        }
+        if !(0..=100).contains(&self.jackpot_share) {
+            return Err(format!(
+                "the jackpot share must be a percentage, not {}",
+                self.jackpot_share
+            ));
+        }
+        if let Some(prize) = self
+            .prizes
+            .iter()
+            .find(|prize| !(1..=self.digits).contains(&prize.matches) || prize.points < 0)
+        {
+            return Err(format!(
+                "the prize for {} matches must match 1 to {} digits without negative points",
+                prize.matches, self.digits
+            ));
+        }
+        if self
+            .free_periods
+            .iter()
+            .any(|period| period.starts_at >= period.ends_at)
+        {
+            return Err("a free period must end after it starts".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn free_period_at(&self, now: chrono::DateTime<Utc>) -> Option<&FreePeriod> {
+        self.free_periods
+            .iter()
+            .find(|period| period.starts_at <= now && now < period.ends_at)
+    }
+
+    pub fn fee_at(&self, now: chrono::DateTime<Utc>) -> i32 {
+        match self.free_period_at(now) {
+            Some(_) => 0,
+            None => self.fee,
+        }
+    }
+
+    pub fn entries_per_week_at(&self, now: chrono::DateTime<Utc>) -> u64 {
+        self.free_period_at(now)
+            .and_then(|period| period.entries_per_week)
+            .unwrap_or(self.entries_per_week)
+    }
+
+    pub fn top_prize(&self) -> i32 {
+        self.prize_for(self.digits)
+    }
+
+    // The part of the fee added to the jackpot
+    pub fn jackpot_contribution(&self, fee: i32) -> i32 {
+        fee * self.jackpot_share.clamp(0, 100) / 100
+    }
+
+    pub fn prize_for(&self, matches: u32) -> i32 {
+        self.prizes
+            .iter()
+            .find(|prize| prize.matches == matches)
+            .map(|prize| prize.points)
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct LottoGuess {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -109,17 +475,25 @@ pub struct LottoGuess {
     #[serde(rename = "dcUsername", skip_serializing_if = "Option::is_none")]
     pub dc_username: Option<String>,
     pub numbers: Vec<i32>,
-    #[serde(skip_deserializing)]
-    pub year: i32,
-    #[serde(rename = "weekNumber")]
-    pub week_number: u32,
+    #[serde(flatten)]
+    pub round: LottoRound,
     #[serde(rename = "matchedCount", skip_serializing_if = "Option::is_none")]
     pub matched_count: Option<i32>,
     #[serde(rename = "isMatched", skip_serializing_if = "Option::is_none")]
     pub is_any_matched: Option<bool>,
     pub points: Option<i32>,
+    // Whether the prize was credited, which is independent of the DM
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub paid: Option<bool>,
     #[serde(rename = "dmSent", skip_serializing_if = "Option::is_none")]
     pub dm_sent: Option<bool>,
+    // The number of failed attempts to send the prize DM
+    #[serde(
+        rename = "dmAttempts",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub dm_attempts: Option<i32>,
     #[serde(rename = "createdAt")]
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: chrono::DateTime<Utc>,
@@ -127,3 +501,391 @@ pub struct LottoGuess {
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub updated_at: chrono::DateTime<Utc>,
 }
+
+// A drawn lotto round with its number of entries, winners per matching numbers and points paid
+#[derive(Debug, Clone)]
+pub struct LottoRoundSummary {
+    pub draw: LottoDraw,
+    pub entries: i64,
+    pub winners: BTreeMap<u32, i64>,
+    pub points_paid: i64,
+}
+
+pub enum LottoEntryOutcome {
+    Accepted(PointsAdjustment),
+    NotEnoughPoints,
+    // The user already made the maximum number of guesses this week
+    LimitReached,
+    // The numbers of the round were drawn before the entry was added
+    RoundClosed,
+}
+
+// The attendance streak is stored on the user document so it survives the activity cleanup
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct AttendanceStreak {
+    #[serde(rename = "attendStreak", default)]
+    pub current: i32,
+    #[serde(rename = "bestAttendStreak", default)]
+    pub best: i32,
+}
+
+// The points credited for a daily check-in, after the maximum points are applied
+#[derive(Debug, Clone, Copy)]
+pub struct AttendanceReward {
+    pub streak: AttendanceStreak,
+    pub points: i32,
+    pub bonus: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RankedUser {
+    #[serde(rename = "_id")]
+    pub dc_id: String,
+    #[serde(rename = "userName", skip_serializing_if = "Option::is_none")]
+    pub user_name: Option<String>,
+    #[serde(default)]
+    pub points: i32,
+}
+
+#[derive(Serialize, PartialEq, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum PointsReason {
+    // The balance the user had before the ledger was introduced
+    Opening,
+    Attend,
+    StreakBonus,
+    React,
+    Receive,
+    Poll,
+    BadEmoji,
+    Exchange,
+    LottoFee,
+    LottoPrize,
+    Refund,
+}
+
+impl fmt::Display for PointsReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PointsReason::Opening => write!(f, "opening"),
+            PointsReason::Attend => write!(f, "attend"),
+            PointsReason::StreakBonus => write!(f, "streakBonus"),
+            PointsReason::React => write!(f, "react"),
+            PointsReason::Receive => write!(f, "receive"),
+            PointsReason::Poll => write!(f, "poll"),
+            PointsReason::BadEmoji => write!(f, "badEmoji"),
+            PointsReason::Exchange => write!(f, "exchange"),
+            PointsReason::LottoFee => write!(f, "lottoFee"),
+            PointsReason::LottoPrize => write!(f, "lottoPrize"),
+            PointsReason::Refund => write!(f, "refund"),
+        }
+    }
+}
+
+impl PointsReason {
+    // A spend must be fully covered by the balance, unlike a penalty
+    pub fn is_spend(&self) -> bool {
+        matches!(self, PointsReason::Exchange | PointsReason::LottoFee)
+    }
+}
+
+// The maximum points a user can earn from any activity
+pub const MAX_POINTS: i32 = 200000;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PointsAdjustment {
+    // The balance of the user after the adjustment
+    pub balance: i32,
+    // The points actually applied, which is less than requested when the cap is reached
+    // and zero when a spend is not covered by the balance
+    pub applied: i32,
+}
+
+impl PointsAdjustment {
+    // A credit stops at the maximum points and a penalty stops at zero, and neither moves a
+    // balance that is already past its bound
+    pub fn capped(previous_points: i32, points: i32) -> Self {
+        let balance = if points > 0 {
+            previous_points.max((previous_points + points).min(MAX_POINTS))
+        } else {
+            previous_points.min((previous_points + points).max(0))
+        };
+        PointsAdjustment {
+            balance,
+            applied: balance - previous_points,
+        }
+    }
+}
+
+// An append-only record of a change to the balance of a user
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LedgerEntry {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub delta: i32,
+    pub reason: PointsReason,
+    // The message ID, exchange ID or lotto guess ID that caused the change
+    #[serde(rename = "sourceId", skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<String>,
+    #[serde(rename = "createdAt")]
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PointsDrift {
+    // The balance rebuilt from the points ledger, which may be beyond any stored balance
+    pub ledger_points: i64,
+    // The balance stored on the user document
+    pub stored_points: i32,
+}
+
+impl PointsDrift {
+    pub fn drift(&self) -> i64 {
+        self.stored_points as i64 - self.ledger_points
+    }
+}
+
+// The default wallet registered on the user document
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserWallet {
+    #[serde(rename = "walletAddress")]
+    pub address: String,
+    // Whether the ownership was proven with a signed message
+    #[serde(rename = "walletVerified", default)]
+    pub verified: bool,
+    #[serde(rename = "walletUpdatedAt")]
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: chrono::DateTime<Utc>,
+}
+
+pub enum WalletUpdate {
+    Changed(WalletAudit),
+    Unchanged,
+    // The wallet was changed within the cooldown
+    Cooldown,
+}
+
+// A record of a wallet change for the admins
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WalletAudit {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    #[serde(rename = "userName")]
+    pub user_name: String,
+    #[serde(
+        rename = "previousAddress",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub previous_address: Option<String>,
+    pub address: String,
+    pub verified: bool,
+    #[serde(rename = "createdAt")]
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+// The persisted state of a scheduled job, so the runs missed while the bot was down are known
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct JobState {
+    #[serde(rename = "_id")]
+    pub name: String,
+    // The scheduled time of the last finished run
+    #[serde(
+        rename = "lastScheduledAt",
+        skip_serializing_if = "Option::is_none",
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional"
+    )]
+    pub last_scheduled_at: Option<chrono::DateTime<Utc>>,
+    #[serde(
+        rename = "lastRunAt",
+        skip_serializing_if = "Option::is_none",
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional"
+    )]
+    pub last_run_at: Option<chrono::DateTime<Utc>>,
+    #[serde(
+        rename = "lastSucceeded",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub last_succeeded: Option<bool>,
+    // The summary or the error of the last run
+    #[serde(
+        rename = "lastResult",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub last_result: Option<String>,
+    #[serde(
+        rename = "nextRunAt",
+        skip_serializing_if = "Option::is_none",
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional"
+    )]
+    pub next_run_at: Option<chrono::DateTime<Utc>>,
+    // A paused job skips its scheduled runs until it is resumed
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub paused: Option<bool>,
+}
+
+// The lease a scheduled run holds. The writes of the run check it, so a run that stalled and
+// lost the lease to another instance cannot pay, draw or move anything anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobFence {
+    pub job: &'static str,
+    pub token: i64,
+}
+
+impl fmt::Display for JobFence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} with token {}", self.job, self.token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_price_rejects_overflow() {
+        let item = CatalogItem {
+            price: 1000,
+            ..Default::default()
+        };
+
+        assert_eq!(item.total_price(3), Some(3000));
+        assert_eq!(item.total_price(i64::MAX / 10), None);
+        assert_eq!(item.total_price(i32::MAX as i64), None);
+    }
+
+    fn round_at(year: i32, month: u32, day: u32) -> LottoRound {
+        LottoRound::at(
+            NaiveDate::from_ymd_opt(year, month, day)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+                .and_utc(),
+        )
+    }
+
+    #[test]
+    fn lotto_round_follows_the_iso_week() {
+        assert_eq!(
+            round_at(2020, 12, 31),
+            LottoRound {
+                year: 2020,
+                week: 53
+            }
+        );
+        assert_eq!(
+            round_at(2021, 1, 3),
+            LottoRound {
+                year: 2020,
+                week: 53
+            }
+        );
+        assert_eq!(
+            round_at(2021, 1, 4),
+            LottoRound {
+                year: 2021,
+                week: 1
+            }
+        );
+        assert_eq!(
+            round_at(2024, 12, 30),
+            LottoRound {
+                year: 2025,
+                week: 1
+            }
+        );
+        assert_eq!(round_at(2024, 12, 30).to_string(), "2025-W01");
+    }
+
+    #[test]
+    fn lotto_round_previous_crosses_the_year() {
+        let round = LottoRound {
+            year: 2021,
+            week: 1,
+        };
+        assert_eq!(
+            round.previous(),
+            LottoRound {
+                year: 2020,
+                week: 53
+            }
+        );
+
+        let round = LottoRound {
+            year: 2025,
+            week: 1,
+        };
+        assert_eq!(
+            round.previous(),
+            LottoRound {
+                year: 2024,
+                week: 52
+            }
+        );
+        assert_eq!(
+            round.starts_at().date_naive(),
+            NaiveDate::from_ymd_opt(2024, 12, 30).unwrap()
+        );
+    }
+
+    #[test]
+    fn points_are_capped() {
+        let credit = PointsAdjustment::capped(MAX_POINTS - 10, 50);
+        assert_eq!((credit.balance, credit.applied), (MAX_POINTS, 10));
+
+        let penalty = PointsAdjustment::capped(30, -50);
+        assert_eq!((penalty.balance, penalty.applied), (0, -30));
+
+        // A balance beyond the maximum is kept, but not raised further
+        let credit = PointsAdjustment::capped(MAX_POINTS + 5, 50);
+        assert_eq!((credit.balance, credit.applied), (MAX_POINTS + 5, 0));
+    }
+
+    #[test]
+    fn lotto_rules_are_validated() {
+        assert!(LottoRules::default().validate().is_ok());
+
+        let with_digits = |digits| LottoRules {
+            digits,
+            prizes: Vec::new(),
+            ..Default::default()
+        };
+        assert!(with_digits(0).validate().is_err());
+        assert!(with_digits(1).validate().is_ok());
+        assert!(with_digits(MAX_LOTTO_DIGITS).validate().is_ok());
+        assert!(with_digits(23).validate().is_err());
+
+        // A prize for more matches than digits can never be won
+        let rules = LottoRules {
+            digits: 3,
+            ..Default::default()
+        };
+        assert!(rules.validate().is_err());
+
+        let rules = LottoRules {
+            jackpot_share: 101,
+            ..Default::default()
+        };
+        assert!(rules.validate().is_err());
+    }
+
+    #[test]
+    fn points_drift_keeps_a_ledger_beyond_any_balance() {
+        let drift = PointsDrift {
+            ledger_points: i32::MAX as i64 + 100,
+            stored_points: MAX_POINTS,
+        };
+        assert_eq!(drift.drift(), MAX_POINTS as i64 - i32::MAX as i64 - 100);
+    }
+}
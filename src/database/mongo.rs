@@ -1,28 +1,57 @@
 use bson::oid::ObjectId;
 use bson::Bson;
-use chrono::{Duration, Utc};
+use chrono::{Datelike, Duration, Utc};
+use futures::future::BoxFuture;
 use futures::stream::StreamExt;
 use mongodb::bson::{doc, DateTime};
-use mongodb::error::Error;
 use mongodb::error::Result as MongoResult;
-use mongodb::results::DeleteResult;
+use mongodb::error::{
+    Error, ErrorKind, WriteFailure, TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT,
+};
 use mongodb::{
-    options::{ClientOptions, FindOneOptions, FindOptions, UpdateOptions},
-    Client, Database,
+    options::{
+        ClientOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReplaceOptions,
+        ReturnDocument, UpdateOptions,
+    },
+    Client, ClientSession, Database,
+};
+use serenity::async_trait;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::clock::Clock;
+use crate::util::{
+    calculate_lotto_points, draw_lotto_numbers, generate_lotto_seed, lotto_commitment,
+};
+
+use super::models::{
+    Activity, ActivityType, AttendanceReward, AttendanceStreak, CatalogItem, Exchange,
+    ExchangeOutcome, ExchangeStatus, FulfilmentResult, FulfilmentStatus, JobFence, JobState,
+    LedgerEntry, LottoDraw, LottoEntryOutcome, LottoGuess, LottoRound, LottoRoundSummary,
+    LottoRules, PointsAdjustment, PointsDrift, PointsReason, RankedUser, UserWallet, WalletAudit,
+    WalletUpdate, MAX_POINTS,
 };
-use tracing::error;
+use super::store::{Store, StoreResult};
 
-use crate::util::{generate_numbers, get_week_number};
+// How often a transaction, or its commit, is tried before the error is returned
+const MAX_TRANSACTION_ATTEMPTS: u32 = 5;
 
-use super::models::{Activity, ActivityType, Exchange, ExchangeStatus, LottoDraw, LottoGuess};
+// The delay before retrying a transaction, doubled after every failed attempt
+const TRANSACTION_RETRY_DELAY_MS: i64 = 50;
+
+// An attempt of a transaction takes the session and hands it back with its result
+type TransactionAttempt<'a, T> = BoxFuture<'a, (ClientSession, MongoResult<T>)>;
 
 #[derive(Clone)]
 pub struct MongoDB {
+    client: Client,
     db: Database,
+    clock: Arc<dyn Clock>,
 }
 
 impl MongoDB {
-    pub async fn new(uri: &str) -> Result<Self, Error> {
+    pub async fn new(uri: &str, clock: Arc<dyn Clock>) -> Result<Self, Error> {
         let mut client_options = ClientOptions::parse(uri).await?;
         client_options.connect_timeout = Some(std::time::Duration::from_secs(10));
 
@@ -31,23 +60,1080 @@ impl MongoDB {
 
         Ok(MongoDB {
             db: client.database("discord-bot"),
+            client,
+            clock,
         })
     }
 
-    pub async fn add_exchange_record(&self, exchange: Exchange) -> MongoResult<()> {
+    // Runs the attempt in a transaction and commits it if `commit_if` accepts the result,
+    // otherwise aborts it. Transient errors such as write conflicts retry the whole
+    // transaction a few times with a growing delay. Transactions need a replica set.
+    async fn with_transaction<'a, T, F>(
+        &self,
+        mut attempt: F,
+        commit_if: impl Fn(&T) -> bool,
+    ) -> MongoResult<T>
+    where
+        F: FnMut(ClientSession) -> TransactionAttempt<'a, T>,
+    {
+        let mut session = self.client.start_session(None).await?;
+        let mut attempts = 1;
+        loop {
+            session.start_transaction(None).await?;
+            let (returned, result) = attempt(session).await;
+            session = returned;
+
+            let result = match result {
+                Ok(value) if commit_if(&value) => {
+                    commit_transaction(&mut session).await.map(|_| value)
+                }
+                Ok(value) => {
+                    session.abort_transaction().await?;
+                    return Ok(value);
+                }
+                Err(e) => {
+                    let _ = session.abort_transaction().await;
+                    Err(e)
+                }
+            };
+
+            match result {
+                Err(e)
+                    if e.contains_label(TRANSIENT_TRANSACTION_ERROR)
+                        && attempts < MAX_TRANSACTION_ATTEMPTS =>
+                {
+                    warn!("Retrying a transaction after a transient error: {}", e);
+                    let delay = TRANSACTION_RETRY_DELAY_MS << (attempts - 1);
+                    self.clock.sleep(Duration::milliseconds(delay)).await;
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Fails the transaction unless the run still holds the lease of the fence. The lease is
+    // written too, so an instance taking it over conflicts with the transaction.
+    async fn try_hold_fence(
+        &self,
+        session: &mut ClientSession,
+        fence: Option<JobFence>,
+    ) -> MongoResult<()> {
+        let fence = match fence {
+            Some(fence) => fence,
+            None => return Ok(()),
+        };
+        let lease_collection = self.db.collection::<mongodb::bson::Document>("jobleases");
+        let now = DateTime::from_chrono(self.clock.now());
+        let filter = doc! {
+            "_id": fence.job,
+            "token": fence.token,
+            "expiresAt": { "$gt": now }
+        };
+        let update = doc! { "$set": { "fencedAt": now } };
+        let result = lease_collection
+            .update_one_with_session(filter, update, None, session)
+            .await?;
+        if result.matched_count == 0 {
+            return Err(Error::custom(fence));
+        }
+
+        Ok(())
+    }
+
+    // Applies the points within the maximum and records the change in the points ledger.
+    // A spend is only applied if the balance covers it.
+    async fn try_adjust_user_points(
+        &self,
+        session: &mut ClientSession,
+        user_id: &str,
+        user_name: Option<&str>,
+        points: i32,
+        reason: PointsReason,
+        source_id: Option<String>,
+    ) -> MongoResult<PointsAdjustment> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let ledger_collection = self
+            .db
+            .collection::<mongodb::bson::Document>("points_ledger");
+
+        let adjustment = if points < 0 && reason.is_spend() {
+            let filter = doc! {"_id": user_id, "points": { "$gte": -points }};
+            let update = doc! {
+                "$inc": {"points": points},
+                "$set": {"updatedAt": DateTime::from_chrono(self.clock.now())}
+            };
+            let options = FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build();
+
+            match user_collection
+                .find_one_and_update_with_session(filter, update, options, session)
+                .await?
+            {
+                Some(document) => PointsAdjustment {
+                    balance: document.get_i32("points").unwrap_or_default(),
+                    applied: points,
+                },
+                None => PointsAdjustment {
+                    balance: user_collection
+                        .find_one_with_session(doc! {"_id": user_id}, None, session)
+                        .await?
+                        .and_then(|document| document.get_i32("points").ok())
+                        .unwrap_or_default(),
+                    applied: 0,
+                },
+            }
+        } else {
+            let update = capped_points_update(points, user_name, self.clock.now());
+            let options = FindOneAndUpdateOptions::builder()
+                .upsert(true)
+                .return_document(ReturnDocument::Before)
+                .build();
+
+            let previous_points = user_collection
+                .find_one_and_update_with_session(doc! {"_id": user_id}, update, options, session)
+                .await?
+                .and_then(|document| document.get_i32("points").ok())
+                .unwrap_or_default();
+
+            PointsAdjustment::capped(previous_points, points)
+        };
+
+        // Record the change that was actually applied in the points ledger
+        if adjustment.applied != 0 {
+            let entry = LedgerEntry {
+                id: None,
+                user_id: user_id.to_string(),
+                delta: adjustment.applied,
+                reason,
+                source_id,
+                created_at: self.clock.now(),
+            };
+            ledger_collection
+                .insert_one_with_session(bson::to_document(&entry)?, None, session)
+                .await?;
+        }
+
+        Ok(adjustment)
+    }
+
+    // Moves the submitted exchanges to processing and returns them, oldest first
+    async fn try_move_submitted_to_processing(
+        &self,
+        session: &mut ClientSession,
+        fence: Option<JobFence>,
+    ) -> MongoResult<Vec<Exchange>> {
+        self.try_hold_fence(session, fence).await?;
         let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
-        let exchange_doc = bson::to_bson(&exchange)
-            .unwrap()
-            .as_document()
-            .unwrap()
-            .clone();
+        let submitted = doc! { "status": Bson::String(ExchangeStatus::Submitted.to_string()) };
+        let options = FindOptions::builder().sort(doc! {"createdAt": 1}).build();
+        let mut cursor = exchange_collection
+            .find_with_session(submitted, options, session)
+            .await?;
+        let mut batch = Vec::new();
+        while let Some(document) = cursor.next(session).await {
+            let exchange: Exchange = bson::from_document(document?)?;
+            batch.push(exchange);
+        }
+        let ids: Vec<ObjectId> = batch.iter().filter_map(|exchange| exchange.id).collect();
+
+        // Only the fetched records are moved, so the batch matches what is exported
+        let filter = doc! { "_id": { "$in": ids }, "status": Bson::String(ExchangeStatus::Submitted.to_string()) };
+        let update = doc! { "$set": { "status": Bson::String(ExchangeStatus::Processing.to_string()), "updatedAt": DateTime::from_chrono(self.clock.now()) }};
         exchange_collection
-            .insert_one(exchange_doc, None)
+            .update_many_with_session(filter, update, None, session)
+            .await?;
+
+        for exchange in batch.iter_mut() {
+            exchange.status = ExchangeStatus::Processing;
+        }
+        Ok(batch)
+    }
+
+    // Registers the wallet unless it was changed within the cooldown, and audits the change
+    async fn try_set_user_wallet(
+        &self,
+        session: &mut ClientSession,
+        user_id: &str,
+        user_name: &str,
+        address: &str,
+        verified: bool,
+        cooldown: Duration,
+    ) -> MongoResult<WalletUpdate> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let now = self.clock.now();
+        // Registering the same wallet again is always allowed, e.g. to verify it
+        let filter = doc! {
+            "_id": user_id,
+            "$or": [
+                { "walletAddress": { "$exists": false } },
+                { "walletAddress": address },
+                { "walletUpdatedAt": { "$lte": DateTime::from_chrono(now - cooldown) } },
+            ]
+        };
+        let same_address = doc! { "$eq": ["$walletAddress", address] };
+        let update = vec![doc! {
+            "$set": {
+                "walletAddress": address,
+                "walletVerified": {
+                    "$cond": [same_address.clone(), { "$or": ["$walletVerified", verified] }, verified]
+                },
+                "walletUpdatedAt": { "$cond": [same_address, "$walletUpdatedAt", DateTime::from_chrono(now)] },
+                "userName": { "$literal": user_name },
+                "createdAt": { "$ifNull": ["$createdAt", DateTime::from_chrono(now)] },
+                "updatedAt": DateTime::from_chrono(now),
+            }
+        }];
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .build();
+
+        // A user outside the cooldown filter makes the upsert collide with the existing ID
+        let previous = match user_collection
+            .find_one_and_update_with_session(filter, update, options, session)
             .await
-            .map(|_| ())
+        {
+            Ok(previous) => previous,
+            Err(e) if is_duplicate_key_error(&e) => return Ok(WalletUpdate::Cooldown),
+            Err(e) => return Err(e),
+        };
+
+        // A user without a registered wallet has no previous wallet to read
+        let previous: Option<UserWallet> =
+            previous.and_then(|document| bson::from_document(document).ok());
+        let changed = match &previous {
+            Some(wallet) => wallet.address != address || (verified && !wallet.verified),
+            None => true,
+        };
+        if !changed {
+            return Ok(WalletUpdate::Unchanged);
+        }
+
+        let audit = WalletAudit {
+            id: None,
+            user_id: user_id.to_string(),
+            user_name: user_name.to_string(),
+            previous_address: previous.map(|wallet| wallet.address),
+            address: address.to_string(),
+            verified,
+            created_at: self.clock.now(),
+        };
+        let audit_collection = self.db.collection::<mongodb::bson::Document>("walletaudit");
+        audit_collection
+            .insert_one_with_session(bson::to_document(&audit)?, None, session)
+            .await?;
+
+        Ok(WalletUpdate::Changed(audit))
+    }
+
+    async fn try_record_attendance(
+        &self,
+        session: &mut ClientSession,
+        activity: &Activity,
+        user_name: Option<&str>,
+        streak_bonus: &BTreeMap<i32, i32>,
+    ) -> MongoResult<Option<AttendanceReward>> {
+        let activity_collection = self.db.collection::<mongodb::bson::Document>("activity");
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let user_id = activity.dc_id.to_string();
+        let today = self.clock.now().date_naive();
+
+        // The check-ins from before the key was introduced only match on the day
+        let filter_today = doc! {
+            "dcId": activity.dc_id as i64,
+            "activity": ActivityType::Attend.to_string(),
+            "createdAt": { "$gte": today.and_hms_opt(0, 0, 0).unwrap().and_utc() }
+        };
+        if activity_collection
+            .find_one_with_session(filter_today, None, session)
+            .await?
+            .is_some()
+        {
+            return Ok(None);
+        }
+
+        // The check-in is keyed on the user and the day (UTC+0), so the upsert only inserts
+        // the first check-in of the day, however many arrive at once
+        let filter = doc! {"_id": format!("attend-{}-{}", activity.dc_id, today)};
+        let update = doc! {"$setOnInsert": bson::to_document(activity)?};
+        let options = UpdateOptions::builder().upsert(true).build();
+        let inserted = activity_collection
+            .update_one_with_session(filter, update, options, session)
+            .await?
+            .upserted_id
+            .is_some();
+        if !inserted {
+            return Ok(None);
+        }
+
+        let filter = doc! {"_id": &user_id};
+        let streak = match user_collection
+            .find_one_with_session(filter.clone(), None, session)
+            .await?
+        {
+            Some(document) => {
+                let last_streak: AttendanceStreak = bson::from_document(document.clone())?;
+                let last_attend_date = document
+                    .get_datetime("lastAttendDate")
+                    .map(|date| date.to_chrono().date_naive());
+
+                let current = match last_attend_date {
+                    Ok(date) if date == today => last_streak.current,
+                    Ok(date) if date == today - Duration::days(1) => last_streak.current + 1,
+                    _ => 1,
+                };
+                AttendanceStreak {
+                    current,
+                    best: last_streak.best.max(current),
+                }
+            }
+            None => AttendanceStreak {
+                current: 1,
+                best: 1,
+            },
+        };
+
+        let mut set_on_insert_doc = doc! {
+            "createdAt": DateTime::from_chrono(self.clock.now()),
+        };
+        if let Some(name) = user_name {
+            set_on_insert_doc.insert("userName", name);
+        }
+        let update = doc! {
+            "$setOnInsert": set_on_insert_doc,
+            "$set": {
+                "attendStreak": streak.current,
+                "bestAttendStreak": streak.best,
+                "lastAttendDate": DateTime::from_chrono(today.and_hms_opt(0, 0, 0).unwrap().and_utc()),
+                "updatedAt": DateTime::from_chrono(self.clock.now()),
+            },
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        user_collection
+            .update_one_with_session(filter, update, options, session)
+            .await?;
+
+        let points = self
+            .try_adjust_user_points(
+                session,
+                &user_id,
+                user_name,
+                activity.reward,
+                PointsReason::Attend,
+                None,
+            )
+            .await?
+            .applied;
+        let bonus = match streak_bonus.get(&streak.current) {
+            Some(&bonus) if bonus > 0 => {
+                self.try_adjust_user_points(
+                    session,
+                    &user_id,
+                    user_name,
+                    bonus,
+                    PointsReason::StreakBonus,
+                    None,
+                )
+                .await?
+                .applied
+            }
+            _ => 0,
+        };
+
+        Ok(Some(AttendanceReward {
+            streak,
+            points,
+            bonus,
+        }))
+    }
+
+    async fn try_add_exchange_record(
+        &self,
+        session: &mut ClientSession,
+        exchange: &Exchange,
+    ) -> MongoResult<ExchangeOutcome> {
+        let catalog_collection = self.db.collection::<mongodb::bson::Document>("catalog");
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
+        let ledger_collection = self
+            .db
+            .collection::<mongodb::bson::Document>("points_ledger");
+        let user_id = exchange.dc_id.to_string();
+
+        let mut filter = active_item_filter(self.clock.now());
+        filter.insert("_id", &exchange.item);
+        let item: CatalogItem = match catalog_collection
+            .find_one_with_session(filter, None, session)
+            .await?
+        {
+            Some(document) => bson::from_document(document)?,
+            None => return Ok(ExchangeOutcome::Unavailable),
+        };
+
+        if let Some(limit) = item.weekly_limit {
+            let exchanged = self
+                .get_weekly_exchanged_quantity(session, exchange.dc_id, &item.id)
+                .await?;
+            if exchanged + exchange.quantity > limit {
+                return Ok(ExchangeOutcome::LimitReached((limit - exchanged).max(0)));
+            }
+        }
+
+        if item.stock.is_some() {
+            let result = catalog_collection
+                .update_one_with_session(
+                    doc! {"_id": &item.id, "stock": { "$gte": exchange.quantity }},
+                    doc! {"$inc": {"stock": -exchange.quantity}},
+                    None,
+                    session,
+                )
+                .await?;
+            if result.modified_count == 0 {
+                return Ok(ExchangeOutcome::OutOfStock);
+            }
+        }
+
+        // The price is read inside the transaction so a catalog change cannot be raced
+        // A total beyond any balance is rejected like any other unaffordable exchange
+        let required_points = match item.total_price(exchange.quantity) {
+            Some(required_points) => required_points,
+            None => return Ok(ExchangeOutcome::NotEnoughPoints),
+        };
+
+        let filter = doc! {"_id": &user_id, "points": { "$gte": required_points }};
+        let update = doc! {
+            "$inc": {"points": -required_points},
+            "$set": {"updatedAt": DateTime::from_chrono(self.clock.now())}
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let balance = match user_collection
+            .find_one_and_update_with_session(filter, update, options, session)
+            .await?
+        {
+            Some(document) => document.get_i32("points").unwrap_or_default(),
+            None => return Ok(ExchangeOutcome::NotEnoughPoints),
+        };
+
+        let mut exchange_doc = bson::to_document(exchange)?;
+        exchange_doc.insert("points", required_points);
+        exchange_collection
+            .insert_one_with_session(exchange_doc, None, session)
+            .await?;
+
+        let entry = LedgerEntry {
+            id: None,
+            user_id,
+            delta: -required_points,
+            reason: PointsReason::Exchange,
+            source_id: exchange.id.map(|id| id.to_hex()),
+            created_at: self.clock.now(),
+        };
+        ledger_collection
+            .insert_one_with_session(bson::to_document(&entry)?, None, session)
+            .await?;
+
+        Ok(ExchangeOutcome::Accepted(PointsAdjustment {
+            balance,
+            applied: -required_points,
+        }))
     }
 
-    pub async fn get_user_points(&self, user_id: &str) -> MongoResult<i32> {
+    // Sums the quantity of the item the user exchanged since Monday, except the rejected requests
+    async fn get_weekly_exchanged_quantity(
+        &self,
+        session: &mut ClientSession,
+        dc_id: u64,
+        item: &str,
+    ) -> MongoResult<i64> {
+        let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
+        let today = self.clock.now().date_naive();
+        let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+        let pipeline = vec![
+            doc! {
+                "$match": {
+                    "dcId": dc_id as i64,
+                    "item": item,
+                    "status": { "$nin": [
+                        Bson::String(ExchangeStatus::Rejected.to_string()),
+                        Bson::String(ExchangeStatus::Refunded.to_string()),
+                    ]},
+                    "createdAt": { "$gte": DateTime::from_chrono(monday.and_hms_opt(0, 0, 0).unwrap().and_utc()) },
+                }
+            },
+            doc! { "$group": { "_id": null, "quantity": { "$sum": "$quantity" } } },
+        ];
+
+        let mut cursor = exchange_collection
+            .aggregate_with_session(pipeline, None, session)
+            .await?;
+        let quantity = match cursor.next(session).await {
+            Some(document) => {
+                let document = document?;
+                document
+                    .get_i64("quantity")
+                    .or_else(|_| document.get_i32("quantity").map(i64::from))
+                    .unwrap_or_default()
+            }
+            None => 0,
+        };
+
+        Ok(quantity)
+    }
+
+    async fn try_refund_exchange(
+        &self,
+        session: &mut ClientSession,
+        id: ObjectId,
+    ) -> MongoResult<Option<Exchange>> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
+        let ledger_collection = self
+            .db
+            .collection::<mongodb::bson::Document>("points_ledger");
+
+        let filter =
+            doc! { "_id": id, "status": Bson::String(ExchangeStatus::Rejected.to_string()) };
+        let update = doc! { "$set": { "status": Bson::String(ExchangeStatus::Refunded.to_string()), "updatedAt": DateTime::from_chrono(self.clock.now()) }};
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let exchange: Exchange = match exchange_collection
+            .find_one_and_update_with_session(filter, update, options, session)
+            .await?
+        {
+            Some(document) => bson::from_document(document)?,
+            None => return Ok(None),
+        };
+
+        // Put the items back in stock, unless the stock is unlimited
+        let catalog_collection = self.db.collection::<mongodb::bson::Document>("catalog");
+        catalog_collection
+            .update_one_with_session(
+                doc! {"_id": &exchange.item, "stock": { "$type": "number" }},
+                doc! {"$inc": {"stock": exchange.quantity}},
+                None,
+                session,
+            )
+            .await?;
+
+        // The refund gives back exactly what was spent, regardless of the maximum points
+        let user_id = exchange.dc_id.to_string();
+        let points = exchange.refund_points();
+        let update = doc! {
+            "$inc": {"points": points},
+            "$set": {"updatedAt": DateTime::from_chrono(self.clock.now())}
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        user_collection
+            .update_one_with_session(doc! {"_id": &user_id}, update, options, session)
+            .await?;
+
+        let entry = LedgerEntry {
+            id: None,
+            user_id,
+            delta: points,
+            reason: PointsReason::Refund,
+            source_id: Some(id.to_hex()),
+            created_at: self.clock.now(),
+        };
+        ledger_collection
+            .insert_one_with_session(bson::to_document(&entry)?, None, session)
+            .await?;
+
+        Ok(Some(exchange))
+    }
+
+    async fn count_full_matches(&self, round: LottoRound, numbers: &[i32]) -> MongoResult<i32> {
+        let guess_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
+        let mut filter = round.filter();
+        filter.insert("numbers", numbers);
+        let count = guess_collection.count_documents(filter, None).await?;
+
+        Ok(count as i32)
+    }
+
+    async fn score_lotto_guesses(
+        &self,
+        round: LottoRound,
+        numbers: &[i32],
+        rules: &LottoRules,
+        jackpot_prize: i32,
+    ) -> MongoResult<()> {
+        let guess_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
+        let mut filter = round.filter();
+        filter.insert("matchedCount", Bson::Null);
+        let options = FindOptions::builder()
+            .projection(doc! {"numbers": 1})
+            .build();
+        let mut cursor = guess_collection.find(filter, options).await?;
+
+        while let Some(result) = cursor.next().await {
+            let document = result?;
+            let guess_numbers: Vec<i32> = document
+                .get_array("numbers")
+                .map(|numbers| numbers.iter().filter_map(|n| n.as_i32()).collect())
+                .unwrap_or_default();
+            let (matches, mut points) = calculate_lotto_points(&guess_numbers, numbers, rules);
+            if matches == rules.digits {
+                points = jackpot_prize;
+            }
+
+            let update = doc! {
+                "$set": {
+                    "matchedCount": matches as i32,
+                    "isMatched": matches > 0,
+                    "points": points,
+                    "updatedAt": DateTime::from_chrono(self.clock.now()),
+                },
+            };
+            guess_collection
+                .update_one(doc! {"_id": document.get("_id").cloned()}, update, None)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // Records the jackpot winners of a closed round and carries the jackpot over to the
+    // current round, or only the remainder of the split if somebody won it
+    async fn settle_lotto_jackpot(
+        &self,
+        draw: LottoDraw,
+        winners: i32,
+        jackpot_prize: i32,
+        fence: Option<JobFence>,
+    ) -> StoreResult<LottoDraw> {
+        if draw.jackpot_winners.is_some() {
+            return Ok(draw);
+        }
+
+        let rollover = if winners > 0 {
+            draw.jackpot_pool() - jackpot_prize * winners
+        } else {
+            draw.jackpot
+        };
+        let current = self.add_weekly_draw().await?;
+        let settled = fenced(
+            self.with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self
+                            .try_settle_lotto_jackpot(
+                                &mut session,
+                                &draw,
+                                &current,
+                                winners,
+                                jackpot_prize,
+                                rollover,
+                                fence,
+                            )
+                            .await;
+                        (session, result)
+                    })
+                },
+                Option::is_some,
+            )
+            .await,
+        )?;
+
+        match settled {
+            Some(settled) => Ok(settled),
+            // Another call settled the round first
+            None => Ok(self.get_lotto_round(draw.round).await?.unwrap_or(draw)),
+        }
+    }
+
+    // Stores the numbers of the round, returns None if they were drawn already
+    async fn try_draw_lotto_round(
+        &self,
+        session: &mut ClientSession,
+        round: LottoRound,
+        numbers: &[i32],
+        fence: Option<JobFence>,
+    ) -> MongoResult<Option<LottoDraw>> {
+        self.try_hold_fence(session, fence).await?;
+        let draw_collection = self.db.collection::<mongodb::bson::Document>("lottodraw");
+
+        let mut filter = round.filter();
+        filter.insert("numbers", doc! { "$size": 0 });
+        let update = doc! {
+            "$set": {
+                "numbers": numbers,
+                "drawnAt": DateTime::from_chrono(self.clock.now()),
+            }
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        match draw_collection
+            .find_one_and_update_with_session(filter, update, options, session)
+            .await?
+        {
+            Some(document) => Ok(Some(bson::from_document(document)?)),
+            None => Ok(None),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn try_settle_lotto_jackpot(
+        &self,
+        session: &mut ClientSession,
+        draw: &LottoDraw,
+        current: &LottoDraw,
+        winners: i32,
+        jackpot_prize: i32,
+        rollover: i32,
+        fence: Option<JobFence>,
+    ) -> MongoResult<Option<LottoDraw>> {
+        self.try_hold_fence(session, fence).await?;
+        let draw_collection = self.db.collection::<mongodb::bson::Document>("lottodraw");
+
+        let filter = doc! {
+            "_id": draw.id,
+            "jackpotWinners": null
+        };
+        let update = doc! {
+            "$set": {
+                "jackpotWinners": winners,
+                "jackpotPrize": jackpot_prize,
+                "jackpotRollover": rollover,
+            }
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let settled = match draw_collection
+            .find_one_and_update_with_session(filter, update, options, session)
+            .await?
+        {
+            Some(document) => bson::from_document(document)?,
+            None => return Ok(None),
+        };
+
+        if rollover > 0 && current.id != draw.id {
+            draw_collection
+                .update_one_with_session(
+                    doc! {"_id": current.id},
+                    doc! {"$inc": {"jackpot": rollover}},
+                    None,
+                    session,
+                )
+                .await?;
+        }
+
+        Ok(Some(settled))
+    }
+
+    async fn try_add_lotto_guess(
+        &self,
+        session: &mut ClientSession,
+        guess: &LottoGuess,
+        fee: i32,
+        jackpot_contribution: i32,
+        max_entries: u64,
+    ) -> MongoResult<LottoEntryOutcome> {
+        let draw_collection = self.db.collection::<mongodb::bson::Document>("lottodraw");
+        let guess_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let ledger_collection = self
+            .db
+            .collection::<mongodb::bson::Document>("points_ledger");
+        let user_id = guess.dc_id.to_string();
+
+        // The round only takes entries until its numbers are drawn. Growing the jackpot of the
+        // round with part of the fee writes the draw, so an entry racing the draw is retried.
+        let mut filter = guess.round.filter();
+        filter.insert("numbers", doc! { "$size": 0 });
+        let open = draw_collection
+            .find_one_and_update_with_session(
+                filter,
+                doc! {"$inc": {"jackpot": jackpot_contribution}},
+                None,
+                session,
+            )
+            .await?
+            .is_some();
+        if !open {
+            return Ok(LottoEntryOutcome::RoundClosed);
+        }
+
+        // Check how many guesses the user has made this week
+        let mut filter = guess.round.filter();
+        filter.insert("dcId", guess.dc_id as i64);
+        let count = guess_collection
+            .count_documents_with_session(filter, None, session)
+            .await?;
+
+        // If the user has made the maximum number of guesses this week, reject the entry
+        if count >= max_entries {
+            return Ok(LottoEntryOutcome::LimitReached);
+        }
+
+        // Two entries at once both write the user document, so one of them is retried
+        // and sees the other guess. A free entry is written the same way to keep that.
+        let filter = if fee == 0 {
+            doc! {"_id": &user_id}
+        } else {
+            doc! {"_id": &user_id, "points": { "$gte": fee }}
+        };
+        let update = doc! {
+            "$inc": {"points": -fee},
+            "$set": {"updatedAt": DateTime::from_chrono(self.clock.now())}
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .upsert(fee == 0)
+            .build();
+        let balance = match user_collection
+            .find_one_and_update_with_session(filter, update, options, session)
+            .await?
+        {
+            Some(document) => document.get_i32("points").unwrap_or_default(),
+            None => return Ok(LottoEntryOutcome::NotEnoughPoints),
+        };
+
+        // Convert LottoGuess instance to a BSON Document
+        let guess_id = guess.id.unwrap_or_default();
+        let guess_doc = doc! {
+            "_id": guess_id,
+            "dcId": guess.dc_id as i64,
+            "dcUsername": guess.dc_username.clone(),
+            "numbers": Bson::Array(guess.numbers.iter().copied().map(Bson::Int32).collect()),
+            "year": guess.round.year,
+            "weekNumber": guess.round.week,
+            "matchedCount": guess.matched_count,
+            "isMatched": guess.is_any_matched,
+            "points": guess.points,
+            "paid": guess.paid,
+            "dmSent": guess.dm_sent,
+            "createdAt": guess.created_at,
+            "updatedAt": guess.updated_at,
+        };
+        guess_collection
+            .insert_one_with_session(guess_doc, None, session)
+            .await?;
+
+        if fee != 0 {
+            let entry = LedgerEntry {
+                id: None,
+                user_id,
+                delta: -fee,
+                reason: PointsReason::LottoFee,
+                source_id: Some(guess_id.to_hex()),
+                created_at: self.clock.now(),
+            };
+            ledger_collection
+                .insert_one_with_session(bson::to_document(&entry)?, None, session)
+                .await?;
+        }
+
+        Ok(LottoEntryOutcome::Accepted(PointsAdjustment {
+            balance,
+            applied: -fee,
+        }))
+    }
+
+    async fn try_pay_lotto_prize(
+        &self,
+        session: &mut ClientSession,
+        id: ObjectId,
+        fence: Option<JobFence>,
+    ) -> MongoResult<Option<PointsAdjustment>> {
+        self.try_hold_fence(session, fence).await?;
+        let guess_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let ledger_collection = self
+            .db
+            .collection::<mongodb::bson::Document>("points_ledger");
+
+        // The guesses notified before the paid state existed were paid with the notification
+        let filter = doc! {
+            "_id": id,
+            "points": { "$type": "number" },
+            "paid": { "$ne": true },
+            "dmSent": { "$ne": true }
+        };
+        let update = doc! {
+            "$set": {
+                "paid": true,
+                "paidAt": DateTime::from_chrono(self.clock.now()),
+                "updatedAt": DateTime::from_chrono(self.clock.now()),
+            }
+        };
+        let guess = match guess_collection
+            .find_one_and_update_with_session(filter, update, None, session)
+            .await?
+        {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        let points = guess.get_i32("points").unwrap_or_default();
+        let user_id = match guess.get("dcId") {
+            Some(Bson::Int64(dc_id)) => dc_id.to_string(),
+            Some(Bson::Int32(dc_id)) => dc_id.to_string(),
+            _ => return Ok(None),
+        };
+        if points <= 0 {
+            return Ok(Some(PointsAdjustment {
+                balance: 0,
+                applied: 0,
+            }));
+        }
+
+        let user_name = guess.get_str("dcUsername").ok();
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .build();
+        let previous_points = user_collection
+            .find_one_and_update_with_session(
+                doc! {"_id": &user_id},
+                capped_points_update(points, user_name, self.clock.now()),
+                options,
+                session,
+            )
+            .await?
+            .and_then(|document| document.get_i32("points").ok())
+            .unwrap_or_default();
+        let adjustment = PointsAdjustment::capped(previous_points, points);
+
+        if adjustment.applied != 0 {
+            let entry = LedgerEntry {
+                id: None,
+                user_id,
+                delta: adjustment.applied,
+                reason: PointsReason::LottoPrize,
+                source_id: Some(id.to_hex()),
+                created_at: self.clock.now(),
+            };
+            ledger_collection
+                .insert_one_with_session(bson::to_document(&entry)?, None, session)
+                .await?;
+        }
+
+        Ok(Some(adjustment))
+    }
+
+    async fn get_lotto_round_summary(&self, draw: LottoDraw) -> MongoResult<LottoRoundSummary> {
+        let guess_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
+        let pipeline = vec![
+            doc! { "$match": draw.round.filter() },
+            doc! { "$group": {
+                "_id": "$matchedCount",
+                "entries": { "$sum": 1 },
+                "points": { "$sum": { "$ifNull": ["$points", 0] } }
+            } },
+        ];
+        let mut cursor = guess_collection.aggregate(pipeline, None).await?;
+
+        let mut summary = LottoRoundSummary {
+            draw,
+            entries: 0,
+            winners: Default::default(),
+            points_paid: 0,
+        };
+        while let Some(result) = cursor.next().await {
+            let document = result?;
+            let as_i64 = |key: &str| match document.get(key) {
+                Some(Bson::Int32(value)) => *value as i64,
+                Some(Bson::Int64(value)) => *value,
+                _ => 0,
+            };
+            let entries = as_i64("entries");
+            let matches = as_i64("_id");
+
+            summary.entries += entries;
+            summary.points_paid += as_i64("points");
+            if matches > 0 {
+                summary.winners.insert(matches as u32, entries);
+            }
+        }
+
+        Ok(summary)
+    }
+}
+
+#[async_trait]
+impl Store for MongoDB {
+    // Seeds the catalog with the tournament ticket when it is empty
+    async fn open_catalog(&self) -> StoreResult<bool> {
+        let catalog_collection = self.db.collection::<mongodb::bson::Document>("catalog");
+        if catalog_collection.count_documents(None, None).await? > 0 {
+            return Ok(false);
+        }
+
+        let ticket = CatalogItem {
+            id: "ticket".to_string(),
+            name: "Tournament ticket".to_string(),
+            price: 1000,
+            ..Default::default()
+        };
+        catalog_collection
+            .insert_one(bson::to_document(&ticket)?, None)
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn get_catalog_item(&self, id: &str) -> StoreResult<Option<CatalogItem>> {
+        let catalog_collection = self.db.collection::<mongodb::bson::Document>("catalog");
+        match catalog_collection.find_one(doc! {"_id": id}, None).await? {
+            Some(document) => Ok(Some(bson::from_document(document)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Returns the catalog sorted by price, only the items in their active window if requested
+    async fn get_catalog_items(&self, active_only: bool) -> StoreResult<Vec<CatalogItem>> {
+        let catalog_collection = self.db.collection::<mongodb::bson::Document>("catalog");
+        let filter = if active_only {
+            active_item_filter(self.clock.now())
+        } else {
+            doc! {}
+        };
+        let options = FindOptions::builder()
+            .sort(doc! {"price": 1, "_id": 1})
+            .build();
+        let mut cursor = catalog_collection.find(filter, options).await?;
+
+        let mut items = Vec::new();
+        while let Some(result) = cursor.next().await {
+            items.push(bson::from_document(result?)?);
+        }
+
+        Ok(items)
+    }
+
+    async fn set_catalog_item(&self, item: &CatalogItem) -> StoreResult<()> {
+        let catalog_collection = self.db.collection::<mongodb::bson::Document>("catalog");
+        let options = ReplaceOptions::builder().upsert(true).build();
+        catalog_collection
+            .replace_one(doc! {"_id": &item.id}, bson::to_document(item)?, options)
+            .await?;
+
+        Ok(())
+    }
+
+    // Checks the catalog, decrements the stock, subtracts the points and adds the exchange record together
+    async fn add_exchange_record(&self, exchange: Exchange) -> StoreResult<ExchangeOutcome> {
+        Ok(self
+            .with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self.try_add_exchange_record(&mut session, &exchange).await;
+                        (session, result)
+                    })
+                },
+                |outcome| matches!(outcome, ExchangeOutcome::Accepted(_)),
+            )
+            .await?)
+    }
+
+    async fn get_user_points(&self, user_id: &str) -> StoreResult<i32> {
         let user_collection = self.db.collection::<mongodb::bson::Document>("users");
         let filter = doc! {"_id": user_id };
         let options = FindOneOptions::builder()
@@ -64,75 +1150,290 @@ impl MongoDB {
         }
     }
 
-    pub async fn adjust_user_points(
+    // Applies the points within the maximum and records them in the points ledger in one transaction
+    async fn adjust_user_points(
         &self,
         user_id: &str,
         user_name: Option<&str>,
         points: i32,
-    ) -> MongoResult<()> {
+        reason: PointsReason,
+        source_id: Option<String>,
+    ) -> StoreResult<PointsAdjustment> {
+        Ok(self
+            .with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self
+                            .try_adjust_user_points(
+                                &mut session,
+                                user_id,
+                                user_name,
+                                points,
+                                reason,
+                                source_id.clone(),
+                            )
+                            .await;
+                        (session, result)
+                    })
+                },
+                |_| true,
+            )
+            .await?)
+    }
+
+    async fn add_ledger_entry(&self, entry: LedgerEntry) -> StoreResult<()> {
+        let ledger_collection = self
+            .db
+            .collection::<mongodb::bson::Document>("points_ledger");
+        let entry_doc = bson::to_document(&entry)?;
+        ledger_collection.insert_one(entry_doc, None).await?;
+
+        Ok(())
+    }
+
+    // Records the current balance as the opening entry for every user without ledger entries
+    async fn open_points_ledger(&self) -> StoreResult<u64> {
         let user_collection = self.db.collection::<mongodb::bson::Document>("users");
-        let max_points = 200000;
+        let ledger_collection = self
+            .db
+            .collection::<mongodb::bson::Document>("points_ledger");
 
-        // Get current points
-        let current_points = self.get_user_points(user_id).await?;
+        let options = FindOptions::builder()
+            .projection(doc! {"_id": 1, "points": 1})
+            .build();
+        let mut cursor = user_collection
+            .find(doc! {"points": { "$ne": 0 }}, options)
+            .await?;
 
-        let points_to_add = if points > 0 {
-            // If adding points and current_points already exceeds max_points, do nothing and return
-            if current_points >= max_points {
-                return Ok(());
-            }
+        let mut opened = 0;
+        while let Some(result) = cursor.next().await {
+            let document = result?;
+            let user_id = match document.get_str("_id") {
+                Ok(user_id) => user_id.to_string(),
+                Err(_) => continue,
+            };
 
-            // Calculate the points to add if adding points would exceed max_points
-            if current_points + points > max_points {
-                max_points - current_points
-            } else {
-                points
+            let has_entries = ledger_collection
+                .count_documents(doc! {"userId": &user_id}, None)
+                .await?
+                > 0;
+            if has_entries {
+                continue;
             }
-        } else {
-            // If subtracting points, just allow it
-            points
+
+            let entry = LedgerEntry {
+                id: None,
+                user_id,
+                delta: document.get_i32("points").unwrap_or_default(),
+                reason: PointsReason::Opening,
+                source_id: None,
+                created_at: self.clock.now(),
+            };
+            self.add_ledger_entry(entry).await?;
+            opened += 1;
+        }
+
+        Ok(opened)
+    }
+
+    // Rebuilds the balance of the user from the points ledger and compares it with the stored one
+    async fn reconcile_user_points(&self, user_id: &str) -> StoreResult<PointsDrift> {
+        let ledger_collection = self
+            .db
+            .collection::<mongodb::bson::Document>("points_ledger");
+        let pipeline = vec![
+            doc! { "$match": { "userId": user_id } },
+            doc! { "$group": { "_id": "$userId", "total": { "$sum": "$delta" } } },
+        ];
+
+        let mut cursor = ledger_collection.aggregate(pipeline, None).await?;
+        let ledger_points = match cursor.next().await {
+            Some(result) => match result?.get("total") {
+                Some(Bson::Int32(total)) => *total as i64,
+                Some(Bson::Int64(total)) => *total,
+                _ => 0,
+            },
+            None => 0,
         };
 
-        // Perform the database operation
-        let filter = doc! {"_id": user_id};
-        let user_exists = user_collection
-            .find_one(filter.clone(), None)
-            .await?
-            .is_some();
+        Ok(PointsDrift {
+            ledger_points,
+            stored_points: self.get_user_points(user_id).await?,
+        })
+    }
 
-        let update = if user_exists {
-            // If user exists, prepare update document for existing user
-            doc! {
-                "$inc": {"points": points_to_add},
-                "$currentDate": {"updatedAt": true}
-            }
-        } else {
-            // If user does not exist, prepare document for new user
-            let mut set_on_insert_doc = doc! {
-                "_id": user_id,
+    // Returns the users whose stored balance does not match the points ledger
+    async fn reconcile_all_points(&self) -> StoreResult<Vec<(String, PointsDrift)>> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let options = FindOptions::builder().projection(doc! {"_id": 1}).build();
+        let mut cursor = user_collection.find(None, options).await?;
+
+        let mut drifts = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let user_id = match result?.get_str("_id") {
+                Ok(user_id) => user_id.to_string(),
+                Err(_) => continue,
             };
-            if let Some(name) = user_name {
-                set_on_insert_doc.insert("userName", name);
-            }
-            doc! {
-                "$setOnInsert": set_on_insert_doc,
-                "$set": {"points": points_to_add},
-                "$currentDate": {"createdAt": true, "updatedAt": true}
+
+            let drift = self.reconcile_user_points(&user_id).await?;
+            if drift.drift() != 0 {
+                drifts.push((user_id, drift));
             }
+        }
+
+        Ok(drifts)
+    }
+
+    async fn get_user_streak(&self, user_id: &str) -> StoreResult<AttendanceStreak> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let filter = doc! {"_id": user_id };
+        let options = FindOneOptions::builder()
+            .projection(doc! {"attendStreak": 1, "bestAttendStreak": 1, "lastAttendDate": 1})
+            .build();
+
+        let document = match user_collection.find_one(filter, options).await? {
+            Some(document) => document,
+            None => return Ok(AttendanceStreak::default()),
         };
+        let mut streak: AttendanceStreak = bson::from_document(document.clone())?;
+
+        // The streak is broken if the user did not check in yesterday or today
+        let yesterday = self.clock.now().date_naive() - Duration::days(1);
+        let last_attend_date = document
+            .get_datetime("lastAttendDate")
+            .map(|date| date.to_chrono().date_naive());
+        if !matches!(last_attend_date, Ok(date) if date >= yesterday) {
+            streak.current = 0;
+        }
 
-        let update_options = UpdateOptions::builder().upsert(true).build();
-        if let Err(e) = user_collection
-            .update_one(filter, update, Some(update_options))
-            .await
-        {
-            error!("Error updating user points: {}", e);
+        Ok(streak)
+    }
+
+    // Records today's check-in, extends the attendance streak and credits the points together
+    async fn record_attendance(
+        &self,
+        activity: Activity,
+        user_name: Option<&str>,
+        streak_bonus: &BTreeMap<i32, i32>,
+    ) -> StoreResult<Option<AttendanceReward>> {
+        Ok(self
+            .with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self
+                            .try_record_attendance(&mut session, &activity, user_name, streak_bonus)
+                            .await;
+                        (session, result)
+                    })
+                },
+                Option::is_some,
+            )
+            .await?)
+    }
+
+    async fn get_user_wallet(&self, user_id: &str) -> StoreResult<Option<UserWallet>> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let filter = doc! {"_id": user_id, "walletAddress": { "$exists": true }};
+        let options = FindOneOptions::builder()
+            .projection(doc! {"walletAddress": 1, "walletVerified": 1, "walletUpdatedAt": 1})
+            .build();
+
+        match user_collection.find_one(filter, options).await? {
+            Some(document) => Ok(Some(bson::from_document(document)?)),
+            None => Ok(None),
         }
+    }
 
-        Ok(())
+    // Registers the default wallet and records the change for the admins
+    async fn set_user_wallet(
+        &self,
+        user_id: &str,
+        user_name: &str,
+        address: &str,
+        verified: bool,
+        cooldown: Duration,
+    ) -> StoreResult<WalletUpdate> {
+        // The wallet change and its audit are written together
+        Ok(self
+            .with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self
+                            .try_set_user_wallet(
+                                &mut session,
+                                user_id,
+                                user_name,
+                                address,
+                                verified,
+                                cooldown,
+                            )
+                            .await;
+                        (session, result)
+                    })
+                },
+                |update| !matches!(update, WalletUpdate::Cooldown),
+            )
+            .await?)
+    }
+
+    // Returns the latest wallet changes of the user
+    async fn get_wallet_audits(&self, user_id: &str, limit: i64) -> StoreResult<Vec<WalletAudit>> {
+        let audit_collection = self.db.collection::<mongodb::bson::Document>("walletaudit");
+        let options = FindOptions::builder()
+            .sort(doc! {"createdAt": -1})
+            .limit(limit)
+            .build();
+        let mut cursor = audit_collection
+            .find(doc! {"userId": user_id}, options)
+            .await?;
+
+        let mut audits = Vec::new();
+        while let Some(result) = cursor.next().await {
+            audits.push(bson::from_document(result?)?);
+        }
+
+        Ok(audits)
+    }
+
+    // Returns the users with the most points, ties are ordered by the user ID
+    async fn get_top_users(&self, limit: i64) -> StoreResult<Vec<RankedUser>> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let filter = doc! {"points": { "$gt": 0 }};
+        let options = FindOptions::builder()
+            .projection(doc! {"_id": 1, "userName": 1, "points": 1})
+            .sort(doc! {"points": -1, "_id": 1})
+            .limit(limit)
+            .build();
+
+        let mut cursor = user_collection.find(filter, options).await?;
+        let mut results = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let user: RankedUser = bson::from_document(result?)?;
+            results.push(user);
+        }
+
+        Ok(results)
+    }
+
+    // Returns the 1-based rank of the user and their points, users with equal points share the same rank
+    async fn get_user_rank(&self, user_id: &str) -> StoreResult<Option<(u64, i32)>> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let points = match user_collection
+            .find_one(doc! {"_id": user_id, "points": { "$gt": 0 }}, None)
+            .await?
+        {
+            Some(document) => document.get_i32("points").unwrap_or_default(),
+            None => return Ok(None),
+        };
+
+        let ahead = user_collection
+            .count_documents(doc! {"points": { "$gt": points }}, None)
+            .await?;
+
+        Ok(Some((ahead + 1, points)))
     }
 
-    pub async fn get_user_records(&self, dc_id: u64) -> Result<Vec<Exchange>, Error> {
+    async fn get_user_records(&self, dc_id: u64) -> StoreResult<Vec<Exchange>> {
         let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
         let filter = doc! {
             "dcId": dc_id as i64,
@@ -145,6 +1446,7 @@ impl MongoDB {
                 "item": 1,
                 "quantity": 1,
                 "status": 1,
+                "txHash": 1,
                 "updatedAt": 1
             })
             .limit(8) // Limit the number of documents returned
@@ -158,52 +1460,203 @@ impl MongoDB {
                     let exchange: Exchange = bson::from_bson(Bson::Document(doc))?;
                     results.push(exchange);
                 }
-                Err(e) => return Err(e),
+                Err(e) => return Err(e.into()),
             }
         }
-        Ok(results)
-    }
+        Ok(results)
+    }
+
+    async fn get_exchanges_by_status(
+        &self,
+        statuses: &[ExchangeStatus],
+        limit: i64,
+    ) -> StoreResult<Vec<Exchange>> {
+        let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
+        let statuses: Vec<Bson> = statuses
+            .iter()
+            .map(|status| Bson::String(status.to_string()))
+            .collect();
+        let filter = doc! { "status": { "$in": statuses } };
+        let options = FindOptions::builder()
+            .sort(doc! {"createdAt": 1}) // Oldest requests first
+            .limit(limit)
+            .build();
+
+        let mut cursor = exchange_collection.find(filter, options).await?;
+        let mut results = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let exchange: Exchange = bson::from_document(result?)?;
+            results.push(exchange);
+        }
+
+        Ok(results)
+    }
+
+    // Moves a single exchange to the new status if it is currently in one of the given statuses,
+    // returns the updated exchange or `None` if the transition is not allowed
+    async fn update_exchange_status(
+        &self,
+        id: ObjectId,
+        from: &[ExchangeStatus],
+        to: ExchangeStatus,
+        reject_reason: Option<String>,
+    ) -> StoreResult<Option<Exchange>> {
+        let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
+        let from: Vec<Bson> = from
+            .iter()
+            .map(|status| Bson::String(status.to_string()))
+            .collect();
+        let filter = doc! { "_id": id, "status": { "$in": from } };
+
+        let mut set_doc = doc! {
+            "status": Bson::String(to.to_string()),
+            "updatedAt": DateTime::from_chrono(self.clock.now()),
+        };
+        if let Some(reason) = reject_reason {
+            set_doc.insert("rejectReason", reason);
+        }
+        let update = doc! { "$set": set_doc };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        match exchange_collection
+            .find_one_and_update(filter, update, options)
+            .await?
+        {
+            Some(document) => Ok(Some(bson::from_document(document)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Gives the points of a rejected exchange back to the user in a single transaction,
+    // returns `None` if the exchange is not rejected
+    async fn refund_exchange(&self, id: ObjectId) -> StoreResult<Option<Exchange>> {
+        Ok(self
+            .with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self.try_refund_exchange(&mut session, id).await;
+                        (session, result)
+                    })
+                },
+                Option::is_some,
+            )
+            .await?)
+    }
+
+    // Moves the submitted exchanges to processing and returns the batch that was moved
+    async fn update_all_submitted_to_processing(
+        &self,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Vec<Exchange>> {
+        // A request rejected while the batch is moved conflicts with the move, which retries
+        // without it
+        fenced(
+            self.with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self
+                            .try_move_submitted_to_processing(&mut session, fence)
+                            .await;
+                        (session, result)
+                    })
+                },
+                |_| true,
+            )
+            .await,
+        )
+    }
+
+    // Returns the exchanges requested between the dates, excluding the rejected ones
+    async fn get_exchanges_between(
+        &self,
+        from: chrono::DateTime<Utc>,
+        to: chrono::DateTime<Utc>,
+    ) -> StoreResult<Vec<Exchange>> {
+        let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
+        let filter = doc! {
+            "createdAt": { "$gte": from, "$lt": to },
+            "status": { "$nin": [
+                Bson::String(ExchangeStatus::Rejected.to_string()),
+                Bson::String(ExchangeStatus::Refunded.to_string()),
+            ] }
+        };
+        let options = FindOptions::builder().sort(doc! {"createdAt": 1}).build();
+
+        let mut cursor = exchange_collection.find(filter, options).await?;
+        let mut results = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let exchange: Exchange = bson::from_document(result?)?;
+            results.push(exchange);
+        }
 
-    pub async fn update_all_submitted_to_processing(&self) -> Result<(), Error> {
-        let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
-        let filter = doc! { "status": Bson::String(ExchangeStatus::Submitted.to_string()) };
-        let update = doc! { "$set": { "status": Bson::String(ExchangeStatus::Processing.to_string())}, "$currentDate": { "updatedAt": true }};
-        exchange_collection
-            .update_many(filter, update, None)
-            .await?;
-        Ok(())
+        Ok(results)
     }
 
-    pub async fn update_all_processing_to_completed(&self) -> Result<(), Error> {
+    // Records the delivery result of a processing exchange, completing it on success,
+    // returns the updated exchange or `None` if the exchange is not processing
+    async fn apply_fulfilment_result(
+        &self,
+        id: ObjectId,
+        result: &FulfilmentResult,
+    ) -> StoreResult<Option<Exchange>> {
         let exchange_collection = self.db.collection::<mongodb::bson::Document>("exchange");
-        let filter = doc! { "status": Bson::String(ExchangeStatus::Processing.to_string()) };
-        let update = doc! { "$set": { "status": Bson::String(ExchangeStatus::Completed.to_string())}, "$currentDate": { "updatedAt": true }};
-        exchange_collection
-            .update_many(filter, update, None)
-            .await?;
+        let filter =
+            doc! { "_id": id, "status": Bson::String(ExchangeStatus::Processing.to_string()) };
+
+        let update = match result.status {
+            FulfilmentStatus::Completed => doc! {
+                "$set": {
+                    "status": Bson::String(ExchangeStatus::Completed.to_string()),
+                    "txHash": result.tx_hash.clone(),
+                    "updatedAt": DateTime::from_chrono(self.clock.now()),
+                },
+                "$unset": { "fulfilmentError": "" },
+            },
+            FulfilmentStatus::Failed => doc! {
+                "$set": {
+                    "fulfilmentError": result.error.clone().unwrap_or_else(|| "Unknown error".to_string()),
+                    "updatedAt": DateTime::from_chrono(self.clock.now()),
+                },
+            },
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
 
-        Ok(())
+        match exchange_collection
+            .find_one_and_update(filter, update, options)
+            .await?
+        {
+            Some(document) => Ok(Some(bson::from_document(document)?)),
+            None => Ok(None),
+        }
     }
 
-    pub async fn clean_documents(&self) -> Result<DeleteResult, Error> {
+    async fn clean_documents(&self) -> StoreResult<u64> {
         let activity_collection = self.db.collection::<mongodb::bson::Document>("activity");
-        let about_five_weeks_ago = Utc::now() - Duration::weeks(5);
-        let about_five_weeks_ago_bson = DateTime::from_chrono(about_five_weeks_ago);
 
         let delete_result = activity_collection
-            .delete_many(
-                doc! { "createdAt": { "$lt": about_five_weeks_ago_bson} },
-                None,
-            )
+            .delete_many(old_activity_filter(self.clock.now()), None)
             .await?;
 
-        Ok(delete_result)
+        Ok(delete_result.deleted_count)
+    }
+
+    // The number of documents `clean_documents` would delete
+    async fn count_old_documents(&self) -> StoreResult<u64> {
+        let activity_collection = self.db.collection::<mongodb::bson::Document>("activity");
+
+        Ok(activity_collection
+            .count_documents(old_activity_filter(self.clock.now()), None)
+            .await?)
     }
 
-    pub async fn add_react_poll_activity(&self, new_activity: Activity) -> Result<bool, Error> {
+    async fn add_react_poll_activity(&self, new_activity: Activity) -> StoreResult<bool> {
         let activity_collection = self.db.collection::<mongodb::bson::Document>("activity");
-        let today = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
-        let datetime_utc: chrono::DateTime<Utc> = chrono::DateTime::from_utc(today, Utc);
+        let today = self.clock.now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+        let datetime_utc: chrono::DateTime<Utc> = today.and_utc();
 
         // Filter to match activities by the same user, of the same type, on the same day
         let filter_today = doc! {
@@ -249,10 +1702,10 @@ impl MongoDB {
         Ok(true)
     }
 
-    pub async fn add_reaction_activity(&self, activity: Activity) -> Result<bool, Error> {
+    async fn add_reaction_activity(&self, activity: Activity) -> StoreResult<bool> {
         let activity_collection = self.db.collection::<mongodb::bson::Document>("activity");
-        let today = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
-        let datetime_utc: chrono::DateTime<Utc> = chrono::DateTime::from_utc(today, Utc);
+        let today = self.clock.now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+        let datetime_utc: chrono::DateTime<Utc> = today.and_utc();
 
         let reaction = &activity.activity.unwrap();
         let filter_today = doc! {
@@ -276,127 +1729,266 @@ impl MongoDB {
         Ok(true)
     }
 
-    pub async fn add_weekly_draw(&self) -> MongoResult<()> {
-        let numbers = generate_numbers();
-        let (year, week) = get_week_number();
+    // Opens the lotto round of this week with a secret seed and returns it.
+    // Returns the existing round if it is already open.
+    async fn add_weekly_draw(&self) -> StoreResult<LottoDraw> {
+        let round = LottoRound::at(self.clock.now());
+        let seed = generate_lotto_seed();
+        // The round follows the rules in effect when the week opened
+        let rules = self.get_lotto_rules(round.starts_at()).await?;
 
         let lotto_draw_collection = self.db.collection::<mongodb::bson::Document>("lottodraw");
+        let filter = round.filter();
+
+        // Only the first call of the week stores its seed, so the commitment never changes
+        let draw = LottoDraw {
+            id: None,
+            round,
+            numbers: Vec::new(),
+            commitment: Some(lotto_commitment(&seed)),
+            seed: Some(seed),
+            drawn_at: None,
+            rules: Some(rules),
+            jackpot: 0,
+            jackpot_winners: None,
+            jackpot_prize: None,
+            jackpot_rollover: None,
+            date: self.clock.now(),
+        };
+        let update = doc! { "$setOnInsert": bson::to_document(&draw)? };
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        match lotto_draw_collection
+            .find_one_and_update(filter, update, options)
+            .await?
+        {
+            Some(document) => Ok(bson::from_document(document)?),
+            None => Ok(draw),
+        }
+    }
+
+    // Moves the lotto documents stored with the calendar year, or without a year, to their ISO
+    // round. Only the weeks around January 1 can differ, so only those documents are checked.
+    async fn open_lotto_rounds(&self) -> StoreResult<u64> {
         let filter = doc! {
-            "year": year,
-            "weekNumber": week
-        };
-
-        // Check if a document for this week already exists
-        match lotto_draw_collection.find_one(filter.clone(), None).await? {
-            // If it does not exist, insert a new one
-            None => {
-                let draw = LottoDraw {
-                    id: None,
-                    year,
-                    numbers,
-                    week_number: week,
-                    date: Utc::now(),
-                    ..Default::default()
+            "$or": [
+                { "year": { "$exists": false } },
+                { "weekNumber": { "$in": [1, 52, 53] } }
+            ]
+        };
+        let mut moved = 0;
+
+        for (collection_name, date_field) in [("lottodraw", "date"), ("lottoguess", "createdAt")] {
+            let collection = self
+                .db
+                .collection::<mongodb::bson::Document>(collection_name);
+            let mut cursor = collection.find(filter.clone(), None).await?;
+
+            while let Some(result) = cursor.next().await {
+                let document = result?;
+                let created_at = match document.get_datetime(date_field) {
+                    Ok(created_at) => created_at.to_chrono(),
+                    Err(_) => continue,
+                };
+                let round = LottoRound::at(created_at);
+                let as_i64 = |key: &str| match document.get(key) {
+                    Some(Bson::Int32(value)) => Some(*value as i64),
+                    Some(Bson::Int64(value)) => Some(*value),
+                    _ => None,
                 };
+                if as_i64("year") == Some(round.year as i64)
+                    && as_i64("weekNumber") == Some(round.week as i64)
+                {
+                    continue;
+                }
 
-                let draw_doc = bson::to_bson(&draw)
-                    .expect("Failed to serialize")
-                    .as_document()
-                    .cloned()
-                    .expect("Expected BSON Document");
+                // Two draws of the same week were opened on both sides of January 1
+                if collection_name == "lottodraw"
+                    && collection.count_documents(round.filter(), None).await? > 0
+                {
+                    warn!(
+                        "The lotto draw {:?} is a duplicate of the round {}",
+                        document.get("_id"),
+                        round
+                    );
+                    continue;
+                }
 
-                lotto_draw_collection.insert_one(draw_doc, None).await?;
+                collection
+                    .update_one(
+                        doc! {"_id": document.get("_id").cloned()},
+                        doc! {"$set": round.filter()},
+                        None,
+                    )
+                    .await?;
+                moved += 1;
             }
-            // If it exists, do nothing
-            Some(_) => {}
         }
 
-        Ok(())
+        Ok(moved)
     }
 
-    pub async fn get_lotto_draw(&self, year: i32, week_number: u32) -> Result<Vec<i32>, Error> {
-        let draw_collection = self.db.collection::<mongodb::bson::Document>("lottodraw");
+    // Returns the latest rules in effect at the time, or the original rules if none are stored
+    async fn get_lotto_rules(&self, at: chrono::DateTime<Utc>) -> StoreResult<LottoRules> {
+        let rules_collection = self.db.collection::<mongodb::bson::Document>("lottorules");
+        let filter = doc! {"effectiveFrom": { "$lte": DateTime::from_chrono(at) }};
+        let options = FindOneOptions::builder()
+            .sort(doc! {"effectiveFrom": -1})
+            .build();
 
-        let filter = doc! {
-            "year": year,
-            "weekNumber": week_number
+        let rules: LottoRules = match rules_collection.find_one(filter, options).await? {
+            Some(document) => bson::from_document(document)?,
+            None => return Ok(LottoRules::default()),
         };
-        // Try to find a document with the matching year and week number
-        let result = draw_collection.find_one(filter, None).await?;
+        rules.validate().map_err(|e| {
+            format!(
+                "Invalid lotto rules from {}: {}",
+                rules.effective_from.date_naive(),
+                e
+            )
+        })?;
 
-        match result {
-            Some(doc) => {
-                // If a document is found, convert the BSON document to LottoDraw
-                match bson::from_bson::<LottoDraw>(bson::Bson::Document(doc)) {
-                    Ok(lotto_draw) => {
-                        // If successful, return the numbers
-                        Ok(lotto_draw.numbers)
-                    }
-                    // If unsuccessful, return an appropriate error
-                    Err(e) => return Err(e.into()),
-                }
-            }
-            None => {
-                // If no document is found, return an appropriate error
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "No matching lotto draw found",
-                )
-                .into())
-            }
-        }
+        Ok(rules)
     }
 
-    pub async fn add_lotto_guess(&self, guess: LottoGuess) -> Result<bool, Error> {
-        let guess_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
+    async fn get_lotto_round(&self, round: LottoRound) -> StoreResult<Option<LottoDraw>> {
+        let draw_collection = self.db.collection::<mongodb::bson::Document>("lottodraw");
 
-        // Check how many guesses the user has made this week
+        match draw_collection.find_one(round.filter(), None).await? {
+            Some(document) => Ok(Some(bson::from_document(document)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_undrawn_lotto_rounds(&self, before: LottoRound) -> StoreResult<Vec<LottoRound>> {
+        let draw_collection = self.db.collection::<mongodb::bson::Document>("lottodraw");
         let filter = doc! {
-            "dcId": guess.dc_id as i64,
-            "weekNumber": guess.week_number
+            "numbers": { "$size": 0 },
+            "$or": [
+                { "year": { "$lt": before.year } },
+                { "year": before.year, "weekNumber": { "$lt": before.week } },
+            ]
         };
+        let options = FindOptions::builder()
+            .projection(doc! {"year": 1, "weekNumber": 1})
+            .sort(doc! {"year": 1, "weekNumber": 1})
+            .build();
 
-        let count = guess_collection.count_documents(filter, None).await?;
+        let mut cursor = draw_collection.find(filter, options).await?;
+        let mut rounds = Vec::new();
+        while let Some(result) = cursor.next().await {
+            rounds.push(bson::from_document(result?)?);
+        }
 
-        // If the user has made 5 or more guesses this week, return false
-        if count >= 5 {
-            return Ok(false);
+        Ok(rounds)
+    }
+
+    // Draws the winning numbers of a round from its committed seed alone, scores all
+    // of its guesses and settles its jackpot. It can be retried, the numbers are drawn once,
+    // only the unscored guesses are scored and the jackpot is rolled over once.
+    async fn close_lotto_round(
+        &self,
+        round: LottoRound,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Option<LottoDraw>> {
+        let mut draw = match self.get_lotto_round(round).await? {
+            Some(draw) => draw,
+            None => return Ok(None),
+        };
+
+        // The rounds from before the commitment were drawn when they opened
+        if draw.numbers.is_empty() {
+            let seed = match &draw.seed {
+                Some(seed) => seed,
+                None => {
+                    return Err(format!("Lotto round {} has no seed to draw from", round).into())
+                }
+            };
+            // Only the seed committed at the week open decides the numbers, so a retry draws the
+            // same ones
+            let numbers = draw_lotto_numbers(seed, draw.rules().digits);
+            let drawn = fenced(
+                self.with_transaction(
+                    |mut session| {
+                        Box::pin(async {
+                            let result = self
+                                .try_draw_lotto_round(&mut session, round, &numbers, fence)
+                                .await;
+                            (session, result)
+                        })
+                    },
+                    Option::is_some,
+                )
+                .await,
+            )?;
+            if let Some(drawn) = drawn {
+                draw = drawn;
+            } else if let Some(drawn) = self.get_lotto_round(round).await? {
+                draw = drawn;
+            }
         }
 
-        // Convert LottoGuess instance to a BSON Document
-        let guess_doc = doc! {
-            "dcId": guess.dc_id as i64,
-            "dcUsername": guess.dc_username,
-            "numbers": Bson::Array(guess.numbers.into_iter().map(Bson::Int32).collect()),
-            "year": guess.year,
-            "weekNumber": guess.week_number,
-            "matchedCount": guess.matched_count,
-            "isMatched": guess.is_any_matched,
-            "points": guess.points,
-            "dmSent": guess.dm_sent,
-            "createdAt": guess.created_at,
-            "updatedAt": guess.updated_at,
+        // The winners who matched all digits split the top prize and the jackpot evenly
+        let rules = draw.rules();
+        let winners = self.count_full_matches(round, &draw.numbers).await?;
+        let jackpot_prize = if winners > 0 {
+            draw.jackpot_pool() / winners
+        } else {
+            0
         };
 
-        guess_collection.insert_one(guess_doc, None).await?;
+        self.score_lotto_guesses(round, &draw.numbers, &rules, jackpot_prize)
+            .await?;
 
-        Ok(true)
+        let draw = self
+            .settle_lotto_jackpot(draw, winners, jackpot_prize, fence)
+            .await?;
+
+        Ok(Some(draw))
+    }
+
+    // Charges the fee and adds the guess together, so only the accepted entries are charged
+    async fn add_lotto_guess(
+        &self,
+        guess: LottoGuess,
+        fee: i32,
+        jackpot_contribution: i32,
+        max_entries: u64,
+    ) -> StoreResult<LottoEntryOutcome> {
+        Ok(self
+            .with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self
+                            .try_add_lotto_guess(
+                                &mut session,
+                                &guess,
+                                fee,
+                                jackpot_contribution,
+                                max_entries,
+                            )
+                            .await;
+                        (session, result)
+                    })
+                },
+                |outcome| matches!(outcome, LottoEntryOutcome::Accepted(_)),
+            )
+            .await?)
     }
 
-    pub async fn get_lotto_guesses(
+    async fn get_lotto_guesses(
         &self,
-        year: i32,
-        week_number: u32,
+        round: LottoRound,
         dm_sent: Option<bool>,
-    ) -> MongoResult<Vec<LottoGuess>> {
+    ) -> StoreResult<Vec<LottoGuess>> {
         let lotto_guesses_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
 
-        // Query to get all LottoGuess documents matching the year, week number, is_any_matched condition, and dm_sent is false
-        let mut filter = doc! {
-            "year": year,
-            "weekNumber": week_number,
-            "isMatched": true,
-        };
+        // Query to get all LottoGuess documents matching the round, is_any_matched condition, and dm_sent is false
+        let mut filter = round.filter();
+        filter.insert("isMatched", true);
 
         if let Some(dm_sent_value) = dm_sent {
             filter.insert("dmSent", dm_sent_value);
@@ -426,7 +2018,48 @@ impl MongoDB {
         Ok(results)
     }
 
-    pub async fn update_dm_sent_flag(&self, id: ObjectId) -> MongoResult<()> {
+    // Credits the prize of a scored guess and marks it paid in one transaction, so a guess
+    // is paid once however often the payout is retried. Returns None if it was already paid.
+    async fn pay_lotto_prize(
+        &self,
+        id: ObjectId,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Option<PointsAdjustment>> {
+        fenced(
+            self.with_transaction(
+                |mut session| {
+                    Box::pin(async {
+                        let result = self.try_pay_lotto_prize(&mut session, id, fence).await;
+                        (session, result)
+                    })
+                },
+                Option::is_some,
+            )
+            .await,
+        )
+    }
+
+    // Records a failed prize DM and returns the number of failed attempts of the guess
+    async fn add_lotto_dm_failure(&self, id: ObjectId, error: &str) -> StoreResult<i32> {
+        let guess_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
+        let update = doc! {
+            "$inc": { "dmAttempts": 1 },
+            "$set": { "dmError": error, "updatedAt": DateTime::from_chrono(self.clock.now()) }
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let attempts = guess_collection
+            .find_one_and_update(doc! {"_id": id}, update, options)
+            .await?
+            .and_then(|document| document.get_i32("dmAttempts").ok())
+            .unwrap_or_default();
+
+        Ok(attempts)
+    }
+
+    async fn update_dm_sent_flag(&self, id: ObjectId) -> StoreResult<()> {
         let lotto_guesses_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
 
         // Query to match the document with the given ID
@@ -437,9 +2070,9 @@ impl MongoDB {
         // Update the dm_sent field for the matched document
         let update = doc! {
             "$set": {
-                "dmSent": true
+                "dmSent": true,
+                "updatedAt": DateTime::from_chrono(self.clock.now()),
             },
-            "$currentDate": { "updatedAt": true }
         };
 
         // Perform the update operation
@@ -450,20 +2083,143 @@ impl MongoDB {
         Ok(())
     }
 
-    pub async fn get_user_lotto_guesses(
+    // Returns a page of the drawn lotto rounds, newest first, and the number of drawn rounds
+    async fn get_lotto_draw_history(
+        &self,
+        page: u64,
+        page_size: u64,
+    ) -> StoreResult<(Vec<LottoRoundSummary>, u64)> {
+        let draw_collection = self.db.collection::<mongodb::bson::Document>("lottodraw");
+        let filter = doc! {"numbers.0": { "$exists": true }};
+        let total = draw_collection
+            .count_documents(filter.clone(), None)
+            .await?;
+
+        let options = FindOptions::builder()
+            .sort(doc! {"year": -1, "weekNumber": -1})
+            .skip(page * page_size)
+            .limit(page_size as i64)
+            .build();
+        let mut cursor = draw_collection.find(filter, options).await?;
+
+        let mut summaries = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let draw: LottoDraw = bson::from_document(result?)?;
+            summaries.push(self.get_lotto_round_summary(draw).await?);
+        }
+
+        Ok((summaries, total))
+    }
+
+    // Returns a page of the user's lotto guesses, newest first, and the number of them
+    async fn get_user_lotto_history(
+        &self,
+        dc_id: u64,
+        page: u64,
+        page_size: u64,
+    ) -> StoreResult<(Vec<LottoGuess>, u64)> {
+        let guess_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
+        let filter = doc! {"dcId": dc_id as i64};
+        let total = guess_collection
+            .count_documents(filter.clone(), None)
+            .await?;
+
+        let options = FindOptions::builder()
+            .sort(doc! {"createdAt": -1})
+            .skip(page * page_size)
+            .limit(page_size as i64)
+            .build();
+        let mut cursor = guess_collection.find(filter, options).await?;
+
+        let mut guesses = Vec::new();
+        while let Some(result) = cursor.next().await {
+            guesses.push(bson::from_document(result?)?);
+        }
+
+        Ok((guesses, total))
+    }
+
+    // The numbers the user saved to replay with `/lotto favorite`
+    async fn get_lotto_favorite(&self, user_id: &str) -> StoreResult<Option<Vec<i32>>> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let options = FindOneOptions::builder()
+            .projection(doc! {"lottoFavorite": 1})
+            .build();
+
+        let favorite = user_collection
+            .find_one(doc! {"_id": user_id}, options)
+            .await?
+            .and_then(|document| {
+                document
+                    .get_array("lottoFavorite")
+                    .ok()
+                    .map(|numbers| numbers.iter().filter_map(|n| n.as_i32()).collect())
+            });
+
+        Ok(favorite)
+    }
+
+    async fn set_lotto_favorite(&self, user_id: &str, numbers: &[i32]) -> StoreResult<()> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let update = doc! {
+            "$set": {"lottoFavorite": numbers, "updatedAt": DateTime::from_chrono(self.clock.now()) }
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        user_collection
+            .update_one(doc! {"_id": user_id}, update, options)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_lotto_mention(&self, user_id: &str, enabled: bool) -> StoreResult<()> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let update = doc! {
+            "$set": {"lottoMention": enabled, "updatedAt": DateTime::from_chrono(self.clock.now()) }
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        user_collection
+            .update_one(doc! {"_id": user_id}, update, options)
+            .await?;
+
+        Ok(())
+    }
+
+    // Returns the users among the given ones who opted in to be mentioned in the lotto results
+    async fn get_lotto_mention_users(&self, dc_ids: &[u64]) -> StoreResult<HashSet<u64>> {
+        let user_collection = self.db.collection::<mongodb::bson::Document>("users");
+        let user_ids: Vec<String> = dc_ids.iter().map(|dc_id| dc_id.to_string()).collect();
+        let filter = doc! {
+            "_id": { "$in": user_ids },
+            "lottoMention": true
+        };
+        let options = FindOptions::builder().projection(doc! {"_id": 1}).build();
+        let mut cursor = user_collection.find(filter, options).await?;
+
+        let mut users = HashSet::new();
+        while let Some(result) = cursor.next().await {
+            if let Some(dc_id) = result?
+                .get_str("_id")
+                .ok()
+                .and_then(|id| id.parse::<u64>().ok())
+            {
+                users.insert(dc_id);
+            }
+        }
+
+        Ok(users)
+    }
+
+    async fn get_user_lotto_guesses(
         &self,
-        year: i32,
-        week_number: u32,
+        round: LottoRound,
         dc_id: u64,
-    ) -> MongoResult<Vec<LottoGuess>> {
+    ) -> StoreResult<Vec<LottoGuess>> {
         let lotto_guesses_collection = self.db.collection::<mongodb::bson::Document>("lottoguess");
 
         let filter = doc! {
             "dcId": dc_id as i64,
-            "$or": [
-                { "year": year, "weekNumber": week_number },
-                { "year": if week_number == 1 { year - 1 } else { year }, "weekNumber": if week_number == 1 { 53 } else { week_number - 1 } }
-            ],
+            "$or": [round.filter(), round.previous().filter()],
         };
 
         // Specify the fields to return and limit the resutls to 8 documents.
@@ -471,6 +2227,7 @@ impl MongoDB {
             .projection(doc! {
                 "dcId": 1,
                 "numbers": 1,
+                "year": 1,
                 "weekNumber": 1,
                 "createdAt": 1,
                 "updatedAt": 1,
@@ -501,4 +2258,276 @@ impl MongoDB {
 
         Ok(results)
     }
+
+    async fn get_job_state(&self, name: &str) -> StoreResult<Option<JobState>> {
+        let job_collection = self.db.collection::<mongodb::bson::Document>("jobs");
+
+        match job_collection.find_one(doc! {"_id": name}, None).await? {
+            Some(document) => Ok(Some(bson::from_document(document)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_job_states(&self) -> StoreResult<Vec<JobState>> {
+        let job_collection = self.db.collection::<mongodb::bson::Document>("jobs");
+        let mut cursor = job_collection.find(doc! {}, None).await?;
+
+        let mut states = Vec::new();
+        while let Some(document) = cursor.next().await {
+            states.push(bson::from_document(document?)?);
+        }
+
+        Ok(states)
+    }
+
+    async fn set_job_paused(&self, name: &str, paused: bool) -> StoreResult<()> {
+        let job_collection = self.db.collection::<mongodb::bson::Document>("jobs");
+        let update = doc! {
+            "$set": { "paused": paused, "updatedAt": DateTime::from_chrono(self.clock.now()) }
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        job_collection
+            .update_one(doc! {"_id": name}, update, options)
+            .await?;
+
+        Ok(())
+    }
+
+    // Moves past a run skipped while the job is paused, so it is not caught up later
+    async fn skip_job_run(
+        &self,
+        name: &str,
+        scheduled_at: chrono::DateTime<Utc>,
+    ) -> StoreResult<()> {
+        let job_collection = self.db.collection::<mongodb::bson::Document>("jobs");
+        let update = doc! {
+            "$set": {
+                "lastScheduledAt": DateTime::from_chrono(scheduled_at),
+                "lastResult": "Skipped, the job is paused",
+                "updatedAt": DateTime::from_chrono(self.clock.now()),
+            },
+            "$unset": { "lastSucceeded": "" },
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        job_collection
+            .update_one(doc! {"_id": name}, update, options)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_job_next_run(
+        &self,
+        name: &str,
+        next_run_at: chrono::DateTime<Utc>,
+    ) -> StoreResult<()> {
+        let job_collection = self.db.collection::<mongodb::bson::Document>("jobs");
+        let update = doc! {
+            "$set": { "nextRunAt": DateTime::from_chrono(next_run_at), "updatedAt": DateTime::from_chrono(self.clock.now()) }
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        job_collection
+            .update_one(doc! {"_id": name}, update, options)
+            .await?;
+
+        Ok(())
+    }
+
+    // Records a finished run of the job with its summary or its error. The run is recorded with
+    // the token of its lease, so a run whose lease was taken over cannot overwrite a newer one.
+    // A run started by an admin has no scheduled time and leaves the last scheduled one.
+    // Returns false when a newer run is already recorded.
+    async fn record_job_run(
+        &self,
+        name: &str,
+        token: i64,
+        scheduled_at: Option<chrono::DateTime<Utc>>,
+        result: &Result<String, String>,
+    ) -> StoreResult<bool> {
+        let job_collection = self.db.collection::<mongodb::bson::Document>("jobs");
+        let (succeeded, message) = match result {
+            Ok(summary) => (true, summary),
+            Err(error) => (false, error),
+        };
+        let filter = doc! {
+            "_id": name,
+            "$or": [{ "lastToken": null }, { "lastToken": { "$lte": token } }]
+        };
+        let now = DateTime::from_chrono(self.clock.now());
+        let mut set = doc! {
+            "lastSucceeded": succeeded,
+            "lastResult": message,
+            "lastToken": token,
+            "lastRunAt": now,
+            "updatedAt": now,
+        };
+        if let Some(scheduled_at) = scheduled_at {
+            set.insert("lastScheduledAt", DateTime::from_chrono(scheduled_at));
+        }
+        let update = doc! { "$set": set };
+        let options = UpdateOptions::builder().upsert(true).build();
+
+        match job_collection.update_one(filter, update, options).await {
+            Ok(_) => Ok(true),
+            // The filter missed the newer run, so the upsert collided with its document
+            Err(e) if is_duplicate_key_error(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Takes the lease of the job for the owner if it is free or expired. Returns the token of
+    // the lease, which grows with every acquisition, or None if another owner holds it.
+    async fn acquire_job_lease(
+        &self,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> StoreResult<Option<i64>> {
+        let lease_collection = self.db.collection::<mongodb::bson::Document>("jobleases");
+        let now = self.clock.now();
+        // The owner is the instance, so even its own live lease is not taken again: an admin
+        // run must not overlap the scheduled one
+        let filter = doc! {
+            "_id": name,
+            "expiresAt": { "$lte": DateTime::from_chrono(now) }
+        };
+        let update = doc! {
+            "$set": {
+                "owner": owner,
+                "acquiredAt": DateTime::from_chrono(now),
+                "expiresAt": DateTime::from_chrono(now + ttl),
+            },
+            "$inc": { "token": 1_i64 }
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        match lease_collection
+            .find_one_and_update(filter, update, options)
+            .await
+        {
+            Ok(Some(lease)) => Ok(lease.get_i64("token").ok()),
+            Ok(None) => Ok(None),
+            // The lease is held, so the upsert collided with it
+            Err(e) if is_duplicate_key_error(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Extends the lease while the job runs. Returns false if the lease was lost.
+    async fn renew_job_lease(
+        &self,
+        name: &str,
+        owner: &str,
+        token: i64,
+        ttl: Duration,
+    ) -> StoreResult<bool> {
+        let lease_collection = self.db.collection::<mongodb::bson::Document>("jobleases");
+        let filter = doc! {
+            "_id": name,
+            "owner": owner,
+            "token": token,
+            "expiresAt": { "$gt": DateTime::from_chrono(self.clock.now()) }
+        };
+        let update = doc! {
+            "$set": { "expiresAt": DateTime::from_chrono(self.clock.now() + ttl) }
+        };
+        let result = lease_collection.update_one(filter, update, None).await?;
+
+        Ok(result.matched_count == 1)
+    }
+
+    // Expires the lease so other owners can take it at once. The document is kept,
+    // so the next token still grows.
+    async fn release_job_lease(&self, name: &str, owner: &str, token: i64) -> StoreResult<()> {
+        let lease_collection = self.db.collection::<mongodb::bson::Document>("jobleases");
+        let filter = doc! { "_id": name, "owner": owner, "token": token };
+        let update = doc! {
+            "$set": { "expiresAt": DateTime::from_chrono(self.clock.now()) }
+        };
+        lease_collection.update_one(filter, update, None).await?;
+
+        Ok(())
+    }
+}
+
+// The balance of `PointsAdjustment::capped`, computed by the database from the current one,
+// which the update returns to work out the applied points
+fn capped_points_update(
+    points: i32,
+    user_name: Option<&str>,
+    now: chrono::DateTime<Utc>,
+) -> Vec<mongodb::bson::Document> {
+    let current_points = doc! { "$ifNull": ["$points", 0] };
+    let new_points = if points > 0 {
+        doc! { "$max": [current_points.clone(), { "$min": [{ "$add": [current_points, points] }, MAX_POINTS] }] }
+    } else {
+        doc! { "$min": [current_points.clone(), { "$max": [{ "$add": [current_points, points] }, 0] }] }
+    };
+    let user_name = match user_name {
+        Some(name) => Bson::Document(doc! { "$literal": name }),
+        None => Bson::String("$$REMOVE".to_string()),
+    };
+    vec![doc! {
+        "$set": {
+            "points": new_points,
+            "userName": { "$ifNull": ["$userName", user_name] },
+            "createdAt": { "$ifNull": ["$createdAt", DateTime::from_chrono(now)] },
+            "updatedAt": DateTime::from_chrono(now),
+        }
+    }]
+}
+
+// Commits the transaction, retrying the commit alone a few times if its result is unknown
+async fn commit_transaction(session: &mut ClientSession) -> MongoResult<()> {
+    let mut attempts = 1;
+    loop {
+        match session.commit_transaction().await {
+            Err(e)
+                if e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT)
+                    && attempts < MAX_TRANSACTION_ATTEMPTS =>
+            {
+                attempts += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Matches the catalog items within their active window
+fn active_item_filter(now: chrono::DateTime<Utc>) -> mongodb::bson::Document {
+    let now = DateTime::from_chrono(now);
+    doc! {
+        "$and": [
+            { "$or": [{ "startsAt": null }, { "startsAt": { "$lte": now } }] },
+            { "$or": [{ "endsAt": null }, { "endsAt": { "$gt": now } }] },
+        ]
+    }
+}
+
+// The activity documents older than about five weeks
+fn old_activity_filter(now: chrono::DateTime<Utc>) -> mongodb::bson::Document {
+    let about_five_weeks_ago = now - Duration::weeks(5);
+    doc! { "createdAt": { "$lt": DateTime::from_chrono(about_five_weeks_ago) } }
+}
+
+// Tells which lease was lost instead of the generic message of a custom error
+fn fenced<T>(result: MongoResult<T>) -> StoreResult<T> {
+    result.map_err(|e| match e.get_custom::<JobFence>() {
+        Some(fence) => format!("The lease of {} is lost", fence).into(),
+        None => e.into(),
+    })
+}
+
+fn is_duplicate_key_error(error: &Error) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    match error.kind.as_ref() {
+        ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY,
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => {
+            write_error.code == DUPLICATE_KEY
+        }
+        _ => false,
+    }
 }
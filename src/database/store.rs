@@ -0,0 +1,293 @@
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use serenity::async_trait;
+use std::collections::{BTreeMap, HashSet};
+
+use super::models::{
+    Activity, AttendanceReward, AttendanceStreak, CatalogItem, Exchange, ExchangeOutcome,
+    ExchangeStatus, FulfilmentResult, JobFence, JobState, LedgerEntry, LottoDraw,
+    LottoEntryOutcome, LottoGuess, LottoRound, LottoRoundSummary, LottoRules, PointsAdjustment,
+    PointsDrift, PointsReason, RankedUser, UserWallet, WalletAudit, WalletUpdate,
+};
+
+pub type StoreResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Everything the bot persists. `MongoDB` is the store of the bot and `MemoryStore` keeps
+// the same data in memory, so the commands and the jobs can run without a database.
+#[async_trait]
+pub trait Store: Send + Sync {
+    // Catalog
+
+    // Seeds the catalog with the tournament ticket when it is empty
+    async fn open_catalog(&self) -> StoreResult<bool>;
+
+    async fn get_catalog_item(&self, id: &str) -> StoreResult<Option<CatalogItem>>;
+
+    // Returns the catalog sorted by price, only the items in their active window if requested
+    async fn get_catalog_items(&self, active_only: bool) -> StoreResult<Vec<CatalogItem>>;
+
+    async fn set_catalog_item(&self, item: &CatalogItem) -> StoreResult<()>;
+
+    // Exchange
+
+    // Checks the catalog, decrements the stock, subtracts the points and adds the exchange record together
+    async fn add_exchange_record(&self, exchange: Exchange) -> StoreResult<ExchangeOutcome>;
+
+    // The latest exchanges of the user
+    async fn get_user_records(&self, dc_id: u64) -> StoreResult<Vec<Exchange>>;
+
+    // Returns the exchanges in the statuses, oldest first, all of them if the limit is 0
+    async fn get_exchanges_by_status(
+        &self,
+        statuses: &[ExchangeStatus],
+        limit: i64,
+    ) -> StoreResult<Vec<Exchange>>;
+
+    // Moves a single exchange to the new status if it is currently in one of the given statuses,
+    // returns the updated exchange or `None` if the transition is not allowed
+    async fn update_exchange_status(
+        &self,
+        id: ObjectId,
+        from: &[ExchangeStatus],
+        to: ExchangeStatus,
+        reject_reason: Option<String>,
+    ) -> StoreResult<Option<Exchange>>;
+
+    // Gives the points of a rejected exchange back to the user,
+    // returns `None` if the exchange is not rejected
+    async fn refund_exchange(&self, id: ObjectId) -> StoreResult<Option<Exchange>>;
+
+    // Moves the submitted exchanges to processing and returns the batch that was moved.
+    // Fails if the run no longer holds the lease of the fence.
+    async fn update_all_submitted_to_processing(
+        &self,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Vec<Exchange>>;
+
+    // Returns the exchanges requested between the dates, excluding the rejected ones
+    async fn get_exchanges_between(
+        &self,
+        from: chrono::DateTime<Utc>,
+        to: chrono::DateTime<Utc>,
+    ) -> StoreResult<Vec<Exchange>>;
+
+    // Records the delivery result of a processing exchange, completing it on success,
+    // returns the updated exchange or `None` if the exchange is not processing
+    async fn apply_fulfilment_result(
+        &self,
+        id: ObjectId,
+        result: &FulfilmentResult,
+    ) -> StoreResult<Option<Exchange>>;
+
+    // Users
+
+    async fn get_user_points(&self, user_id: &str) -> StoreResult<i32>;
+
+    // Applies the points within the maximum and records them in the points ledger.
+    // A spend is only applied if the balance covers it.
+    async fn adjust_user_points(
+        &self,
+        user_id: &str,
+        user_name: Option<&str>,
+        points: i32,
+        reason: PointsReason,
+        source_id: Option<String>,
+    ) -> StoreResult<PointsAdjustment>;
+
+    async fn add_ledger_entry(&self, entry: LedgerEntry) -> StoreResult<()>;
+
+    // Records the current balance as the opening entry for every user without ledger entries
+    async fn open_points_ledger(&self) -> StoreResult<u64>;
+
+    // Rebuilds the balance of the user from the points ledger and compares it with the stored one
+    async fn reconcile_user_points(&self, user_id: &str) -> StoreResult<PointsDrift>;
+
+    // Returns the users whose stored balance does not match the points ledger
+    async fn reconcile_all_points(&self) -> StoreResult<Vec<(String, PointsDrift)>>;
+
+    async fn get_user_streak(&self, user_id: &str) -> StoreResult<AttendanceStreak>;
+
+    // Records today's check-in, extends the attendance streak and credits the points of the
+    // activity and the bonus of the streak together. Returns None if the user has already
+    // checked in today (UTC+0).
+    async fn record_attendance(
+        &self,
+        activity: Activity,
+        user_name: Option<&str>,
+        streak_bonus: &BTreeMap<i32, i32>,
+    ) -> StoreResult<Option<AttendanceReward>>;
+
+    async fn get_user_wallet(&self, user_id: &str) -> StoreResult<Option<UserWallet>>;
+
+    // Registers the default wallet and records the change for the admins
+    async fn set_user_wallet(
+        &self,
+        user_id: &str,
+        user_name: &str,
+        address: &str,
+        verified: bool,
+        cooldown: Duration,
+    ) -> StoreResult<WalletUpdate>;
+
+    // Returns the latest wallet changes of the user
+    async fn get_wallet_audits(&self, user_id: &str, limit: i64) -> StoreResult<Vec<WalletAudit>>;
+
+    // Returns the users with the most points, ties are ordered by the user ID
+    async fn get_top_users(&self, limit: i64) -> StoreResult<Vec<RankedUser>>;
+
+    // Returns the 1-based rank of the user and their points, users with equal points share the same rank
+    async fn get_user_rank(&self, user_id: &str) -> StoreResult<Option<(u64, i32)>>;
+
+    // Activity
+
+    // Removes the activity documents older than about five weeks and returns how many
+    async fn clean_documents(&self) -> StoreResult<u64>;
+
+    // The number of documents `clean_documents` would delete
+    async fn count_old_documents(&self) -> StoreResult<u64>;
+
+    // Each of the following returns false if the daily limit of the activity is reached
+    async fn add_react_poll_activity(&self, new_activity: Activity) -> StoreResult<bool>;
+
+    async fn add_reaction_activity(&self, activity: Activity) -> StoreResult<bool>;
+
+    // Lotto
+
+    // Opens the lotto round of this week with a secret seed and returns it.
+    // Returns the existing round if it is already open.
+    async fn add_weekly_draw(&self) -> StoreResult<LottoDraw>;
+
+    // Moves the lotto documents stored with the calendar year to their ISO round
+    async fn open_lotto_rounds(&self) -> StoreResult<u64>;
+
+    // Returns the latest rules in effect at the time, or the original rules if none are stored
+    async fn get_lotto_rules(&self, at: chrono::DateTime<Utc>) -> StoreResult<LottoRules>;
+
+    async fn get_lotto_round(&self, round: LottoRound) -> StoreResult<Option<LottoDraw>>;
+
+    // Returns the rounds before the given one whose numbers are not drawn yet, oldest first
+    async fn get_undrawn_lotto_rounds(&self, before: LottoRound) -> StoreResult<Vec<LottoRound>>;
+
+    // Draws the winning numbers of a round from its committed seed alone, scores all
+    // of its guesses and settles its jackpot. It can be retried, and fails if the round has no seed.
+    // The draw and the jackpot fail if the run no longer holds the lease of the fence.
+    async fn close_lotto_round(
+        &self,
+        round: LottoRound,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Option<LottoDraw>>;
+
+    // Charges the fee and adds the guess together, so only the accepted entries are charged
+    async fn add_lotto_guess(
+        &self,
+        guess: LottoGuess,
+        fee: i32,
+        jackpot_contribution: i32,
+        max_entries: u64,
+    ) -> StoreResult<LottoEntryOutcome>;
+
+    // Returns the winning guesses of the round, only the ones with the DM flag if given
+    async fn get_lotto_guesses(
+        &self,
+        round: LottoRound,
+        dm_sent: Option<bool>,
+    ) -> StoreResult<Vec<LottoGuess>>;
+
+    // Credits the prize of a scored guess and marks it paid, so a guess is paid once
+    // however often the payout is retried. Returns None if it was already paid.
+    // Fails if the run no longer holds the lease of the fence.
+    async fn pay_lotto_prize(
+        &self,
+        id: ObjectId,
+        fence: Option<JobFence>,
+    ) -> StoreResult<Option<PointsAdjustment>>;
+
+    // Records a failed prize DM and returns the number of failed attempts of the guess
+    async fn add_lotto_dm_failure(&self, id: ObjectId, error: &str) -> StoreResult<i32>;
+
+    async fn update_dm_sent_flag(&self, id: ObjectId) -> StoreResult<()>;
+
+    // Returns a page of the drawn lotto rounds, newest first, and the number of drawn rounds
+    async fn get_lotto_draw_history(
+        &self,
+        page: u64,
+        page_size: u64,
+    ) -> StoreResult<(Vec<LottoRoundSummary>, u64)>;
+
+    // Returns a page of the user's lotto guesses, newest first, and the number of them
+    async fn get_user_lotto_history(
+        &self,
+        dc_id: u64,
+        page: u64,
+        page_size: u64,
+    ) -> StoreResult<(Vec<LottoGuess>, u64)>;
+
+    // The numbers the user saved to replay with `/lotto favorite`
+    async fn get_lotto_favorite(&self, user_id: &str) -> StoreResult<Option<Vec<i32>>>;
+
+    async fn set_lotto_favorite(&self, user_id: &str, numbers: &[i32]) -> StoreResult<()>;
+
+    async fn set_lotto_mention(&self, user_id: &str, enabled: bool) -> StoreResult<()>;
+
+    // Returns the users among the given ones who opted in to be mentioned in the lotto results
+    async fn get_lotto_mention_users(&self, dc_ids: &[u64]) -> StoreResult<HashSet<u64>>;
+
+    // Returns the guesses of the user in the round and the one before it
+    async fn get_user_lotto_guesses(
+        &self,
+        round: LottoRound,
+        dc_id: u64,
+    ) -> StoreResult<Vec<LottoGuess>>;
+
+    // Jobs
+
+    async fn get_job_state(&self, name: &str) -> StoreResult<Option<JobState>>;
+
+    async fn get_job_states(&self) -> StoreResult<Vec<JobState>>;
+
+    async fn set_job_paused(&self, name: &str, paused: bool) -> StoreResult<()>;
+
+    // Moves past a run skipped while the job is paused, so it is not caught up later
+    async fn skip_job_run(
+        &self,
+        name: &str,
+        scheduled_at: chrono::DateTime<Utc>,
+    ) -> StoreResult<()>;
+
+    async fn set_job_next_run(
+        &self,
+        name: &str,
+        next_run_at: chrono::DateTime<Utc>,
+    ) -> StoreResult<()>;
+
+    // Records a finished run of the job with the token of its lease, so a run whose lease was
+    // taken over cannot overwrite a newer one. Returns false when a newer run is already recorded.
+    async fn record_job_run(
+        &self,
+        name: &str,
+        token: i64,
+        scheduled_at: Option<chrono::DateTime<Utc>>,
+        result: &Result<String, String>,
+    ) -> StoreResult<bool>;
+
+    // Takes the lease of the job for the owner if it is free or expired. Returns the token of
+    // the lease, which grows with every acquisition, or None while it is held, even by the owner.
+    async fn acquire_job_lease(
+        &self,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> StoreResult<Option<i64>>;
+
+    // Extends the lease while the job runs. Returns false if the lease was lost.
+    async fn renew_job_lease(
+        &self,
+        name: &str,
+        owner: &str,
+        token: i64,
+        ttl: Duration,
+    ) -> StoreResult<bool>;
+
+    // Expires the lease so other owners can take it at once
+    async fn release_job_lease(&self, name: &str, owner: &str, token: i64) -> StoreResult<()>;
+}
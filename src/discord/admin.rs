@@ -0,0 +1,560 @@
+use bson::oid::ObjectId;
+use chrono::{Duration, NaiveDate};
+use serenity::{
+    model::channel::AttachmentType,
+    model::prelude::interaction::{
+        application_command::{
+            ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue,
+        },
+        InteractionResponseType, MessageFlags,
+    },
+    prelude::Context,
+};
+use std::borrow::Cow;
+use std::str::FromStr;
+use tracing::error;
+
+use super::embeds::build_exchange_queue_embed;
+use super::handler::Handler;
+use crate::database::models::{CatalogItem, Exchange, ExchangeStatus, FulfilmentResult};
+use crate::util::{build_exchange_csv, parse_fulfilment_results, send_exchange_dm};
+
+// The number of requests shown in the review queue
+const QUEUE_SIZE: i64 = 20;
+// The number of wallet changes shown for a user
+const WALLET_HISTORY_SIZE: i64 = 10;
+// The number of users listed with a points drift, so the reply fits in one message
+const DRIFT_LIST_SIZE: usize = 20;
+
+impl Handler {
+    pub async fn handle_exchange_admin(
+        &self,
+        ctx: Context,
+        command: ApplicationCommandInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // The command is hidden from non-admins, but the permissions can be overridden per guild
+        if !is_admin(&command) {
+            return respond(
+                &ctx,
+                &command,
+                "Only admins can review the exchange requests.",
+            )
+            .await;
+        }
+
+        let subcommand = match command.data.options.first() {
+            Some(subcommand) => subcommand,
+            None => return Ok(()),
+        };
+
+        if subcommand.name == "pending" {
+            let records = self
+                .db
+                .get_exchanges_by_status(
+                    &[ExchangeStatus::Submitted, ExchangeStatus::Processing],
+                    QUEUE_SIZE,
+                )
+                .await?;
+            let embed = build_exchange_queue_embed(&records, self.clock.now());
+
+            command
+                .create_interaction_response(&ctx.http, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|m| {
+                            m.flags(MessageFlags::EPHEMERAL).add_embed(embed)
+                        })
+                })
+                .await?;
+            return Ok(());
+        }
+
+        if subcommand.name == "export" {
+            return self.export_exchanges(&ctx, &command, subcommand).await;
+        }
+
+        if subcommand.name == "import" {
+            return self.import_fulfilment(&ctx, &command, subcommand).await;
+        }
+
+        if subcommand.name == "items" {
+            let content = self.list_catalog().await?;
+            return respond(&ctx, &command, &content).await;
+        }
+
+        if subcommand.name == "item" {
+            let content = self.set_catalog_item(subcommand).await?;
+            return respond(&ctx, &command, &content).await;
+        }
+
+        if subcommand.name == "wallet" {
+            let user_id = get_string_option(&subcommand.options, "user").unwrap_or_default();
+            let content = self.wallet_history(user_id).await?;
+            return respond(&ctx, &command, &content).await;
+        }
+
+        if subcommand.name == "points" {
+            let content = match get_string_option(&subcommand.options, "user") {
+                Some(user_id) => self.user_points_drift(user_id).await?,
+                None => self.points_drifts().await?,
+            };
+            return respond(&ctx, &command, &content).await;
+        }
+
+        let exchange_id = match get_string_option(&subcommand.options, "exchange_id")
+            .and_then(|id| ObjectId::from_str(id).ok())
+        {
+            Some(exchange_id) => exchange_id,
+            None => return respond(&ctx, &command, "Invalid exchange ID! Please try again.").await,
+        };
+
+        let content = match subcommand.name.as_str() {
+            "approve" => {
+                match self
+                    .db
+                    .update_exchange_status(
+                        exchange_id,
+                        &[ExchangeStatus::Submitted, ExchangeStatus::Processing],
+                        ExchangeStatus::Completed,
+                        None,
+                    )
+                    .await?
+                {
+                    Some(exchange) => {
+                        self.notify_exchange(&ctx, &exchange).await;
+                        format!("Exchange `{}` is approved and completed.", exchange_id)
+                    }
+                    None => format!("Exchange `{}` is not pending.", exchange_id),
+                }
+            }
+            "reject" => {
+                let reason = get_string_option(&subcommand.options, "reason")
+                    .unwrap_or_default()
+                    .to_string();
+                match self
+                    .db
+                    .update_exchange_status(
+                        exchange_id,
+                        &[ExchangeStatus::Submitted, ExchangeStatus::Processing],
+                        ExchangeStatus::Rejected,
+                        Some(reason),
+                    )
+                    .await?
+                {
+                    // The user is told about the rejection together with the refund
+                    Some(exchange) => {
+                        self.refund_exchange(&ctx, exchange_id, Some(&exchange))
+                            .await
+                    }
+                    None => format!("Exchange `{}` is not pending.", exchange_id),
+                }
+            }
+            "refund" => self.refund_exchange(&ctx, exchange_id, None).await,
+            _ => return Ok(()),
+        };
+
+        respond(&ctx, &command, &content).await
+    }
+
+    async fn export_exchanges(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+        subcommand: &CommandDataOption,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let parse_date = |name: &str| {
+            get_string_option(&subcommand.options, name)
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        };
+        let (from, to) = match (parse_date("from"), parse_date("to")) {
+            (Some(from), Some(to)) if from <= to => (from, to),
+            _ => {
+                return respond(
+                    ctx,
+                    command,
+                    "Invalid dates! Please use the YYYY-MM-DD format with `from` before `to`.",
+                )
+                .await
+            }
+        };
+
+        // The last day is included in the range
+        let records = self
+            .db
+            .get_exchanges_between(
+                from.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                (to + Duration::days(1))
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap()
+                    .and_utc(),
+            )
+            .await?;
+        let csv = build_exchange_csv(&records);
+        let filename = format!("exchanges-{}-{}.csv", from, to);
+
+        command
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|m| {
+                        m.content(format!(
+                            "📦 **{}** exchange request(s) from {} to {}.",
+                            records.len(),
+                            from,
+                            to
+                        ))
+                        .add_file(AttachmentType::Bytes {
+                            data: Cow::from(csv.into_bytes()),
+                            filename,
+                        })
+                        .flags(MessageFlags::EPHEMERAL)
+                    })
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn import_fulfilment(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+        subcommand: &CommandDataOption,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let attachment = subcommand
+            .options
+            .iter()
+            .find(|option| option.name == "results")
+            .and_then(|option| match &option.resolved {
+                Some(CommandDataOptionValue::Attachment(attachment)) => Some(attachment),
+                _ => None,
+            });
+        let attachment = match attachment {
+            Some(attachment) => attachment,
+            None => return respond(ctx, command, "Please attach the fulfilment results.").await,
+        };
+
+        // Downloading and applying the results may take longer than the interaction deadline
+        command
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|m| m.flags(MessageFlags::EPHEMERAL))
+            })
+            .await?;
+
+        let content = match attachment.download().await {
+            Ok(bytes) => match parse_fulfilment_results(&String::from_utf8_lossy(&bytes)) {
+                Ok(results) => self.apply_fulfilment_results(ctx, &results).await,
+                Err(e) => format!("Unable to read `{}`: {}", attachment.filename, e),
+            },
+            Err(e) => {
+                error!("Error downloading {}: {}", attachment.filename, e);
+                format!("Unable to download `{}`.", attachment.filename)
+            }
+        };
+
+        command
+            .edit_original_interaction_response(&ctx.http, |r| r.content(content))
+            .await?;
+
+        Ok(())
+    }
+
+    // Marks each exchange on its own so one bad row does not block the rest of the batch
+    async fn apply_fulfilment_results(
+        &self,
+        ctx: &Context,
+        results: &[FulfilmentResult],
+    ) -> String {
+        let mut completed = 0;
+        let mut failed = Vec::new();
+        let mut skipped = Vec::new();
+
+        for result in results {
+            let exchange_id = match ObjectId::from_str(&result.exchange_id) {
+                Ok(exchange_id) => exchange_id,
+                Err(_) => {
+                    skipped.push(result.exchange_id.clone());
+                    continue;
+                }
+            };
+
+            match self.db.apply_fulfilment_result(exchange_id, result).await {
+                Ok(Some(exchange)) if exchange.status == ExchangeStatus::Completed => {
+                    completed += 1;
+                    self.notify_exchange(ctx, &exchange).await;
+                }
+                Ok(Some(_)) => failed.push(result.exchange_id.clone()),
+                Ok(None) => skipped.push(result.exchange_id.clone()),
+                Err(e) => {
+                    error!(
+                        "Error applying fulfilment result for {}: {}",
+                        exchange_id, e
+                    );
+                    skipped.push(result.exchange_id.clone());
+                }
+            }
+        }
+
+        let mut content = format!(
+            "✅ **{}** completed, ❌ **{}** failed, ⏭️ **{}** skipped.",
+            completed,
+            failed.len(),
+            skipped.len()
+        );
+        if !failed.is_empty() {
+            content.push_str(&format!(
+                "\nFailed (still processing): `{}`",
+                failed.join("`, `")
+            ));
+        }
+        if !skipped.is_empty() {
+            content.push_str(&format!(
+                "\nSkipped (unknown ID or not processing): `{}`",
+                skipped.join("`, `")
+            ));
+        }
+        content
+    }
+
+    async fn list_catalog(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let items = self.db.get_catalog_items(false).await?;
+        if items.is_empty() {
+            return Ok("The catalog is empty.".to_string());
+        }
+
+        let now = self.clock.now();
+        let mut content = String::from("🛒 Catalog:");
+        for item in items {
+            let mut details = vec![format!("{} points", item.price)];
+            if let Some(limit) = item.weekly_limit {
+                details.push(format!("{} per week", limit));
+            }
+            if let Some(stock) = item.stock {
+                details.push(format!("{} in stock", stock));
+            }
+            if let Some(starts_at) = item.starts_at {
+                details.push(format!("from <t:{}:d>", starts_at.timestamp()));
+            }
+            if let Some(ends_at) = item.ends_at {
+                details.push(format!("until <t:{}:d>", ends_at.timestamp()));
+            }
+            content.push_str(&format!(
+                "\n{} `{}` **{}**: {}",
+                if item.is_active(now) { "🟢" } else { "⚪" },
+                item.id,
+                item.name,
+                details.join(", ")
+            ));
+        }
+        Ok(content)
+    }
+
+    async fn set_catalog_item(
+        &self,
+        subcommand: &CommandDataOption,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let get_integer_option = |name: &str| {
+            subcommand
+                .options
+                .iter()
+                .find(|option| option.name == name)
+                .and_then(|option| option.value.as_ref())
+                .and_then(|value| value.as_i64())
+        };
+        let parse_date = |name: &str| match get_string_option(&subcommand.options, name) {
+            Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(|date| Some(date.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+                .map_err(|_| ()),
+            None => Ok(None),
+        };
+
+        let (starts_at, ends_at) = match (parse_date("starts"), parse_date("ends")) {
+            (Ok(starts_at), Ok(ends_at)) => (starts_at, ends_at),
+            _ => return Ok("Invalid dates! Please use the YYYY-MM-DD format.".to_string()),
+        };
+        let price = match get_integer_option("price").and_then(|price| i32::try_from(price).ok()) {
+            Some(price) if price > 0 => price,
+            _ => return Ok("Invalid price! Please try again.".to_string()),
+        };
+
+        let item = CatalogItem {
+            id: get_string_option(&subcommand.options, "id")
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase(),
+            name: get_string_option(&subcommand.options, "name")
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            price,
+            weekly_limit: get_integer_option("weekly_limit"),
+            stock: get_integer_option("stock"),
+            starts_at,
+            ends_at,
+        };
+        if item.id.is_empty() || item.name.is_empty() {
+            return Ok("The ID and the name of the item are required.".to_string());
+        }
+
+        self.db.set_catalog_item(&item).await?;
+        Ok(format!(
+            "Item `{}` **{}** is saved for {} points.",
+            item.id, item.name, item.price
+        ))
+    }
+
+    async fn wallet_history(
+        &self,
+        user_id: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let audits = self
+            .db
+            .get_wallet_audits(user_id, WALLET_HISTORY_SIZE)
+            .await?;
+        if audits.is_empty() {
+            return Ok(format!("<@{}> has never registered a wallet.", user_id));
+        }
+
+        let mut content = format!("🔐 Wallet changes of <@{}>:", user_id);
+        for audit in audits {
+            content.push_str(&format!(
+                "\n<t:{}:f> {} → **{}**{}",
+                audit.created_at.timestamp(),
+                audit.previous_address.as_deref().unwrap_or("none"),
+                audit.address,
+                if audit.verified { " ✅" } else { "" }
+            ));
+        }
+        Ok(content)
+    }
+
+    async fn user_points_drift(
+        &self,
+        user_id: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let drift = self.db.reconcile_user_points(user_id).await?;
+        let mut content = format!(
+            "🧾 <@{}> has **{}** points, the points ledger adds up to **{}**.",
+            user_id, drift.stored_points, drift.ledger_points
+        );
+        if drift.drift() == 0 {
+            content.push_str(
+                "
+✅ The balance matches the points ledger.",
+            );
+        } else {
+            content.push_str(&format!(
+                "
+⚠️ The balance is off by **{:+}** points.",
+                drift.drift()
+            ));
+        }
+        Ok(content)
+    }
+
+    async fn points_drifts(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let drifts = self.db.reconcile_all_points().await?;
+        if drifts.is_empty() {
+            return Ok("✅ Every balance matches the points ledger.".to_string());
+        }
+
+        let mut content = format!(
+            "⚠️ **{}** user(s) have a balance that does not match the points ledger:",
+            drifts.len()
+        );
+        for (user_id, drift) in drifts.iter().take(DRIFT_LIST_SIZE) {
+            content.push_str(&format!(
+                "
+<@{}> has **{}** points, the ledger adds up to **{}** ({:+})",
+                user_id,
+                drift.stored_points,
+                drift.ledger_points,
+                drift.drift()
+            ));
+        }
+        if drifts.len() > DRIFT_LIST_SIZE {
+            content.push_str(&format!(
+                "
+… and {} more",
+                drifts.len() - DRIFT_LIST_SIZE
+            ));
+        }
+        Ok(content)
+    }
+
+    // Refunds a rejected exchange and returns the outcome for the admin. If the refund fails
+    // right after the rejection, the user is only told about the rejected exchange.
+    async fn refund_exchange(
+        &self,
+        ctx: &Context,
+        exchange_id: ObjectId,
+        rejected: Option<&Exchange>,
+    ) -> String {
+        match self.db.refund_exchange(exchange_id).await {
+            Ok(Some(exchange)) => {
+                self.notify_exchange(ctx, &exchange).await;
+                format!(
+                    "Exchange `{}` is rejected and {} points are refunded to <@{}>.",
+                    exchange_id,
+                    exchange.refund_points(),
+                    exchange.dc_id
+                )
+            }
+            Ok(None) => format!(
+                "Exchange `{}` is not rejected, so it cannot be refunded.",
+                exchange_id
+            ),
+            Err(e) => {
+                error!("Error refunding exchange {}: {}", exchange_id, e);
+                if let Some(rejected) = rejected {
+                    self.notify_exchange(ctx, rejected).await;
+                }
+                format!(
+                    "Exchange `{}` is rejected but the refund failed, please run `/exchange-admin refund` again.",
+                    exchange_id
+                )
+            }
+        }
+    }
+
+    // A closed DM should not fail the review, so the error is only logged
+    async fn notify_exchange(&self, ctx: &Context, exchange: &Exchange) {
+        if let Err(why) = send_exchange_dm(ctx.http.clone(), exchange).await {
+            error!(
+                "Error sending the exchange DM to {}: {:?}",
+                exchange.dc_id, why
+            );
+        }
+    }
+}
+
+// Checks whether the member who used the command is an administrator of the guild
+pub fn is_admin(command: &ApplicationCommandInteraction) -> bool {
+    command
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.administrator())
+}
+
+pub fn get_string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+pub async fn respond(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    content: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    command
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|m| m.content(content).flags(MessageFlags::EPHEMERAL))
+        })
+        .await?;
+
+    Ok(())
+}
@@ -1,26 +1,38 @@
-use super::embeds::{send_check_points, send_records_to_discord};
-use crate::database::models::{Activity, ActivityType, Exchange, ExchangeStatus, LottoGuess};
+use super::embeds::{
+    build_leaderboard_embed, build_rank_embed, send_check_points, send_records_to_discord,
+};
+use crate::database::models::{
+    Activity, ActivityType, AttendanceReward, CatalogItem, Exchange, ExchangeOutcome,
+    ExchangeStatus, LottoEntryOutcome, LottoGuess, LottoRound, LottoRules, PointsAdjustment,
+    PointsReason,
+};
 use crate::discord::embeds::send_message;
-use crate::util::{self, calculate_lotto_points, get_week_number, BAD_EMOJI};
+use crate::util::{self, BAD_EMOJI};
+use bson::oid::ObjectId;
 use chrono::Utc;
-use ethers::types::Address;
-use ethers::utils::to_checksum;
 use serenity::builder::CreateEmbed;
 use serenity::utils::Color;
-use std::str::FromStr;
 use tracing::error;
 
 use serenity::{
     model::channel::Message as DiscordMessage,
     model::prelude::interaction::{
-        application_command::ApplicationCommandInteraction, InteractionResponseType, MessageFlags,
+        application_command::ApplicationCommandInteraction, autocomplete::AutocompleteInteraction,
+        InteractionResponseType, MessageFlags,
     },
     model::prelude::{ChannelId, Reaction, ReactionType, UserId},
     model::user::User,
     prelude::{Context, Mentionable},
 };
 
+use super::admin::{get_string_option, respond};
 use super::handler::Handler;
+use super::slash::MAX_EXCHANGE_QUANTITY;
+
+// The points credited for the daily check-in
+const ATTEND_POINTS: i32 = 50;
+// The number of users shown in the leaderboard
+const LEADERBOARD_SIZE: i64 = 10;
 
 impl Handler {
     pub async fn handle_exchange(
@@ -50,7 +62,7 @@ impl Handler {
         }
 
         // Except Thursday for requesting the exchange
-        if util::is_thu() {
+        if util::is_thu(self.clock.now()) {
             let _ = command
                 .create_interaction_response(&ctx.http, |r| {
                     r.kind(InteractionResponseType::ChannelMessageWithSource)
@@ -69,27 +81,44 @@ impl Handler {
         };
 
         // Get the options from the command
-        let wallet_address_option = command
+        let item_option = get_string_option(&command.data.options, "item");
+        let wallet_address_option = get_string_option(&command.data.options, "wallet_address");
+        let quantity_option = command
             .data
             .options
-            .get(0)
-            .and_then(|o| o.value.as_ref())
-            .and_then(|v| v.as_str());
-        let number_of_tickets_option = command
-            .data
-            .options
-            .get(1)
+            .iter()
+            .find(|o| o.name == "quantity")
             .and_then(|o| o.value.as_ref())
             .and_then(|v| v.as_i64());
 
-        // Check if the wallet address is valid and convert it to checksum format
+        // Check if the item is in the catalog and can be exchanged now
+        let item = match item_option {
+            Some(item_id) => self.db.get_catalog_item(item_id).await?,
+            None => None,
+        };
+        let item = match item.filter(|item| item.is_active(self.clock.now())) {
+            Some(item) => item,
+            None => {
+                command
+                    .create_interaction_response(&ctx.http, |r| {
+                        r.kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|m| {
+                                m.content("This item is not available for exchange! Please choose one from the list.")
+                                    .flags(MessageFlags::EPHEMERAL)
+                            })
+                    })
+                    .await?;
+
+                return Ok(());
+            }
+        };
+
+        // Check if the wallet address is valid and convert it to checksum format,
+        // falling back to the registered wallet
         let wallet_address = match wallet_address_option {
-            Some(addr) => match Address::from_str(addr) {
-                Ok(address) => {
-                    let checksummed = to_checksum(&address, None);
-                    Some(checksummed)
-                }
-                Err(_) => {
+            Some(addr) => match util::checksum_address(addr) {
+                Some(checksummed) => checksummed,
+                None => {
                     command
                         .create_interaction_response(&ctx.http, |r| {
                             r.kind(InteractionResponseType::ChannelMessageWithSource)
@@ -103,13 +132,41 @@ impl Handler {
                     return Ok(());
                 }
             },
-            None => {
+            None => match self
+                .db
+                .get_user_wallet(&command.user.id.to_string())
+                .await?
+            {
+                Some(wallet) => wallet.address,
+                None => {
+                    command
+                        .create_interaction_response(&ctx.http, |r| {
+                            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                                .interaction_response_data(|m| {
+                                    m.content("No wallet address provided! Please register one with `/wallet set` or try again with the wallet address.")
+                                        .flags(MessageFlags::EPHEMERAL)
+                                })
+                        })
+                        .await?;
+
+                    return Ok(());
+                }
+            },
+        };
+
+        // Check if the quantity is valid
+        let quantity = match quantity_option {
+            Some(num) if (1..=MAX_EXCHANGE_QUANTITY).contains(&num) => num,
+            _ => {
                 command
                     .create_interaction_response(&ctx.http, |r| {
                         r.kind(InteractionResponseType::ChannelMessageWithSource)
                             .interaction_response_data(|m| {
-                                m.content("No wallet address provided! Please try again.")
-                                    .flags(MessageFlags::EPHEMERAL)
+                                m.content(format!(
+                                    "Invalid quantity! Please choose between 1 and {}.",
+                                    MAX_EXCHANGE_QUANTITY
+                                ))
+                                .flags(MessageFlags::EPHEMERAL)
                             })
                     })
                     .await?;
@@ -118,75 +175,97 @@ impl Handler {
             }
         };
 
-        // Check if the number of tickets is valid
-        let number_of_tickets = match number_of_tickets_option {
-            Some(num) => num,
-            None => {
+        // Create an Exchange record, the points are set from the catalog price
+        let now = self.clock.now();
+        let exchange = Exchange {
+            id: Some(ObjectId::new()),
+            dc_id: command.user.id.into(),
+            dc_username: command.user.name.to_string(),
+            wallet_address: Some(wallet_address.clone()),
+            item: item.id.clone(),
+            quantity,
+            status: ExchangeStatus::Submitted,
+            created_at: now,
+            updated_at: now,
+            ..Default::default()
+        };
+
+        // Check the catalog, subtract the required points and add the exchange record to the database together
+        let rejection = match self.db.add_exchange_record(exchange).await {
+            Ok(ExchangeOutcome::Accepted(adjustment)) => {
+                return self
+                    .acknowledge_exchange(
+                        &ctx,
+                        &command,
+                        username,
+                        &item,
+                        quantity,
+                        &wallet_address,
+                        adjustment,
+                    )
+                    .await;
+            }
+            Ok(ExchangeOutcome::NotEnoughPoints) => {
+                "Sorry! You do not have enough points to exchange. Try to earn more points! 🏋️‍♂️💪🏋️‍♀️"
+                    .to_string()
+            }
+            Ok(ExchangeOutcome::Unavailable) => {
+                "Sorry! This item is no longer available for exchange.".to_string()
+            }
+            Ok(ExchangeOutcome::OutOfStock) => format!(
+                "Sorry! There are not enough **{}** left in stock.",
+                item.name
+            ),
+            Ok(ExchangeOutcome::LimitReached(remaining)) => format!(
+                "Sorry! You can exchange only **{}** more **{}** this week.",
+                remaining, item.name
+            ),
+            Err(why) => {
                 command
                     .create_interaction_response(&ctx.http, |r| {
                         r.kind(InteractionResponseType::ChannelMessageWithSource)
                             .interaction_response_data(|m| {
-                                m.content("No number of tickets provided! Please try again.")
+                                m.content("Sorry! We could not process your exchange request and no points were subtracted. Please try again later.")
+                                    .flags(MessageFlags::EPHEMERAL)
                             })
                     })
                     .await?;
 
-                return Ok(());
+                return Err(why);
             }
         };
 
-        // Check if the user has enough points
-        let required_points = number_of_tickets as i32 * 1000;
-
-        let user_points = self
-            .db
-            .get_user_points(&command.user.id.to_string())
-            .await
-            .unwrap_or_default();
-        if user_points < required_points {
-            command
-                .create_interaction_response(&ctx.http, |r| {
-                    r.kind(InteractionResponseType::ChannelMessageWithSource)
-                        .interaction_response_data(|m| {
-                            m.content("Sorry! You do not have enough points to exchange. Try to earn more points! 🏋️‍♂️💪🏋️‍♀️")
-                                .flags(MessageFlags::EPHEMERAL)
-                        })
-                })
-                .await?;
-
-            return Ok(());
-        }
-
-        // Subtract the required points from the user's points
-        self.db
-            .adjust_user_points(&command.user.id.to_string(), None, -required_points)
+        command
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|m| {
+                        m.content(rejection).flags(MessageFlags::EPHEMERAL)
+                    })
+            })
             .await?;
 
-        const ITEM_TICKET: &str = "ticket";
-        // Create an Exchange record
-        let exchange = Exchange {
-            id: None,
-            dc_id: command.user.id.into(),
-            dc_username: command.user.name.to_string(),
-            wallet_address: wallet_address.clone(), // If it can be `None` and it's an error case
-            item: ITEM_TICKET.to_string(),
-            quantity: number_of_tickets,
-            status: ExchangeStatus::Submitted,
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
-        };
-
-        // Add the exchange record to the database
-        if let Err(why) = self.db.add_exchange_record(exchange).await {
-            error!("Error adding exchange record: {}", why);
-        }
+        Ok(())
+    }
 
+    #[allow(clippy::too_many_arguments)]
+    async fn acknowledge_exchange(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+        username: &str,
+        item: &CatalogItem,
+        quantity: i64,
+        wallet_address: &str,
+        adjustment: PointsAdjustment,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         // Send the hidden acknowledge message
         let content = format!(
-            "Hello {}!👋🏻 \nWe have already received your request of exchanging the Discord points into **{} Tournament ticket(s)** from the wallet address **{}**.\nOnce your request is submitted, the points are subtracted immediately, and we will send you the Tournament ticket(s) on the coming **Thursday**!🤩 \nPlease check your Tournament page on Thursday.\nFor any inquiries, please contact the Discord Admin.🙌🏻",
+            "Hello {}!👋🏻 \nWe have already received your request of exchanging the Discord points into **{} {}(s)** from the wallet address **{}**.\nOnce your request is submitted, the points are subtracted immediately, and we will send you the item(s) on the coming **Thursday**!🤩 \nYour current remaining points is **{}**.\nFor any inquiries, please contact the Discord Admin.🙌🏻",
             username,
-            number_of_tickets,
-            wallet_address.unwrap()
+            quantity,
+            item.name,
+            wallet_address,
+            adjustment.balance
         );
         let _ = command
             .create_interaction_response(&ctx.http, |r| {
@@ -203,10 +282,11 @@ impl Handler {
             .say(
                 &ctx.http,
                 format!(
-                    "🥳 <@{}> just exchanged {} points to {} Tournament ticket(s)! 🎟️",
+                    "🥳 <@{}> just exchanged {} points to {} {}(s)! 🎁",
                     command.user.id, // Make sure to use the user's ID
-                    number_of_tickets * 1000,
-                    number_of_tickets
+                    -adjustment.applied,
+                    quantity,
+                    item.name
                 ),
             )
             .await
@@ -217,6 +297,50 @@ impl Handler {
         Ok(())
     }
 
+    // Suggests the active catalog items matching what the user typed
+    pub async fn handle_exchange_autocomplete(
+        &self,
+        ctx: Context,
+        autocomplete: AutocompleteInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let typed = autocomplete
+            .data
+            .options
+            .iter()
+            .find(|option| option.focused)
+            .and_then(|option| option.value.as_ref())
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let items = self.db.get_catalog_items(true).await?;
+        autocomplete
+            .create_autocomplete_response(&ctx.http, |r| {
+                // Discord shows at most 25 choices
+                for item in items
+                    .iter()
+                    .filter(|item| {
+                        item.id.to_lowercase().contains(&typed)
+                            || item.name.to_lowercase().contains(&typed)
+                    })
+                    .take(25)
+                {
+                    let stock = match item.stock {
+                        Some(stock) => format!(" ({} left)", stock),
+                        None => String::new(),
+                    };
+                    r.add_string_choice(
+                        format!("{} - {} points{}", item.name, item.price, stock),
+                        &item.id,
+                    );
+                }
+                r
+            })
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn handle_lotto(
         &self,
         ctx: Context,
@@ -246,150 +370,255 @@ impl Handler {
             None => &command.user.name,
         };
 
-        // Check if the user has enough points
-        const FEE_POINTS: i32 = 200;
-        let user_points = self
-            .db
-            .get_user_points(&command.user.id.to_string())
-            .await
-            .unwrap_or_default();
-        if user_points < FEE_POINTS {
-            command
-            .create_interaction_response(&ctx.http, |r| {
-                r.kind(InteractionResponseType::ChannelMessageWithSource)
-                    .interaction_response_data(|m| {
-                        m.content("Sorry! You do not have sufficient points to cover the lottery fee. Try to earn more points! 🏋️‍♂️💪🏋️‍♀️")
-                            .flags(MessageFlags::EPHEMERAL)
+        // Make sure the round of this week is open, so its commitment exists before any guess
+        let round = match self.db.add_weekly_draw().await {
+            Ok(round) => round,
+            Err(e) => {
+                error!("Error opening the lotto round: {}", e);
+                command
+                    .create_interaction_response(&ctx.http, |r| {
+                        r.kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|m| {
+                                m.content("Sorry! The lotto is not available right now and no points were subtracted. Please try again later.")
+                                    .flags(MessageFlags::EPHEMERAL)
+                            })
                     })
-            })
-            .await?;
-
-            return Ok(());
-        }
-
-        let first_number = command
-            .data
-            .options
-            .get(0)
-            .and_then(|o| o.value.as_ref())
-            .and_then(|v| v.as_i64())
-            .unwrap_or(0) as i32;
-        let second_number = command
-            .data
-            .options
-            .get(1)
-            .and_then(|o| o.value.as_ref())
-            .and_then(|v| v.as_i64())
-            .unwrap_or(0) as i32;
-        let third_number = command
-            .data
-            .options
-            .get(2)
-            .and_then(|o| o.value.as_ref())
-            .and_then(|v| v.as_i64())
-            .unwrap_or(0) as i32;
-        let fourth_number = command
-            .data
-            .options
-            .get(3)
-            .and_then(|o| o.value.as_ref())
-            .and_then(|v| v.as_i64())
-            .unwrap_or(0) as i32;
-
-        let user_numbers = vec![first_number, second_number, third_number, fourth_number];
-
-        // Fetch current week number
-        let (year, current_week) = get_week_number();
-
-        // Attempt to retrieve the draw numbers from the database
-        let draw_numbers_result = self.db.get_lotto_draw(year, current_week).await;
+                    .await?;
 
-        // Unwrap draw numbers or log error and return
-        let draw_numbers = match draw_numbers_result {
-            Ok(numbers) => numbers,
-            Err(e) => {
-                error!("Error fetching lotto draw numbers: {}", e);
-                return Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>);
-                // Return the error, stopping the function execution
+                return Err(e);
             }
         };
 
-        // Calculate matching numbers and corresponding reward points
-        let (matches, reward_points) = calculate_lotto_points(&user_numbers, &draw_numbers);
-
-        // Build a LottoGuess object with calculated data
-        let guess = LottoGuess {
-            id: None,
-            dc_id: command.user.id.into(),
-            dc_username: Some(user_name.to_string()),
-            numbers: user_numbers,
-            year,
-            week_number: current_week,
-            matched_count: Some(matches.try_into().unwrap()), // Convert matches to i32
-            is_any_matched: Some(matches > 0), // Boolean flag indicating any match found
-            points: Some(reward_points),       // Reward points
-            dm_sent: Some(false),              // Flag indicating if a direct message was sent
-            created_at: Utc::now(),            // Current timestamp
-            updated_at: Utc::now(),
+        // The numbers follow the rules of the round, the fee and the limit the current rules
+        let round_rules = round.rules();
+        let dc_id: u64 = command.user.id.into();
+        let (subcommand, options) = match command.data.options.first() {
+            Some(subcommand) => (subcommand.name.as_str(), subcommand.options.as_slice()),
+            None => ("pick", command.data.options.as_slice()),
         };
 
-        // Try to add the lotto guess to the database
-        match self.db.add_lotto_guess(guess).await {
-            Ok(true) => {
-                // If we reach here, it means the lotto guess was successfully added to the database.
-                let content = format!(
-            "You have chosen {}, {}, {}, {} for the lotto 🎰\nThe results will be revealed on the upcoming Monday at 03:00 (UTC+0) 😎\nGood luck! 🍀",
-            first_number, second_number, third_number, fourth_number
-        );
-
-                let _ = command
-                    .create_interaction_response(&ctx.http, |r| {
-                        r.kind(InteractionResponseType::ChannelMessageWithSource)
-                            .interaction_response_data(|m| {
-                                m.content(content).flags(MessageFlags::EPHEMERAL)
-                            })
+        // Every ticket is a set of numbers, quick-pick may buy several tickets at once
+        let tickets: Vec<Vec<i32>> = match subcommand {
+            "quick-pick" => {
+                let count = options
+                    .iter()
+                    .find(|o| o.name == "tickets")
+                    .and_then(|o| o.value.as_ref())
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(1)
+                    .max(1);
+                (0..count)
+                    .map(|_| util::quick_pick_lotto_numbers(round_rules.digits))
+                    .collect()
+            }
+            "favorite" => match self.db.get_lotto_favorite(&dc_id.to_string()).await? {
+                Some(numbers) if numbers.len() == round_rules.digits as usize => vec![numbers],
+                Some(_) => {
+                    respond(&ctx, &command, &format!("Your favorite numbers do not fit this week's lotto of {} numbers 😅 Please save a new set with `/lotto pick`.", round_rules.digits)).await?;
+                    return Ok(());
+                }
+                None => {
+                    respond(&ctx, &command, "You have no favorite numbers yet 🤔 Choose them with `/lotto pick` and set `save_favorite` to save them.").await?;
+                    return Ok(());
+                }
+            },
+            _ => {
+                let user_numbers: Option<Vec<i32>> = (1..=round_rules.digits)
+                    .map(|position| {
+                        options
+                            .iter()
+                            .find(|o| o.name == util::lotto_option_name(position))
+                            .and_then(|o| o.value.as_ref())
+                            .and_then(|v| v.as_i64())
+                            .filter(|number| (0..=9).contains(number))
+                            .map(|number| number as i32)
                     })
-                    .await;
+                    .collect();
+                match user_numbers {
+                    Some(numbers) => vec![numbers],
+                    None => {
+                        respond(&ctx, &command, &format!(
+                            "Please choose {} single-digit numbers (i.e., between 0-9) for this week's lotto.",
+                            round_rules.digits
+                        ))
+                        .await?;
 
-                // Send a public message to the channel
-                if let Err(why) = command
-                    .channel_id
-                    .say(
-                        &ctx.http,
-                        format!(
-                            "🎲 The lotto is heating up! <@{}> is in - will you be next? Check out `/lotto-guideline` and participate! 💰",
-                            command.user.id, // Make sure to use the user's ID
-                        ),
-                    )
-                    .await
-                {
-                    error!("Error sending message: {}", why);
+                        return Ok(());
+                    }
                 }
             }
-            Ok(false) => {
-                // User has already made 3 guesses this week.
-                let content = "You have already made 5 guesses this week 😩 Please wait until next week to play again 💪🏻";
+        };
 
-                let _ = command
-                    .create_interaction_response(&ctx.http, |r| {
-                        r.kind(InteractionResponseType::ChannelMessageWithSource)
-                            .interaction_response_data(|m| {
-                                m.content(content).flags(MessageFlags::EPHEMERAL)
-                            })
-                    })
-                    .await;
+        let save_favorite = options
+            .iter()
+            .find(|o| o.name == "save_favorite")
+            .and_then(|o| o.value.as_ref())
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let now = self.clock.now();
+        let rules = self.db.get_lotto_rules(now).await?;
+        let fee = rules.fee_at(now);
+        let max_entries = rules.entries_per_week_at(now);
+
+        // Every ticket is charged on its own, so the weekly limit stops the remaining ones
+        let requested = tickets.len();
+        let mut accepted: Vec<Vec<i32>> = Vec::new();
+        let mut charged = 0;
+        let mut balance = 0;
+        let mut stopped = None;
+        for numbers in tickets {
+            // Build a LottoGuess object, it is scored once the round is drawn
+            let guess = LottoGuess {
+                id: Some(ObjectId::new()),
+                dc_id,
+                dc_username: Some(user_name.to_string()),
+                numbers: numbers.clone(),
+                round: round.round,
+                matched_count: None,
+                is_any_matched: None,
+                points: None,
+                paid: Some(false),
+                dm_sent: Some(false), // Flag indicating if a direct message was sent
+                dm_attempts: None,
+                created_at: now, // Current timestamp
+                updated_at: now,
+            };
+
+            // Try to add the lotto guess to the database and charge the fee
+            match self
+                .db
+                .add_lotto_guess(guess, fee, rules.jackpot_contribution(fee), max_entries)
+                .await
+            {
+                Ok(LottoEntryOutcome::Accepted(adjustment)) => {
+                    accepted.push(numbers);
+                    charged -= adjustment.applied;
+                    balance = adjustment.balance;
+                }
+                Ok(outcome) => {
+                    stopped = Some(outcome);
+                    break;
+                }
+                Err(e) if accepted.is_empty() => {
+                    // An error occurred while adding the lotto guess, so nothing was charged.
+                    error!("Error adding lotto guess to the database: {}", e);
+                    respond(&ctx, &command, "Sorry! We could not register your lotto entry and no points were subtracted. Please try again later.").await?;
+
+                    return Err(e);
+                }
+                Err(e) => {
+                    // The tickets bought so far stay, the user is told how many went through
+                    error!("Error adding lotto guess to the database: {}", e);
+                    break;
+                }
             }
-            Err(e) => {
-                // An error occurred while adding the lotto guess to the database.
-                error!("Error adding lotto guess to the database: {}", e);
+        }
+
+        if accepted.is_empty() {
+            let content = match stopped {
+                Some(LottoEntryOutcome::NotEnoughPoints) => "Sorry! You do not have sufficient points to cover the lottery fee. Try to earn more points! 🏋️‍♂️💪🏋️‍♀️".to_string(),
+                Some(LottoEntryOutcome::RoundClosed) => "Sorry! The numbers of this round were just drawn, no points were subtracted. Please try again in the new round 🎰".to_string(),
+                // User has already made the maximum guesses this week, nothing is charged.
+                _ => format!("You have already made {} guesses this week 😩 Please wait until next week to play again 💪🏻", max_entries),
+            };
+            respond(&ctx, &command, &content).await?;
+
+            return Ok(());
+        }
+
+        if save_favorite {
+            if let Err(e) = self
+                .db
+                .set_lotto_favorite(&dc_id.to_string(), &accepted[0])
+                .await
+            {
+                error!("Error saving the favorite lotto numbers: {}", e);
             }
         }
 
+        let chosen_numbers = accepted
+            .iter()
+            .map(|numbers| {
+                numbers
+                    .iter()
+                    .map(|n| n.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        let mut content = format!(
+            "You have chosen the following numbers for the lotto 🎰\n{}\n**{}** points were charged for {} entr{}, your remaining points is **{}**.",
+            chosen_numbers,
+            charged,
+            accepted.len(),
+            if accepted.len() == 1 { "y" } else { "ies" },
+            balance
+        );
+        if accepted.len() < requested {
+            let reason = match stopped {
+                Some(LottoEntryOutcome::NotEnoughPoints) => "you ran out of points",
+                Some(LottoEntryOutcome::LimitReached) => "you reached the weekly limit",
+                Some(LottoEntryOutcome::RoundClosed) => "the round was drawn",
+                _ => "of an error, please try again later",
+            };
+            content += &format!(
+                "\nOnly {} of {} tickets were bought because {}.",
+                accepted.len(),
+                requested,
+                reason
+            );
+        }
+        if save_favorite {
+            content +=
+                "\nThese numbers are saved as your favorite, replay them with `/lotto favorite` ⭐";
+        }
+        content += "\nThe results will be revealed on the upcoming Monday at 03:00 (UTC+0) 😎\nGood luck! 🍀";
+        let _ = respond(&ctx, &command, &content).await;
+
+        // Send a public message to the channel
+        if let Err(why) = command
+            .channel_id
+            .say(
+                &ctx.http,
+                format!(
+                    "🎲 The lotto is heating up! <@{}> is in - will you be next? Check out `/lotto-guideline` and participate! 💰",
+                    command.user.id, // Make sure to use the user's ID
+                ),
+            )
+            .await
+        {
+            error!("Error sending message: {}", why);
+        }
+
+        Ok(())
+    }
+
+    pub async fn handle_lotto_mention(
+        &self,
+        ctx: Context,
+        command: ApplicationCommandInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let enabled = command
+            .data
+            .options
+            .iter()
+            .find(|o| o.name == "enabled")
+            .and_then(|o| o.value.as_ref())
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
         self.db
-            .adjust_user_points(&command.user.id.to_string(), None, -FEE_POINTS)
+            .set_lotto_mention(&command.user.id.to_string(), enabled)
             .await?;
 
-        Ok(()) // Continue the function despite the outcome
+        let content = if enabled {
+            "You will be mentioned in the lotto results when you win 🎉"
+        } else {
+            "You will not be mentioned in the lotto results, your winning tickets are only counted 🤫"
+        };
+        respond(&ctx, &command, content).await
     }
 
     pub async fn handle_lotto_guideline(
@@ -397,39 +626,16 @@ impl Handler {
         ctx: Context,
         command: ApplicationCommandInteraction,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let lotto_channel_id = self.config.lotto_channel;
-        let lotto_channel = ChannelId(lotto_channel_id);
-        let content = format!(
-            "**Welcome to PlayDapp Weekly Lotto!~**:partying_face: :slot_machine: \
-            \n\n*How to join?*🤩 \
-            \n1. Go to <#{}> channel. \
-            \n2. Type **\"/lotto\"** and enter your 4 choices of single-digit numbers (i.e., between 0-9), e.g., '1', '5', '4', '7'. \
-            \n3. Once you successfully join the lotto, a confirmation message will be displayed!📨 \
-            \n4. Type **\"/checklotto\"** to check your lotto participation status and chosen numbers for the current and previous week. \
-            \n\n*Rules*🧑🏻‍🏫 \
-            \n- Participants need to choose 4 single-digit numbers (i.e., between 0-9). \
-            \n- Both the **integer values** and **position** should match with the winning lotto numbers to win. \
-            \n*Example*: If the winning number is '0', '6', '0', '6'. \
-            \n If Mary chose '1', '0', '6', '9' —> 0 matching number \
-            \n If Sally chose  '1', '3', '4', '6' —> 1 matching number \
-            \n If Tom chose '6', '0', '0', '6' —> 2 matching numbers \
-            \n If David chose '2', '6', '0', '6' —> 3 matching numbers \
-            \n If Corrie chose '0', '6', '0', '6' —> 4 matching numbers \
-            \n\n*Prize*🏆 \
-            \n 0 matching number: 0 point \
-            \n 1 matching number: 400 points \
-            \n 2 matching numbers: 1,000 points \
-            \n 3 matching numbers: 5,000 points + Achievement Badges (Level 1) \
-            \n 4 matching numbers: 100,000 points + Achievement Badges (Level 2) \
-            \nWinners will be notified by DM. 📩 \
-            \n\n*Participation guidelines*💰 \
-            \n- **Free of charge from 31st Jul - 28th Aug at 3:00 (UTC+0)**; maximum 3 times of participation per week. \
-            \n- After the free period, the participation fee is 200 points; maximum 5 times of participation per week. \
-            \n\n*When will the Weekly Lotto open?*⏰ \
-            \n- The entry period is **Monday 00:00 - Sun 23:59 (UTC+0)**. \
-            \n- The result of the previous week will be announced on **every Monday 03:00 (UTC+0)**",
-            lotto_channel
-        );
+        let lotto_channel = ChannelId(self.config.lotto_channel);
+
+        // The digits and the prizes of this week's round, the fee and the limits of today
+        let now = self.clock.now();
+        let rules = self.db.get_lotto_rules(now).await?;
+        let round_rules = match self.db.get_lotto_round(LottoRound::at(now)).await? {
+            Some(round) => round.rules(),
+            None => rules.clone(),
+        };
+        let content = lotto_guideline_content(lotto_channel, &round_rules, &rules, now);
 
         command
             .create_interaction_response(&ctx.http, |r| {
@@ -448,6 +654,13 @@ impl Handler {
         ctx: Context,
         command: ApplicationCommandInteraction,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let streak_bonus = self
+            .config
+            .streak_bonus
+            .iter()
+            .map(|(days, bonus)| format!("{} days: {} points", days, bonus))
+            .collect::<Vec<String>>()
+            .join(", ");
         let content = format!(
             "**Here is an introduction of the Discord Bot beta service** :hugging: \
             \n1. **Discord bot commands** :speaking_head: \
@@ -456,11 +669,12 @@ impl Handler {
             \n    b. `!cp` - Check your current accumulated points. \
             \n    c. `!rank` - Check the Cumulative Points TOP 10 Leaderboard. \
             \n    d. `!myrank` - Check your point ranking. \
-            \n    e. `/exchange` - Exchange your 1000 Discord points into 1 Tournament ticket. \
+            \n    e. `/exchange` - Exchange your Discord points for the items of the catalog, the price of each item is shown when you choose it. \
             \n    f. `!cr` - Check your points exchange record. \
             \n\n2. **How to gain points?** :mermaid_tone1: \
             \n    a. **Check-in Attendance** :man_raising_hand_tone1: \
             \n   - By typing `!attend`, you can earn 50 points per day for daily attendance. The attendance points per day reset at 00:00 (UTC+0). \
+            \n   - Check in on consecutive days to build your streak and earn bonus points ({}). \
             \n  b. **Giving / Receiving reaction by emoticons** :thumbsup_tone1: \
             \n   - Leaving an emoticon reaction: 3 points (max 5 times). \
             \n   - Receiving an emoticon reaction: 10 points (max 10 times). \
@@ -471,7 +685,8 @@ impl Handler {
             \n- As this is a beta service, points may be initialized during the official points service launch. \
             \n- Points will be used in various ways, such as future events. \
             \n\nWe will continue to upgrade Discord functions through this beta service. Our future goal is to provide a complete official service by introducing levels-for-point functions and mini-games to strengthen our community. :people_hugging: :people_hugging: \
-            \n\nThank you for your continuous support. We will work hard to provide a better gaming environment for you all! :sparkling_heart:"
+            \n\nThank you for your continuous support. We will work hard to provide a better gaming environment for you all! :sparkling_heart:",
+            streak_bonus
         );
 
         command
@@ -495,25 +710,38 @@ impl Handler {
         ctx: Context,
         command: ApplicationCommandInteraction,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Fetch current week number
-        let (year, current_week) = get_week_number();
+        // The round of the current week
+        let round = LottoRound::at(self.clock.now());
         // Get the Discord user ID
         let dc_id = command.user.id.into();
 
         // Fetch the user's lotto guesses
         let lotto_guesses = self
             .db
-            .get_user_lotto_guesses(year, current_week, dc_id)
+            .get_user_lotto_guesses(round, dc_id)
             .await
             .map_err(|e| {
                 error!("Error fetching lotto guesses: {}", e);
-                Box::new(e)
+                e
             })?;
 
+        // The top prize with the jackpot of this week's round
+        let pot = match self.db.get_lotto_round(round).await {
+            Ok(Some(round)) => Some(round.jackpot_pool()),
+            Ok(None) => None,
+            Err(e) => {
+                error!("Error fetching the lotto round: {}", e);
+                None
+            }
+        };
+
         // If the user hasn't made any guesses yet, send a reminder to participate
         if lotto_guesses.is_empty() {
             let lotto_channel = ChannelId(self.config.lotto_channel);
-            let reminder_content = format!("Sorry, you haven’t joined the Weekly Lotto this week yet :frowning2:\nType **“/lotto”** in <#{}> channel to try your luck! 🍀", lotto_channel);
+            let mut reminder_content = format!("Sorry, you haven’t joined the Weekly Lotto this week yet :frowning2:\nType **“/lotto”** in <#{}> channel to try your luck! 🍀", lotto_channel);
+            if let Some(pot) = pot {
+                reminder_content += &format!("\n💰 This week's jackpot is **{}** points!", pot);
+            }
 
             command
                 .create_interaction_response(&ctx.http, |r| {
@@ -529,9 +757,7 @@ impl Handler {
 
         // If the user has made guesses, construct an embed with the details of their guesses
         let user = &command.user;
-        let description = format!(
-        "Thank you for joining the Weekly Lotto! 🎰\n🤗 Below is your participation status for the first **8** (if fewer, all) lottos of the current and previous week:"
-    );
+        let description = "Thank you for joining the Weekly Lotto! 🎰\n🤗 Below is your participation status for the first **8** (if fewer, all) lottos of the current and previous week:";
 
         let thumbnail = user.face();
         let footer_text = format!("Given to {}", user.name);
@@ -542,13 +768,17 @@ impl Handler {
             .color(Color::new(0x00FA9A))
             .thumbnail(thumbnail.clone())
             .footer(|f| f.text(footer_text).icon_url(thumbnail))
-            .timestamp(chrono::Utc::now().to_rfc3339());
+            .timestamp(self.clock.now().to_rfc3339());
+
+        if let Some(pot) = pot {
+            embed.field("💰 Jackpot", format!("{} points", pot), false);
+        }
 
         // Add a field for each guess
         for guess in lotto_guesses {
             embed
                 .field("Chosen Numbers", format!("{:?}", guess.numbers), true)
-                .field("Week", format!("{:?}", guess.week_number), true)
+                .field("Week", format!("{:?}", guess.round.week), true)
                 .field(
                     "Time (UTC)",
                     guess.created_at.format("%Y-%m-%d %H:%M"),
@@ -631,7 +861,15 @@ impl Handler {
         }
 
         // If the user has records, send these records to the attendance channel on Discord.
-        send_records_to_discord(&records, ctx, msg.channel_id, user, user_points).await;
+        send_records_to_discord(
+            &records,
+            ctx,
+            msg.channel_id,
+            user,
+            user_points,
+            self.clock.now(),
+        )
+        .await;
 
         Ok(())
     }
@@ -690,11 +928,213 @@ impl Handler {
 
         // If the message was in the attendance channel,
         // we send the user's points information to the channel.
-        send_check_points(ctx, msg.channel_id, user, user_points).await;
+        let streak = self
+            .db
+            .get_user_streak(&msg.author.id.to_string())
+            .await
+            .unwrap_or_default();
+        send_check_points(
+            ctx,
+            msg.channel_id,
+            user,
+            user_points,
+            streak,
+            self.clock.now(),
+        )
+        .await;
+
+        Ok(())
+    }
+
+    // This function is responsible for handling the leaderboard and personal ranking commands.
+    pub async fn handle_rank_command(
+        &self,
+        msg: &DiscordMessage,
+        ctx: &Context,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // If the message content is not a ranking command, we ignore it and return early.
+        if msg.content != "!rank" && msg.content != "!myrank" {
+            return Ok(());
+        }
+
+        // If the guild ID from the message doesn't match the configured guild,
+        // we don't process the command and return early.
+        let guild: u64 = self.config.discord_guild;
+        if msg.guild_id.unwrap_or_default().0 != guild {
+            return Ok(());
+        }
+
+        // Check if the message was sent in the attendance channel.
+        // If not, we reply with a message directing the user to the attendance channel.
+        let attendance_channel = ChannelId(self.config.attendance_channel);
+        if msg.channel_id != attendance_channel {
+            msg.reply(
+                &ctx.http,
+                format!(
+                    "{} Please go to the <#{}> channel for Daily Attendance and Points Checking.",
+                    msg.author.mention(),
+                    attendance_channel
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let embed = if msg.content == "!rank" {
+            let users = self.db.get_top_users(LEADERBOARD_SIZE).await?;
+            build_leaderboard_embed(&users, self.clock.now())
+        } else {
+            let rank = self.db.get_user_rank(&msg.author.id.to_string()).await?;
+            build_rank_embed(&msg.author, rank, self.clock.now())
+        };
+
+        msg.channel_id
+            .send_message(&ctx.http, |m| m.set_embed(embed))
+            .await?;
 
         Ok(())
     }
 
+    pub async fn handle_rank(
+        &self,
+        ctx: Context,
+        command: ApplicationCommandInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let embed = if command.data.name == "rank" {
+            let users = self.db.get_top_users(LEADERBOARD_SIZE).await?;
+            build_leaderboard_embed(&users, self.clock.now())
+        } else {
+            let rank = self.db.get_user_rank(&command.user.id.to_string()).await?;
+            build_rank_embed(&command.user, rank, self.clock.now())
+        };
+
+        command
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|m| m.add_embed(embed))
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    // This function is responsible for handling the daily check-in command.
+    pub async fn handle_attend_command(
+        &self,
+        msg: &DiscordMessage,
+        ctx: &Context,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // If the message content is not an attendance command, we ignore it and return early.
+        if msg.content != "!attend" {
+            return Ok(());
+        }
+
+        // If the guild ID from the message doesn't match the configured guild,
+        // we don't process the command and return early.
+        let guild: u64 = self.config.discord_guild;
+        if msg.guild_id.unwrap_or_default().0 != guild {
+            return Ok(());
+        }
+
+        // Check if the message was sent in the attendance channel.
+        // If not, we reply with a message directing the user to the attendance channel.
+        let attendance_channel = ChannelId(self.config.attendance_channel);
+        if msg.channel_id != attendance_channel {
+            msg.reply(
+                &ctx.http,
+                format!(
+                    "{} Please go to the <#{}> channel for Daily Attendance and Points Checking.",
+                    msg.author.mention(),
+                    attendance_channel
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        if msg.author.bot {
+            return Ok(());
+        }
+
+        // Record the check-in and reward the user if it's the first one today.
+        if let Some(reward) = self.record_attendance(&msg.author).await? {
+            let content = attendance_content(msg.author.id, reward);
+            send_message(ctx, attendance_channel, content).await;
+        } else {
+            msg.reply(&ctx.http, already_attended_content(msg.author.id))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn handle_attend(
+        &self,
+        ctx: Context,
+        command: ApplicationCommandInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Check for the correct channel
+        let attendance_channel = ChannelId(self.config.attendance_channel);
+
+        if command.channel_id != attendance_channel {
+            command
+                .create_interaction_response(&ctx.http, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|m| {
+                            m.content(format!(
+                                "Please go to the <#{}> channel for Daily Attendance and Points Checking.",
+                                attendance_channel
+                            ))
+                            .flags(MessageFlags::EPHEMERAL)
+                        })
+                })
+                .await?;
+            return Ok(());
+        }
+
+        // Record the check-in; the confirmation is public, the rejection is hidden.
+        let (content, ephemeral) = match self.record_attendance(&command.user).await? {
+            Some(reward) => (attendance_content(command.user.id, reward), false),
+            None => (already_attended_content(command.user.id), true),
+        };
+
+        command
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|m| {
+                        if ephemeral {
+                            m.flags(MessageFlags::EPHEMERAL);
+                        }
+                        m.content(content)
+                    })
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    // Records the daily check-in of the user and credits the attendance points, including
+    // the bonus when the attendance streak reaches a milestone, in one transaction.
+    // Returns `None` if the user has already checked in today (UTC+0).
+    async fn record_attendance(
+        &self,
+        user: &User,
+    ) -> Result<Option<AttendanceReward>, Box<dyn std::error::Error + Send + Sync>> {
+        let activity = Activity {
+            id: None,
+            dc_id: user.id.into(),
+            dc_username: Some(user.name.to_string()),
+            activity: Some(ActivityType::Attend),
+            reward: ATTEND_POINTS,
+            created_at: self.clock.now(),
+            ..Default::default()
+        };
+
+        self.db
+            .record_attendance(activity, Some(&user.name), &self.config.streak_bonus)
+            .await
+    }
+
     pub async fn poll_reaction(
         &self,
         ctx: &Context,
@@ -716,8 +1156,7 @@ impl Handler {
         let user_id = match add_reaction.user_id {
             Some(user_id) => user_id,
             None => {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+                return Err(Box::new(std::io::Error::other(
                     "No user ID found for reaction",
                 )));
             }
@@ -757,7 +1196,7 @@ impl Handler {
             activity: Some(ActivityType::Poll),
             reward: REWARD_POINTS,
             message_id: Some(message_id),
-            created_at: Utc::now(),
+            created_at: self.clock.now(),
             ..Default::default()
         };
 
@@ -765,14 +1204,21 @@ impl Handler {
         // If the document was successfully added, award points to the user and send a confirmation message.
         if let Ok(true) = self.db.add_react_poll_activity(activity).await {
             // Adjust the user's points in the database.
-            self.db
-                .adjust_user_points(&user_id.to_string(), None, REWARD_POINTS)
+            let adjustment = self
+                .db
+                .adjust_user_points(
+                    &user_id.to_string(),
+                    None,
+                    REWARD_POINTS,
+                    PointsReason::Poll,
+                    Some(message_id.to_string()),
+                )
                 .await?;
 
             // Prepare the content for the confirmation message.
             let content = format!(
-                "<@{}> got 15 points from participating in the [Quiz & Poll] (https://discord.com/channels/{}/{}/{}) in <#{}> channel 👏🏻",
-                user_id, guild_id, message_channel_id, message_id, message_channel_id
+                "<@{}> got {} points from participating in the [Quiz & Poll] (https://discord.com/channels/{}/{}/{}) in <#{}> channel 👏🏻",
+                user_id, adjustment.applied, guild_id, message_channel_id, message_id, message_channel_id
             );
 
             // Send the message.
@@ -812,9 +1258,9 @@ impl Handler {
         let attendance_channel = ChannelId(attendance_channel_id);
 
         // Try to extract the user ID from the reaction. If it cannot be found, return an error.
-        let user_id = reaction.user_id.ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::Other, "No user ID found for reaction")
-        })?;
+        let user_id = reaction
+            .user_id
+            .ok_or_else(|| std::io::Error::other("No user ID found for reaction"))?;
 
         // Fetch the user who reacted and the message that was reacted to.
         let user = user_id.to_user(&ctx).await?;
@@ -829,19 +1275,24 @@ impl Handler {
             ReactionType::Unicode(s) => Some(s.as_str()),
             _ => None,
         };
-        let emoji_name = emoji_name.ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::Other, "Emoji name not found")
-        })?;
+        let emoji_name = emoji_name.ok_or_else(|| std::io::Error::other("Emoji name not found"))?;
 
         // If a bad emoji was used, deduct points from the user and notify them.
         if BAD_EMOJI.contains(emoji_name) {
-            self.db
-                .adjust_user_points(&user_id.to_string(), None, DEDUCT_POINTS)
+            let adjustment = self
+                .db
+                .adjust_user_points(
+                    &user_id.to_string(),
+                    None,
+                    DEDUCT_POINTS,
+                    PointsReason::BadEmoji,
+                    Some(message_id.to_string()),
+                )
                 .await?;
 
             let content = format!(
-                "<@{}> got 10 points deducted for reacting {} in the <#{}> channel.",
-                user_id, emoji_name, attendance_channel.0
+                "<@{}> got {} points deducted for reacting {} in the <#{}> channel.",
+                user_id, -adjustment.applied, emoji_name, attendance_channel.0
             );
 
             send_message(ctx, attendance_channel, content).await;
@@ -867,21 +1318,27 @@ impl Handler {
             reward: REACT_POINTS,
             message_id: Some(message_id),
             emoji: Some(emoji_name.to_string()),
-            created_at: Utc::now(),
-            ..Default::default()
+            created_at: self.clock.now(),
         };
 
         // Add the activity to the database and grant points to the user.
         if let Ok(true) = self.db.add_reaction_activity(activity).await {
             let user_id_str = user_id.to_string();
 
-            self.db
-                .adjust_user_points(&user_id_str, None, REACT_POINTS)
+            let adjustment = self
+                .db
+                .adjust_user_points(
+                    &user_id_str,
+                    None,
+                    REACT_POINTS,
+                    PointsReason::React,
+                    Some(message_id.to_string()),
+                )
                 .await?;
 
             let content = format!(
-            "<@{}> got 3 points from reacting {} on (https://discord.com/channels/{}/{}/{}) in the <#{}> channel.",
-            user_id, emoji_name, guild_id, message_channel_id, message_id, message_channel_id
+            "<@{}> got {} points from reacting {} on (https://discord.com/channels/{}/{}/{}) in the <#{}> channel.",
+            user_id, adjustment.applied, emoji_name, guild_id, message_channel_id, message_id, message_channel_id
         );
 
             send_message(ctx, attendance_channel, content).await;
@@ -902,21 +1359,27 @@ impl Handler {
             reward: RECEIVE_POINTS,
             message_id: Some(message_id),
             emoji: Some(emoji_name.to_string()),
-            created_at: Utc::now(),
-            ..Default::default()
+            created_at: self.clock.now(),
         };
 
         // Add the activity to the database and grant points to the author of the message.
         if let Ok(true) = self.db.add_reaction_activity(activity).await {
             let author_id_str = author.id.to_string();
 
-            self.db
-                .adjust_user_points(&author_id_str, Some(&author.name), RECEIVE_POINTS)
+            let adjustment = self
+                .db
+                .adjust_user_points(
+                    &author_id_str,
+                    Some(&author.name),
+                    RECEIVE_POINTS,
+                    PointsReason::Receive,
+                    Some(message_id.to_string()),
+                )
                 .await?;
 
             let content = format!(
-            "<@{}> got 10 points from <@{}>'s reaction {} on (https://discord.com/channels/{}/{}/{}) in the <#{}> channel.",
-            author.id, user_id, emoji_name, guild_id, message_channel_id, message_id, message_channel_id
+            "<@{}> got {} points from <@{}>'s reaction {} on (https://discord.com/channels/{}/{}/{}) in the <#{}> channel.",
+            author.id, adjustment.applied, user_id, emoji_name, guild_id, message_channel_id, message_id, message_channel_id
         );
 
             send_message(ctx, attendance_channel, content).await;
@@ -925,3 +1388,137 @@ impl Handler {
         Ok(())
     }
 }
+
+fn attendance_content(user_id: UserId, reward: AttendanceReward) -> String {
+    let mut content = format!(
+        "<@{}> checked in today and got {} points for the Daily Attendance! ✅ (🔥 {} day streak)",
+        user_id, reward.points, reward.streak.current
+    );
+    if reward.bonus > 0 {
+        content += &format!(
+            "\n🎉 Amazing! You reached a {} day attendance streak and earned {} bonus points!",
+            reward.streak.current, reward.bonus
+        );
+    }
+    content
+}
+
+fn already_attended_content(user_id: UserId) -> String {
+    format!(
+        "<@{}> You have already checked in today 🙌🏻 The attendance resets at 00:00 (UTC+0), see you tomorrow!",
+        user_id
+    )
+}
+
+// Builds the lotto guideline from the rules, with an example of every number of matches
+fn lotto_guideline_content(
+    lotto_channel: ChannelId,
+    round_rules: &LottoRules,
+    rules: &LottoRules,
+    now: chrono::DateTime<Utc>,
+) -> String {
+    let digits = round_rules.digits;
+    let join = |numbers: &[i32]| {
+        numbers
+            .iter()
+            .map(|n| format!("'{}'", n))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    // The winning numbers alternate 0 and 6, and each example misses one more position
+    let winning: Vec<i32> = (0..digits)
+        .map(|i| if i % 2 == 0 { 0 } else { 6 })
+        .collect();
+    let mut examples = String::new();
+    for matches in (0..=digits).rev() {
+        let chosen: Vec<i32> = winning
+            .iter()
+            .enumerate()
+            .map(|(i, n)| {
+                if (i as u32) < digits - matches {
+                    (n + 1) % 10
+                } else {
+                    *n
+                }
+            })
+            .collect();
+        examples += &format!(
+            "\n If you chose {} —> {} matching number(s)",
+            join(&chosen),
+            matches
+        );
+    }
+
+    let mut prizes = String::new();
+    for matches in 0..=digits {
+        let points = round_rules.prize_for(matches);
+        let reward = round_rules
+            .prizes
+            .iter()
+            .find(|prize| prize.matches == matches)
+            .and_then(|prize| prize.reward.as_ref())
+            .map(|reward| format!(" + {}", reward))
+            .unwrap_or_default();
+        let jackpot = if matches == digits {
+            " + the jackpot, split evenly among the winners"
+        } else {
+            ""
+        };
+        prizes += &format!(
+            "\n {} matching number(s): {} points{}{}",
+            matches, points, reward, jackpot
+        );
+    }
+
+    let mut participation = format!(
+        "\n- The participation fee is {} points; maximum {} times of participation per week.",
+        rules.fee, rules.entries_per_week
+    );
+    if rules.jackpot_contribution(rules.fee) > 0 {
+        participation += &format!(
+            "\n- {} points of every fee go to the jackpot; it rolls over to the next week when nobody matches all {} numbers.",
+            rules.jackpot_contribution(rules.fee),
+            digits
+        );
+    }
+    for period in rules
+        .free_periods
+        .iter()
+        .filter(|period| now < period.ends_at)
+    {
+        participation += &format!(
+            "\n- **Free of charge from {} to {} (UTC+0)**; maximum {} times of participation per week.",
+            period.starts_at.format("%d %b %H:%M"),
+            period.ends_at.format("%d %b %H:%M"),
+            period.entries_per_week.unwrap_or(rules.entries_per_week)
+        );
+    }
+
+    format!(
+        "**Welcome to PlayDapp Weekly Lotto!~**:partying_face: :slot_machine: \
+        \n\n*How to join?*🤩 \
+        \n1. Go to <#{}> channel. \
+        \n2. Type **\"/lotto pick\"** and enter your {} choices of single-digit numbers (i.e., between 0-9), e.g., {}. Set `save_favorite` to replay them later with **\"/lotto favorite\"**, or let **\"/lotto quick-pick\"** choose random numbers for one or more tickets. \
+        \n3. Once you successfully join the lotto, a confirmation message will be displayed!📨 \
+        \n4. Type **\"/checklotto\"** to check your lotto participation status and chosen numbers for the current and previous week. \
+        \n\n*Rules*🧑🏻‍🏫 \
+        \n- Participants need to choose {} single-digit numbers (i.e., between 0-9). \
+        \n- Both the **integer values** and **position** should match with the winning lotto numbers to win. \
+        \n*Example*: If the winning number is {}.{} \
+        \n\n*Prize*🏆{} \
+        \nWinners will be notified by DM. 📩 \
+        \n\n*Participation guidelines*💰{} \
+        \n\n*When will the Weekly Lotto open?*⏰ \
+        \n- The entry period is **Monday 00:00 - Sun 23:59 (UTC+0)**. \
+        \n- The result of the previous week will be announced on **every Monday 03:00 (UTC+0)**",
+        lotto_channel,
+        digits,
+        join(&[1, 5, 4, 7, 2, 8, 3, 9, 0, 6][..digits.min(10) as usize]),
+        digits,
+        join(&winning),
+        examples,
+        prizes,
+        participation
+    )
+}
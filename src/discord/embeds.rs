@@ -1,12 +1,20 @@
+use chrono::Utc;
 use serenity::builder::CreateEmbed;
 use serenity::utils::Color;
 use serenity::{
     model::{prelude::ChannelId, user::User},
     prelude::Context,
 };
+use std::collections::HashSet;
 use tracing::{error, info};
 
-use crate::database::models::Exchange;
+use crate::database::models::{
+    AttendanceStreak, Exchange, JobState, LottoDraw, LottoGuess, RankedUser,
+};
+use crate::scheduler::runner::Job;
+
+// The most winners mentioned per matching numbers, the field value is limited to 1024 characters
+const LOTTO_MENTIONS_PER_TIER: usize = 20;
 
 pub async fn send_records_to_discord(
     records: &[Exchange],
@@ -14,6 +22,7 @@ pub async fn send_records_to_discord(
     channel_id: ChannelId,
     user: &User,
     points: i32,
+    now: chrono::DateTime<Utc>,
 ) {
     let title = format!("{}'s Exchange Records", user.name);
     let description = format!(
@@ -31,13 +40,17 @@ pub async fn send_records_to_discord(
         .color(Color::new(0x00FA9A))
         .thumbnail(thumbnail)
         .footer(|f| f.text(footer_text).icon_url(footer_icon_url))
-        .timestamp(chrono::Utc::now().to_rfc3339());
+        .timestamp(now.to_rfc3339());
 
     for record in records {
         let items = format!("{} {}(s) 🎟️", record.quantity, record.item);
+        let status = match &record.tx_hash {
+            Some(tx_hash) => format!("{:?}\nTx: `{}`", record.status, tx_hash),
+            None => format!("{:?}", record.status),
+        };
         embed
             .field("Item", items, true)
-            .field("Status", format!("{:?}", record.status), true)
+            .field("Status", status, true)
             .field(
                 "Time (UTC)",
                 record.updated_at.format("%Y-%m-%d %H:%M"),
@@ -53,7 +66,14 @@ pub async fn send_records_to_discord(
     }
 }
 
-pub async fn send_check_points(ctx: &Context, channel_id: ChannelId, user: &User, points: i32) {
+pub async fn send_check_points(
+    ctx: &Context,
+    channel_id: ChannelId,
+    user: &User,
+    points: i32,
+    streak: AttendanceStreak,
+    now: chrono::DateTime<Utc>,
+) {
     let thumbnail = user.face();
     let footer_text = format!("Given to {}", user.name);
     let footer_icon_url = thumbnail.clone();
@@ -64,9 +84,16 @@ pub async fn send_check_points(ctx: &Context, channel_id: ChannelId, user: &User
         .color(Color::new(0x00AAFF))
         .thumbnail(thumbnail)
         .footer(|f| f.text(footer_text).icon_url(footer_icon_url))
-        .timestamp(chrono::Utc::now().to_rfc3339());
+        .timestamp(now.to_rfc3339());
 
-    embed.field(user.name.to_string(), format!("{:?}", points), true);
+    embed
+        .field(user.name.to_string(), format!("{:?}", points), true)
+        .field(
+            "Current Streak 🔥",
+            format!("{} day(s)", streak.current),
+            true,
+        )
+        .field("Best Streak 🏆", format!("{} day(s)", streak.best), true);
 
     if let Err(why) = channel_id
         .send_message(&ctx.http, |m| m.set_embed(embed))
@@ -76,6 +103,276 @@ pub async fn send_check_points(ctx: &Context, channel_id: ChannelId, user: &User
     }
 }
 
+// Builds the list of exchange requests waiting for the admin review
+pub fn build_exchange_queue_embed(records: &[Exchange], now: chrono::DateTime<Utc>) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("Pending Exchange Requests ({})", records.len()))
+        .color(Color::new(0xFFA500))
+        .timestamp(now.to_rfc3339());
+
+    if records.is_empty() {
+        embed.description("There are no pending exchange requests. 🎉");
+    }
+
+    for record in records {
+        let name = format!(
+            "{} {}(s) 🎟️ - {}",
+            record.quantity, record.item, record.status
+        );
+        let value = format!(
+            "ID: `{}`\nUser: <@{}>\nWallet: `{}`\nTime (UTC): {}",
+            record.id.map(|id| id.to_hex()).unwrap_or_default(),
+            record.dc_id,
+            record.wallet_address.as_deref().unwrap_or("-"),
+            record.updated_at.format("%Y-%m-%d %H:%M")
+        );
+        embed.field(name, value, false);
+    }
+
+    embed
+}
+
+// Builds the list of the scheduled jobs with their state
+pub fn build_jobs_embed(
+    jobs: &[std::sync::Arc<dyn Job>],
+    states: &[JobState],
+    now: chrono::DateTime<Utc>,
+) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Scheduled Jobs")
+        .color(Color::new(0x5865F2))
+        .timestamp(now.to_rfc3339());
+
+    let format_time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+        time.map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| "-".to_string())
+    };
+    for job in jobs {
+        let state = states.iter().find(|state| state.name == job.name());
+        let paused = state.is_some_and(|state| state.paused == Some(true));
+        let last_result = match state.and_then(|state| state.last_result.as_deref()) {
+            Some(result) => {
+                let icon = match state.and_then(|state| state.last_succeeded) {
+                    Some(true) => "✅",
+                    Some(false) => "❌",
+                    None => "⏭️",
+                };
+                // The field value is limited to 1024 characters
+                format!("{} {}", icon, result.chars().take(300).collect::<String>())
+            }
+            None => "-".to_string(),
+        };
+
+        let name = format!("{}{}", job.name(), if paused { " ⏸️ (paused)" } else { "" });
+        let value = format!(
+            "{}\nSchedule: `{}`\nNext run (UTC): {}\nLast run (UTC): {}\nLast result: {}",
+            job.description(),
+            job.schedule(),
+            format_time(state.and_then(|state| state.next_run_at)),
+            format_time(state.and_then(|state| state.last_run_at)),
+            last_result
+        );
+        embed.field(name, value, false);
+    }
+
+    embed
+}
+
+// Builds the Cumulative Points TOP leaderboard, users with equal points share the same rank
+pub fn build_leaderboard_embed(users: &[RankedUser], now: chrono::DateTime<Utc>) -> CreateEmbed {
+    let mut lines = Vec::new();
+    let mut rank = 0;
+    for (index, user) in users.iter().enumerate() {
+        if index == 0 || users[index - 1].points != user.points {
+            rank = index + 1;
+        }
+        let medal = match rank {
+            1 => "🥇",
+            2 => "🥈",
+            3 => "🥉",
+            _ => "🏅",
+        };
+        lines.push(format!(
+            "{} **#{}** <@{}> - **{}** points",
+            medal, rank, user.dc_id, user.points
+        ));
+    }
+
+    let description = if lines.is_empty() {
+        "Nobody has earned points yet. Type `!attend` to be the first! 🙌🏻".to_string()
+    } else {
+        lines.join("\n")
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("The Cumulative Points TOP {} 🏆", users.len()))
+        .description(description)
+        .color(Color::new(0xFFD700))
+        .timestamp(now.to_rfc3339());
+
+    embed
+}
+
+// Builds the personal ranking of the user, `None` when the user has no points yet
+pub fn build_rank_embed(
+    user: &User,
+    rank: Option<(u64, i32)>,
+    now: chrono::DateTime<Utc>,
+) -> CreateEmbed {
+    let thumbnail = user.face();
+    let footer_text = format!("Given to {}", user.name);
+    let footer_icon_url = thumbnail.clone();
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Your Point Ranking")
+        .color(Color::new(0x00AAFF))
+        .thumbnail(thumbnail)
+        .footer(|f| f.text(footer_text).icon_url(footer_icon_url))
+        .timestamp(now.to_rfc3339());
+
+    match rank {
+        Some((rank, points)) => {
+            embed.field("Rank", format!("#{}", rank), true).field(
+                "Points",
+                points.to_string(),
+                true,
+            );
+        }
+        None => {
+            embed.description(
+                "You are not ranked yet. Earn some points to join the leaderboard! 🏋️‍♂️💪🏋️‍♀️",
+            );
+        }
+    }
+
+    embed
+}
+
+// The results of a drawn lotto round. Only the winners who opted in with `/lotto-mention`
+// are listed, the other winning tickets are counted.
+pub fn build_lotto_results_embed(
+    draw: &LottoDraw,
+    winners: &[LottoGuess],
+    mentionable: &HashSet<u64>,
+    current_pot: Option<i32>,
+    attendance_channel: ChannelId,
+    now: chrono::DateTime<Utc>,
+) -> CreateEmbed {
+    let rules = draw.rules();
+    let numbers = draw
+        .numbers
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("🎰 Weekly Lotto Results - Week {}", draw.round.week))
+        .description(format!(
+            "Thank you all for joining last week's lotto! 😆 Here are the results 🔥\n\n**Winning Numbers: {}**",
+            numbers
+        ))
+        .color(Color::new(0x00FA9A))
+        .timestamp(now.to_rfc3339());
+
+    for matches in (1..=rules.digits).rev() {
+        let tickets: Vec<&LottoGuess> = winners
+            .iter()
+            .filter(|guess| guess.matched_count == Some(matches as i32))
+            .collect();
+        let points = if matches == rules.digits {
+            draw.jackpot_prize.unwrap_or(rules.top_prize())
+        } else {
+            rules.prize_for(matches)
+        };
+
+        let mut listed: Vec<u64> = tickets
+            .iter()
+            .map(|guess| guess.dc_id)
+            .filter(|dc_id| mentionable.contains(dc_id))
+            .collect();
+        listed.sort_unstable();
+        listed.dedup();
+        listed.truncate(LOTTO_MENTIONS_PER_TIER);
+
+        let mut value = format!(
+            "{} winning ticket(s) · {} points each",
+            tickets.len(),
+            points
+        );
+        if !listed.is_empty() {
+            let mentions = listed
+                .iter()
+                .map(|dc_id| format!("<@{}>", dc_id))
+                .collect::<Vec<String>>()
+                .join(", ");
+            value += &format!("\n{}", mentions);
+
+            let others = tickets
+                .iter()
+                .filter(|guess| !listed.contains(&guess.dc_id))
+                .count();
+            if others > 0 {
+                value += &format!(" and {} more", others);
+            }
+        }
+        embed.field(
+            format!("{}\u{fe0f}\u{20e3} matching number(s)", matches),
+            value,
+            false,
+        );
+    }
+
+    // The jackpot is settled when the round is closed
+    let mut jackpot = match (draw.jackpot_winners, draw.jackpot_prize) {
+        (Some(winners), Some(prize)) if winners > 0 => format!(
+            "{} points, split among {} winner(s) — **{}** points each!",
+            draw.jackpot_pool(),
+            winners,
+            prize
+        ),
+        _ => format!(
+            "Nobody matched all {} numbers, so {} points roll over to this week!",
+            rules.digits,
+            draw.jackpot_rollover.unwrap_or(draw.jackpot)
+        ),
+    };
+    if let Some(pot) = current_pot {
+        jackpot += &format!("\nThis week's jackpot is **{}** points 🤑", pot);
+    }
+    embed.field("💰 Jackpot", jackpot, false);
+
+    // Reveal the seed so anyone can verify the commitment and the numbers
+    if let (Some(seed), Some(commitment)) = (&draw.seed, &draw.commitment) {
+        embed.field(
+            "🔐 Verification",
+            format!(
+                "**Seed:** `{}`\n**Commitment:** `{}`\nThe commitment is `keccak256(seed)` and the numbers follow from the seed alone: they are the first {} bytes of `keccak256(\"lotto-numbers:\" + seed)` below 250 (rehashing the hash when more are needed), each taken modulo 10.",
+                seed, commitment, rules.digits
+            ),
+            false,
+        );
+    }
+
+    embed.field(
+        "🎁 Prizes",
+        format!(
+            "Winners, please read the DM 📨 that we sent you and check your prize in <#{}>!\n\
+            Use `/lotto-mention` to be listed here when you win.\n\
+            The entry period opens every Monday 00:00 (UTC+0), good luck to you all! 🍀",
+            attendance_channel
+        ),
+        false,
+    );
+
+    embed
+}
+
 // Helper function to format and send a message to a Discord channel
 pub async fn send_message(ctx: &Context, channel: ChannelId, content: String) {
     // Try to send the message
@@ -5,30 +5,38 @@ use serenity::{
     model::application::interaction::Interaction,
     model::channel::Message as DiscordMessage,
     model::gateway::{GatewayIntents, Ready},
-    model::id::ChannelId,
     model::prelude::Reaction,
     prelude::*,
 };
 
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::HashSet,
+    sync::{Arc, OnceLock},
+};
 use tracing::{error, info};
 
+use super::history::LOTTO_HISTORY_PREFIX;
 use super::slash;
-use crate::scheduler::send_daily_report;
+use crate::clock::Clock;
+use crate::config::EnvConfig;
+use crate::database::models::LottoRules;
+use crate::database::store::Store;
+use crate::scheduler::{runner::JobScheduler, setup_scheduler};
 use crate::util::filter_guilds;
-use crate::{config::EnvConfig, scheduler::lotto_game_scheduler};
-use crate::{database::mongo::MongoDB, scheduler::send_announcement_lotto_scheduler};
 
 pub struct Handler {
-    pub db: Arc<MongoDB>,
+    pub db: Arc<dyn Store>,
     pub config: Arc<EnvConfig>,
+    pub clock: Arc<dyn Clock>,
+    // Set once the client is built, since the jobs use its HTTP client
+    pub scheduler: Arc<OnceLock<Arc<JobScheduler>>>,
 }
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        match interaction {
-            Interaction::ApplicationCommand(command) => match command.data.name.as_str() {
+        if let Interaction::ApplicationCommand(command) = interaction {
+            match command.data.name.as_str() {
                 "exchange" => {
                     if let Err(why) = self.handle_exchange(ctx.clone(), command).await {
                         error!("Error handling exchange: {:?}", why);
@@ -54,9 +62,58 @@ impl EventHandler for Handler {
                         error!("Error handling check lotto guesses: {:?}", why);
                     }
                 }
+                "attend" => {
+                    if let Err(why) = self.handle_attend(ctx.clone(), command).await {
+                        error!("Error handling attend: {:?}", why);
+                    }
+                }
+                "rank" | "myrank" => {
+                    if let Err(why) = self.handle_rank(ctx.clone(), command).await {
+                        error!("Error handling rank: {:?}", why);
+                    }
+                }
+                "job-admin" => {
+                    if let Err(why) = self.handle_job_admin(ctx.clone(), command).await {
+                        error!("Error handling job admin: {:?}", why);
+                    }
+                }
+                "exchange-admin" => {
+                    if let Err(why) = self.handle_exchange_admin(ctx.clone(), command).await {
+                        error!("Error handling exchange admin: {:?}", why);
+                    }
+                }
+                "wallet" => {
+                    if let Err(why) = self.handle_wallet(ctx.clone(), command).await {
+                        error!("Error handling wallet: {:?}", why);
+                    }
+                }
+                "lotto-mention" => {
+                    if let Err(why) = self.handle_lotto_mention(ctx.clone(), command).await {
+                        error!("Error handling lotto mention: {:?}", why);
+                    }
+                }
+                "lotto-history" => {
+                    if let Err(why) = self.handle_lotto_history(ctx.clone(), command).await {
+                        error!("Error handling lotto history: {:?}", why);
+                    }
+                }
                 _ => info!("Command not found"),
-            },
-            _ => (),
+            }
+        } else if let Interaction::Autocomplete(autocomplete) = interaction {
+            if autocomplete.data.name == "exchange" {
+                if let Err(why) = self
+                    .handle_exchange_autocomplete(ctx.clone(), autocomplete)
+                    .await
+                {
+                    error!("Error handling exchange autocomplete: {:?}", why);
+                }
+            }
+        } else if let Interaction::MessageComponent(component) = interaction {
+            if component.data.custom_id.starts_with(LOTTO_HISTORY_PREFIX) {
+                if let Err(why) = self.handle_lotto_history_page(ctx.clone(), component).await {
+                    error!("Error handling lotto history page: {:?}", why);
+                }
+            }
         }
     }
 
@@ -65,11 +122,33 @@ impl EventHandler for Handler {
         // Filter out unwanted guilds, leaving those not in the allowed list
         filter_guilds(&ctx, ready).await;
 
+        // The lotto command follows the digits of this week's round
+        let lotto_rules = match self.db.add_weekly_draw().await {
+            Ok(round) => round.rules(),
+            Err(e) => {
+                error!("Error opening the lotto round: {}", e);
+                LottoRules::default()
+            }
+        };
+
+        let job_names: Vec<&str> = match self.scheduler.get() {
+            Some(scheduler) => scheduler.jobs().iter().map(|job| job.name()).collect(),
+            None => Vec::new(),
+        };
+
         // Setup global commands, deleting the "exchange" command if it exists and recreating it
-        setup_global_commands(&ctx).await;
+        setup_global_commands(&ctx, &lotto_rules, &job_names).await;
     }
 
     async fn message(&self, ctx: Context, msg: DiscordMessage) {
+        if let Err(why) = self.handle_attend_command(&msg, &ctx).await {
+            error!("Error handling attend command: {:?}", why);
+        }
+
+        if let Err(why) = self.handle_rank_command(&msg, &ctx).await {
+            error!("Error handling rank command: {:?}", why);
+        }
+
         if let Err(why) = self.handle_records_command(&msg, &ctx).await {
             error!("Error handling records command: {:?}", why);
         }
@@ -95,8 +174,9 @@ impl EventHandler for Handler {
 
 pub async fn run_discord_bot(
     token: &str,
-    db: Arc<MongoDB>,       // Make sure to pass an Arc<MongoDB> instead of &MongoDB
+    db: Arc<dyn Store>, // Make sure to pass an Arc<dyn Store> instead of &dyn Store
     config: Arc<EnvConfig>, // Same with the EnvConfig
+    clock: Arc<dyn Clock>,
 ) -> tokio::task::JoinHandle<()> {
     // Define the necessary gateway intents
     let intents = GatewayIntents::GUILD_MESSAGES
@@ -106,36 +186,32 @@ pub async fn run_discord_bot(
         | GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::GUILD_MESSAGE_REACTIONS
         | GatewayIntents::DIRECT_MESSAGE_REACTIONS;
+    let scheduler = Arc::new(OnceLock::new());
     // Build the Discord client with the token, intents and event handler
-    let client = Client::builder(&token, intents)
+    let client = Client::builder(token, intents)
         .event_handler(Handler {
             db: Arc::clone(&db),
             config: Arc::clone(&config),
+            clock: Arc::clone(&clock),
+            scheduler: Arc::clone(&scheduler),
         })
         .await
         .expect("Error creating Discord client");
 
-    // Clone the HTTP context for use in the daily report task
+    // Clone the HTTP context for use in the scheduled jobs
     let http = client.cache_and_http.http.clone();
     // Create a shared, mutable reference to the client using an Arc<Mutex<>>
     let shared_client = Arc::new(Mutex::new(client));
 
     // Spawn a new async task to handle running the Discord bot
     let handler = tokio::spawn(async move {
-        // The channel ID to send the daily reports
-        let channel_id = ChannelId(1054296641651347486); // Replace with the specific channel ID
-                                                         // Start the daily report in a new async task
-        send_daily_report(http.clone(), channel_id).await;
-
-        lotto_game_scheduler(Arc::clone(&db), Arc::clone(&config), http.clone()).await;
-
-        send_announcement_lotto_scheduler(
+        // Start the scheduled jobs, including the daily report
+        let _ = scheduler.set(setup_scheduler(
             Arc::clone(&db),
             Arc::clone(&config),
             http.clone(),
-            channel_id,
-        )
-        .await;
+            Arc::clone(&clock),
+        ));
 
         // Lock the shared client for use in this task
         let mut locked_client = shared_client.lock().await;
@@ -149,7 +225,7 @@ pub async fn run_discord_bot(
     handler
 }
 
-pub async fn setup_global_commands(ctx: &Context) {
+pub async fn setup_global_commands(ctx: &Context, lotto_rules: &LottoRules, job_names: &[&str]) {
     // Fetch existing global commands.
     let global_commands = Command::get_global_application_commands(&ctx.http)
         .await
@@ -161,6 +237,14 @@ pub async fn setup_global_commands(ctx: &Context) {
         "lotto-guideline",
         "attendance-guideline",
         "checklotto",
+        "attend",
+        "rank",
+        "myrank",
+        "exchange-admin",
+        "wallet",
+        "lotto-history",
+        "lotto-mention",
+        "job-admin",
     ];
     let commands_to_delete: HashSet<&str> = commands_to_delete.iter().cloned().collect();
 
@@ -175,14 +259,28 @@ pub async fn setup_global_commands(ctx: &Context) {
 
     let command_setups: Vec<fn(&mut CreateApplicationCommand) -> &mut CreateApplicationCommand> = vec![
         slash::exchange,
-        slash::lotto,
         slash::lotto_guideline,
         slash::attendance_guideline,
         slash::check_lotto,
+        slash::attend,
+        slash::rank,
+        slash::my_rank,
+        slash::exchange_admin,
+        slash::wallet,
+        slash::lotto_history,
+        slash::lotto_mention,
     ];
 
     for setup in command_setups {
         let _ =
             Command::create_global_application_command(&ctx.http, |command| setup(command)).await;
     }
+    let _ = Command::create_global_application_command(&ctx.http, |command| {
+        slash::lotto(command, lotto_rules)
+    })
+    .await;
+    let _ = Command::create_global_application_command(&ctx.http, |command| {
+        slash::job_admin(command, job_names)
+    })
+    .await;
 }
@@ -0,0 +1,197 @@
+use serenity::{
+    builder::{CreateComponents, CreateEmbed},
+    model::application::component::ButtonStyle,
+    model::prelude::interaction::{
+        application_command::ApplicationCommandInteraction,
+        message_component::MessageComponentInteraction, InteractionResponseType, MessageFlags,
+    },
+    model::prelude::UserId,
+    prelude::Context,
+    utils::Color,
+};
+
+use super::handler::Handler;
+use crate::database::models::{LottoGuess, LottoRoundSummary};
+
+// The custom id of the paging buttons is "lotto-history:<draws|entries>:<page>"
+pub const LOTTO_HISTORY_PREFIX: &str = "lotto-history:";
+// The number of draws or entries on a page
+const HISTORY_PAGE_SIZE: u64 = 5;
+
+impl Handler {
+    pub async fn handle_lotto_history(
+        &self,
+        ctx: Context,
+        command: ApplicationCommandInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let view = match command.data.options.first() {
+            Some(option) if option.name == "entries" => "entries",
+            _ => "draws",
+        };
+        let (embed, components) = self.lotto_history_page(view, command.user.id, 0).await?;
+
+        command
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|m| {
+                        m.flags(MessageFlags::EPHEMERAL)
+                            .add_embed(embed)
+                            .set_components(components)
+                    })
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    // Turns the page when one of the buttons below the history is clicked
+    pub async fn handle_lotto_history_page(
+        &self,
+        ctx: Context,
+        component: MessageComponentInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut parts = component.data.custom_id[LOTTO_HISTORY_PREFIX.len()..].split(':');
+        let view = match parts.next() {
+            Some("entries") => "entries",
+            _ => "draws",
+        };
+        let page = parts
+            .next()
+            .and_then(|page| page.parse::<u64>().ok())
+            .unwrap_or(0);
+        let (embed, components) = self
+            .lotto_history_page(view, component.user.id, page)
+            .await?;
+
+        component
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|m| m.set_embed(embed).set_components(components))
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn lotto_history_page(
+        &self,
+        view: &str,
+        user_id: UserId,
+        page: u64,
+    ) -> Result<(CreateEmbed, CreateComponents), Box<dyn std::error::Error + Send + Sync>> {
+        let mut embed = CreateEmbed::default();
+        embed.color(Color::new(0x00FA9A));
+
+        let total = if view == "entries" {
+            let (guesses, total) = self
+                .db
+                .get_user_lotto_history(user_id.0, page, HISTORY_PAGE_SIZE)
+                .await?;
+            embed.title("🎟️ Your Lotto Entries");
+            for guess in guesses {
+                add_entry_field(&mut embed, &guess);
+            }
+            total
+        } else {
+            let (rounds, total) = self
+                .db
+                .get_lotto_draw_history(page, HISTORY_PAGE_SIZE)
+                .await?;
+            embed.title("🎰 Past Lotto Draws");
+            for round in rounds {
+                add_draw_field(&mut embed, &round);
+            }
+            total
+        };
+
+        if total == 0 {
+            embed.description("There is nothing to show yet.");
+        }
+        let pages = total.div_ceil(HISTORY_PAGE_SIZE).max(1);
+        embed.footer(|f| f.text(format!("Page {} of {}", page + 1, pages)));
+
+        let mut components = CreateComponents::default();
+        components.create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .custom_id(format!(
+                        "{}{}:{}",
+                        LOTTO_HISTORY_PREFIX,
+                        view,
+                        page.saturating_sub(1)
+                    ))
+                    .label("◀ Previous")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page == 0)
+            })
+            .create_button(|button| {
+                button
+                    .custom_id(format!("{}{}:{}", LOTTO_HISTORY_PREFIX, view, page + 1))
+                    .label("Next ▶")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page + 1 >= pages)
+            })
+        });
+
+        Ok((embed, components))
+    }
+}
+
+fn add_draw_field(embed: &mut CreateEmbed, round: &LottoRoundSummary) {
+    let draw = &round.draw;
+    let numbers = draw
+        .numbers
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let winners = (1..=draw.rules().digits)
+        .rev()
+        .map(|matches| {
+            format!(
+                "{}\u{fe0f}\u{20e3} {}",
+                matches,
+                round.winners.get(&matches).unwrap_or(&0)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" · ");
+
+    let mut value = format!(
+        "**Numbers:** {}\n**Winners:** {}\n**Entries:** {} · **Points paid:** {}",
+        numbers, winners, round.entries, round.points_paid
+    );
+    if let Some(rollover) = draw.jackpot_rollover {
+        value += &format!(
+            "\n**Jackpot:** {} points, {} rolled over",
+            draw.jackpot_pool(),
+            rollover
+        );
+    }
+
+    embed.field(
+        format!("Week {}, {}", draw.round.week, draw.round.year),
+        value,
+        false,
+    );
+}
+
+fn add_entry_field(embed: &mut CreateEmbed, guess: &LottoGuess) {
+    let result = match (guess.matched_count, guess.points) {
+        (Some(matches), Some(points)) => {
+            format!("{} matching number(s), {} points", matches, points)
+        }
+        _ => "Not drawn yet".to_string(),
+    };
+
+    embed.field(
+        format!(
+            "Week {}, {} — {} (UTC)",
+            guess.round.week,
+            guess.round.year,
+            guess.created_at.format("%Y-%m-%d %H:%M")
+        ),
+        format!("**Numbers:** {:?}\n{}", guess.numbers, result),
+        false,
+    );
+}
@@ -0,0 +1,103 @@
+use serenity::{
+    model::prelude::interaction::{
+        application_command::ApplicationCommandInteraction, InteractionResponseType, MessageFlags,
+    },
+    prelude::Context,
+};
+
+use super::admin::{get_string_option, is_admin, respond};
+use super::embeds::build_jobs_embed;
+use super::handler::Handler;
+
+impl Handler {
+    pub async fn handle_job_admin(
+        &self,
+        ctx: Context,
+        command: ApplicationCommandInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // The command is hidden from non-admins, but the permissions can be overridden per guild
+        if !is_admin(&command) {
+            return respond(&ctx, &command, "Only admins can manage the scheduled jobs.").await;
+        }
+
+        let subcommand = match command.data.options.first() {
+            Some(subcommand) => subcommand,
+            None => return Ok(()),
+        };
+        let scheduler = match self.scheduler.get() {
+            Some(scheduler) => scheduler,
+            None => return respond(&ctx, &command, "The scheduler is not running yet.").await,
+        };
+
+        if subcommand.name == "list" {
+            let states = self.db.get_job_states().await?;
+            let embed = build_jobs_embed(scheduler.jobs(), &states, self.clock.now());
+
+            command
+                .create_interaction_response(&ctx.http, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|m| {
+                            m.flags(MessageFlags::EPHEMERAL).add_embed(embed)
+                        })
+                })
+                .await?;
+            return Ok(());
+        }
+
+        let name = get_string_option(&subcommand.options, "job").unwrap_or_default();
+        let job = match scheduler.job(name) {
+            Some(job) => job,
+            None => return respond(&ctx, &command, &format!("There is no job `{}`.", name)).await,
+        };
+
+        let content = match subcommand.name.as_str() {
+            "run" => {
+                let dry_run = subcommand
+                    .options
+                    .iter()
+                    .find(|option| option.name == "dry_run")
+                    .and_then(|option| option.value.as_ref())
+                    .and_then(|value| value.as_bool())
+                    .unwrap_or(false);
+
+                // The job may take longer than the interaction deadline
+                command
+                    .create_interaction_response(&ctx.http, |r| {
+                        r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                            .interaction_response_data(|m| m.flags(MessageFlags::EPHEMERAL))
+                    })
+                    .await?;
+
+                let content = match scheduler.run_now(job.as_ref(), dry_run).await {
+                    Some(Ok(summary)) if dry_run => {
+                        format!("🔍 Dry run of `{}`: {}", job.name(), summary)
+                    }
+                    Some(Ok(summary)) => format!("✅ `{}` succeeded: {}", job.name(), summary),
+                    Some(Err(error)) => format!("❌ `{}` failed: {}", job.name(), error),
+                    None => format!(
+                        "`{}` is running on another instance. Please try again later.",
+                        job.name()
+                    ),
+                };
+                command
+                    .edit_original_interaction_response(&ctx.http, |r| r.content(content))
+                    .await?;
+                return Ok(());
+            }
+            "pause" => {
+                self.db.set_job_paused(job.name(), true).await?;
+                format!(
+                    "⏸️ `{}` is paused, its scheduled runs are skipped until it is resumed.",
+                    job.name()
+                )
+            }
+            "resume" => {
+                self.db.set_job_paused(job.name(), false).await?;
+                format!("▶️ `{}` is resumed.", job.name())
+            }
+            _ => return Ok(()),
+        };
+
+        respond(&ctx, &command, &content).await
+    }
+}
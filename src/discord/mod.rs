@@ -1,4 +1,8 @@
+pub mod admin;
 pub mod commands;
 pub mod embeds;
 pub mod handler;
+pub mod history;
+pub mod jobs;
 pub mod slash;
+pub mod wallet;
@@ -1,71 +1,138 @@
 use serenity::builder;
 use serenity::model::application::command::CommandOptionType;
+use serenity::model::Permissions;
+
+use crate::database::models::LottoRules;
+use crate::util::lotto_option_name;
+
+// The most items a user can exchange at once
+pub const MAX_EXCHANGE_QUANTITY: i64 = 1000;
 
 pub fn exchange(
     command: &mut builder::CreateApplicationCommand,
 ) -> &mut builder::CreateApplicationCommand {
     command
         .name("exchange")
-        .description("Exchange tickets")
+        .description("Exchange points for items")
         .create_option(|option| {
             option
-                .name("wallet_address")
-                .description("Your wallet address")
+                .name("item")
+                .description("The item to exchange")
                 .kind(CommandOptionType::String)
+                .set_autocomplete(true)
                 .required(true)
         })
         .create_option(|option| {
             option
-                .name("number_of_tickets")
-                .description("Number of tickets to exchange")
+                .name("quantity")
+                .description("Number of items to exchange")
                 .kind(CommandOptionType::Integer)
                 .min_int_value(1)
-                .max_int_value(256)
+                .max_int_value(MAX_EXCHANGE_QUANTITY)
                 .required(true)
         })
+        .create_option(|option| {
+            option
+                .name("wallet_address")
+                .description("Your wallet address, the registered wallet is used if omitted")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
 }
 
-pub fn lotto(
+pub fn wallet(
     command: &mut builder::CreateApplicationCommand,
 ) -> &mut builder::CreateApplicationCommand {
     command
-        .name("lotto")
-        .description("Weekly Lottery")
+        .name("wallet")
+        .description("Manage your default wallet for the exchange")
         .create_option(|option| {
             option
-                .name("1st_number")
-                .description("The first number")
-                .kind(CommandOptionType::Integer)
-                .min_int_value(0)
-                .max_int_value(9)
-                .required(true)
+                .name("set")
+                .description("Register your default wallet")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("wallet_address")
+                        .description("Your wallet address")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("signature")
+                        .description("The signed verification message to prove the ownership")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
         })
         .create_option(|option| {
             option
-                .name("2nd_number")
-                .description("The second number")
-                .kind(CommandOptionType::Integer)
-                .min_int_value(0)
-                .max_int_value(9)
-                .required(true)
+                .name("show")
+                .description("Show your registered wallet and the verification message")
+                .kind(CommandOptionType::SubCommand)
         })
+}
+
+// The number options follow the digits of the current round
+pub fn lotto<'a>(
+    command: &'a mut builder::CreateApplicationCommand,
+    rules: &LottoRules,
+) -> &'a mut builder::CreateApplicationCommand {
+    // A free period may allow more entries than usual
+    let max_tickets = rules
+        .free_periods
+        .iter()
+        .filter_map(|period| period.entries_per_week)
+        .fold(rules.entries_per_week, u64::max)
+        .max(1);
+    command
+        .name("lotto")
+        .description("Weekly Lottery")
         .create_option(|option| {
             option
-                .name("3rd_number")
-                .description("The third number")
-                .kind(CommandOptionType::Integer)
-                .min_int_value(0)
-                .max_int_value(9)
-                .required(true)
+                .name("pick")
+                .description("Choose your numbers")
+                .kind(CommandOptionType::SubCommand);
+            for position in 1..=rules.digits {
+                option.create_sub_option(|sub_option| {
+                    sub_option
+                        .name(lotto_option_name(position))
+                        .description(format!("The number at position {}", position))
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(0)
+                        .max_int_value(9)
+                        .required(true)
+                });
+            }
+            option.create_sub_option(|sub_option| {
+                sub_option
+                    .name("save_favorite")
+                    .description("Save these numbers to replay them with /lotto favorite")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
         })
         .create_option(|option| {
             option
-                .name("4th_number")
-                .description("The fourth number")
-                .kind(CommandOptionType::Integer)
-                .min_int_value(0)
-                .max_int_value(9)
-                .required(true)
+                .name("quick-pick")
+                .description("Get random numbers")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("tickets")
+                        .description("The number of tickets to buy, each with its own numbers")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(1)
+                        .max_int_value(max_tickets)
+                        .required(false)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("favorite")
+                .description("Replay your saved numbers")
+                .kind(CommandOptionType::SubCommand)
         })
 }
 
@@ -92,3 +159,317 @@ pub fn check_lotto(
         .name("checklotto")
         .description("This week's lotto guesses")
 }
+
+pub fn lotto_mention(
+    command: &mut builder::CreateApplicationCommand,
+) -> &mut builder::CreateApplicationCommand {
+    command
+        .name("lotto-mention")
+        .description("Choose whether you are mentioned in the lotto results when you win")
+        .create_option(|option| {
+            option
+                .name("enabled")
+                .description("Mention me in the lotto results")
+                .kind(CommandOptionType::Boolean)
+                .required(true)
+        })
+}
+
+pub fn lotto_history(
+    command: &mut builder::CreateApplicationCommand,
+) -> &mut builder::CreateApplicationCommand {
+    command
+        .name("lotto-history")
+        .description("Browse the past lotto draws and your entries")
+        .create_option(|option| {
+            option
+                .name("draws")
+                .description("The winning numbers, winners and points paid of the past draws")
+                .kind(CommandOptionType::SubCommand)
+        })
+        .create_option(|option| {
+            option
+                .name("entries")
+                .description("Your lotto entries and their results")
+                .kind(CommandOptionType::SubCommand)
+        })
+}
+
+pub fn attend(
+    command: &mut builder::CreateApplicationCommand,
+) -> &mut builder::CreateApplicationCommand {
+    command
+        .name("attend")
+        .description("Check-in to PlayDapp Discord daily")
+}
+
+pub fn rank(
+    command: &mut builder::CreateApplicationCommand,
+) -> &mut builder::CreateApplicationCommand {
+    command
+        .name("rank")
+        .description("The Cumulative Points TOP 10 Leaderboard")
+}
+
+pub fn my_rank(
+    command: &mut builder::CreateApplicationCommand,
+) -> &mut builder::CreateApplicationCommand {
+    command
+        .name("myrank")
+        .description("Check your point ranking")
+}
+
+pub fn exchange_admin(
+    command: &mut builder::CreateApplicationCommand,
+) -> &mut builder::CreateApplicationCommand {
+    command
+        .name("exchange-admin")
+        .description("Review the exchange requests")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .dm_permission(false)
+        .create_option(|option| {
+            option
+                .name("pending")
+                .description("List the pending exchange requests")
+                .kind(CommandOptionType::SubCommand)
+        })
+        .create_option(|option| {
+            option
+                .name("approve")
+                .description("Approve an exchange request")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("exchange_id")
+                        .description("The ID of the exchange request")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("reject")
+                .description("Reject an exchange request and refund the points")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("exchange_id")
+                        .description("The ID of the exchange request")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("reason")
+                        .description("The reason shown to the user")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("export")
+                .description("Export the exchange requests between the dates as a CSV file")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("from")
+                        .description("The first day (UTC) in YYYY-MM-DD format")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("to")
+                        .description("The last day (UTC) in YYYY-MM-DD format")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("import")
+                .description("Import the fulfilment results to complete the processing requests")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("results")
+                        .description(
+                            "A CSV or JSON file with the exchangeId, status, txHash and error",
+                        )
+                        .kind(CommandOptionType::Attachment)
+                        .required(true)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("items")
+                .description("List the catalog items")
+                .kind(CommandOptionType::SubCommand)
+        })
+        .create_option(|option| {
+            option
+                .name("item")
+                .description("Add or replace a catalog item")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("id")
+                        .description("The ID of the item, e.g. ticket")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("name")
+                        .description("The name shown to the users")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("price")
+                        .description("The points for one item")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(1)
+                        .required(true)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("weekly_limit")
+                        .description(
+                            "The quantity a user can exchange per week, unlimited if omitted",
+                        )
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(1)
+                        .required(false)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("stock")
+                        .description("The quantity in stock, unlimited if omitted")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(0)
+                        .required(false)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("starts")
+                        .description("The first day (UTC) in YYYY-MM-DD format")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("ends")
+                        .description("The day (UTC) the item is removed in YYYY-MM-DD format")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("wallet")
+                .description("Show the wallet changes of a user")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("user")
+                        .description("The user to look up")
+                        .kind(CommandOptionType::User)
+                        .required(true)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("points")
+                .description("Compare the stored points with the points ledger")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("user")
+                        .description("The user to check, every user with a drift if omitted")
+                        .kind(CommandOptionType::User)
+                        .required(false)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("refund")
+                .description("Refund the points of a rejected exchange request")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("exchange_id")
+                        .description("The ID of the exchange request")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+        })
+}
+
+// The jobs are listed as choices, so the command follows the registered jobs
+pub fn job_admin<'a>(
+    command: &'a mut builder::CreateApplicationCommand,
+    job_names: &[&str],
+) -> &'a mut builder::CreateApplicationCommand {
+    let job_option = |sub_option: &mut builder::CreateApplicationCommandOption| {
+        sub_option
+            .name("job")
+            .description("The name of the job")
+            .kind(CommandOptionType::String)
+            .required(true);
+        for name in job_names {
+            sub_option.add_string_choice(name, name);
+        }
+    };
+
+    command
+        .name("job-admin")
+        .description("Inspect and control the scheduled jobs")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .dm_permission(false)
+        .create_option(|option| {
+            option
+                .name("list")
+                .description("List the jobs with their next run and last result")
+                .kind(CommandOptionType::SubCommand)
+        })
+        .create_option(|option| {
+            option
+                .name("run")
+                .description("Run a job now")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    job_option(sub_option);
+                    sub_option
+                })
+                .create_sub_option(|sub_option| {
+                    sub_option
+                        .name("dry_run")
+                        .description("Only report what the job would do")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("pause")
+                .description("Skip the scheduled runs of a job until it is resumed")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    job_option(sub_option);
+                    sub_option
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("resume")
+                .description("Resume the scheduled runs of a paused job")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|sub_option| {
+                    job_option(sub_option);
+                    sub_option
+                })
+        })
+}
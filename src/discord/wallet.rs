@@ -0,0 +1,143 @@
+use chrono::Duration;
+use serenity::{
+    model::prelude::interaction::application_command::{
+        ApplicationCommandInteraction, CommandDataOption,
+    },
+    model::prelude::ChannelId,
+    prelude::Context,
+};
+use tracing::error;
+
+use super::admin::{get_string_option, respond};
+use super::handler::Handler;
+use crate::database::models::{WalletAudit, WalletUpdate};
+use crate::util::{checksum_address, verify_wallet_signature, wallet_verification_message};
+
+impl Handler {
+    pub async fn handle_wallet(
+        &self,
+        ctx: Context,
+        command: ApplicationCommandInteraction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let subcommand = match command.data.options.first() {
+            Some(subcommand) => subcommand,
+            None => return Ok(()),
+        };
+
+        let content = match subcommand.name.as_str() {
+            "set" => self.set_wallet(&ctx, &command, subcommand).await?,
+            "show" => self.show_wallet(&command).await?,
+            _ => return Ok(()),
+        };
+
+        respond(&ctx, &command, &content).await
+    }
+
+    async fn set_wallet(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+        subcommand: &CommandDataOption,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let address = match get_string_option(&subcommand.options, "wallet_address")
+            .and_then(checksum_address)
+        {
+            Some(address) => address,
+            None => return Ok("Invalid wallet address! Please try again.".to_string()),
+        };
+
+        // The signature is optional, but a wrong one is rejected rather than ignored
+        let verified = match get_string_option(&subcommand.options, "signature") {
+            Some(signature) => {
+                if !verify_wallet_signature(command.user.id, &address, signature) {
+                    return Ok(format!(
+                        "The signature does not match the wallet. Please sign exactly this message with **{}**:\n```\n{}\n```",
+                        address,
+                        wallet_verification_message(command.user.id, &address)
+                    ));
+                }
+                true
+            }
+            None => false,
+        };
+
+        let user_name = match &command.member {
+            Some(member) => member.nick.as_deref().unwrap_or(&member.user.name),
+            None => &command.user.name,
+        };
+        let user_id = command.user.id.to_string();
+        let cooldown = Duration::days(self.config.wallet_cooldown_days);
+
+        match self
+            .db
+            .set_user_wallet(&user_id, user_name, &address, verified, cooldown)
+            .await?
+        {
+            WalletUpdate::Changed(audit) => {
+                self.notify_wallet_change(ctx, &audit).await;
+                Ok(format!(
+                    "Your wallet **{}** is registered{} ✅\nIt is used by `/exchange` when no wallet address is given.",
+                    address,
+                    if audit.verified { " and verified" } else { "" }
+                ))
+            }
+            WalletUpdate::Unchanged => Ok(format!(
+                "Your wallet **{}** is already registered.",
+                address
+            )),
+            WalletUpdate::Cooldown => {
+                let next_change = self
+                    .db
+                    .get_user_wallet(&user_id)
+                    .await?
+                    .map(|wallet| (wallet.updated_at + cooldown).timestamp())
+                    .unwrap_or_default();
+                Ok(format!(
+                    "Your wallet was changed recently. You can change it again <t:{}:R>.",
+                    next_change
+                ))
+            }
+        }
+    }
+
+    async fn show_wallet(
+        &self,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let user_id = command.user.id.to_string();
+        let content = match self.db.get_user_wallet(&user_id).await? {
+            Some(wallet) => format!(
+                "Your registered wallet is **{}** ({}).\nTo prove the ownership, sign this message with the wallet and run `/wallet set` with the signature:\n```\n{}\n```",
+                wallet.address,
+                if wallet.verified { "verified ✅" } else { "not verified" },
+                wallet_verification_message(command.user.id, &wallet.address)
+            ),
+            None => "You have no registered wallet. Use `/wallet set` to register one.".to_string(),
+        };
+
+        Ok(content)
+    }
+
+    // Every change is posted to the admin channel, a failure is only logged
+    async fn notify_wallet_change(&self, ctx: &Context, audit: &WalletAudit) {
+        let content = format!(
+            "🔐 <@{}> changed the wallet from **{}** to **{}** ({}).",
+            audit.user_id,
+            audit.previous_address.as_deref().unwrap_or("none"),
+            audit.address,
+            if audit.verified {
+                "verified"
+            } else {
+                "not verified"
+            }
+        );
+        if let Err(why) = ChannelId(self.config.admin_channel)
+            .send_message(&ctx.http, |m| {
+                m.content(content).allowed_mentions(|am| am.empty_parse())
+            })
+            .await
+        {
+            error!("Error sending the wallet change: {:?}", why);
+        }
+    }
+}
@@ -1,12 +1,12 @@
 use std::sync::Arc;
 
 use tracing::{error, info, Level};
-use tracing_subscriber;
 
+use discord_playdapp_bot::clock::{Clock, SystemClock};
 use discord_playdapp_bot::config::Config;
 use discord_playdapp_bot::database::mongo::MongoDB;
+use discord_playdapp_bot::database::store::Store;
 use discord_playdapp_bot::discord::handler::run_discord_bot;
-use discord_playdapp_bot::scheduler;
 
 #[tokio::main]
 async fn main() {
@@ -18,21 +18,41 @@ async fn main() {
         .await
         .expect("Failed to read configuration file");
 
+    // Every part of the bot reads the time from the same clock
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
     // Connect to the database
-    let db = MongoDB::new(&config.mongo_uri)
+    let db = MongoDB::new(&config.mongo_uri, Arc::clone(&clock))
         .await
         .expect("Failed to connect to database");
     info!("Connected to database");
 
-    // Setup the schedulers
-    let scheduler_db = db.clone();
-    tokio::spawn(async move {
-        scheduler::setup_scheduler(scheduler_db).await;
-    });
+    // Open the points ledger for the users who had points before it was introduced
+    match db.open_points_ledger().await {
+        Ok(opened) => info!("Opened the points ledger for {} users", opened),
+        Err(e) => error!("Failed to open the points ledger: {}", e),
+    }
+
+    // Move the lotto documents stored with the calendar year to their ISO round
+    match db.open_lotto_rounds().await {
+        Ok(0) => {}
+        Ok(moved) => info!("Moved {} lotto documents to their ISO round", moved),
+        Err(e) => error!(
+            "Failed to move the lotto documents to their ISO round: {}",
+            e
+        ),
+    }
+
+    // Offer the tournament ticket when the catalog has not been set up yet
+    match db.open_catalog().await {
+        Ok(true) => info!("Opened the catalog with the tournament ticket"),
+        Ok(false) => {}
+        Err(e) => error!("Failed to open the catalog: {}", e),
+    }
 
     // Run the Discord bot
     let token = config.discord_token.clone();
-    let discord_bot_handle = run_discord_bot(&token, Arc::new(db), Arc::new(config)).await;
+    let discord_bot_handle = run_discord_bot(&token, Arc::new(db), Arc::new(config), clock).await;
     if let Err(why) = discord_bot_handle.await {
         error!("An error occurred while connecting to Discord: {}", why);
     }
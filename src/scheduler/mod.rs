@@ -0,0 +1,658 @@
+pub mod runner;
+
+use crate::{
+    clock::Clock,
+    config::EnvConfig,
+    database::{
+        models::{Exchange, ExchangeStatus, JobFence, LottoRound},
+        store::Store,
+    },
+    discord::{embeds::build_lotto_results_embed, slash},
+    util::{notify_error, send_dm, send_exchange_csv, send_exchange_dm},
+};
+use chrono::{NaiveDate, Utc};
+use serenity::{
+    async_trait,
+    http::Http,
+    model::{application::command::Command, id::ChannelId},
+};
+use std::{error::Error, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+use runner::{CatchUp, Job, JobContext, JobResult, JobScheduler, RetryPolicy};
+
+// The number of attempts to send a lotto prize DM before the admins are told
+const MAX_LOTTO_DM_ATTEMPTS: i32 = 3;
+
+// Registers the jobs of the bot and starts them
+pub fn setup_scheduler(
+    database: Arc<dyn Store>,
+    config: Arc<EnvConfig>,
+    http: Arc<Http>,
+    clock: Arc<dyn Clock>,
+) -> Arc<JobScheduler> {
+    let mut scheduler = JobScheduler::new(JobContext {
+        database,
+        config,
+        http,
+        clock,
+        fence: None,
+    });
+    scheduler.register(ExchangeProcessingJob);
+    scheduler.register(ExchangeReminderJob);
+    scheduler.register(CleanupJob);
+    scheduler.register(LottoDrawJob);
+    scheduler.register(LottoPayoutJob);
+    scheduler.register(LottoAnnouncementJob);
+    scheduler.register(DailyReportJob);
+
+    let scheduler = Arc::new(scheduler);
+    scheduler.start();
+    scheduler
+}
+
+// Moves the submitted exchanges to processing and posts them for the fulfilment
+pub struct ExchangeProcessingJob;
+
+#[async_trait]
+impl Job for ExchangeProcessingJob {
+    fn name(&self) -> &'static str {
+        "exchange-processing"
+    }
+
+    fn description(&self) -> &'static str {
+        "Moves the submitted exchanges to processing and posts the batch"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "0 1 0 * * 5"
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::until_success(Duration::from_secs(300))
+    }
+
+    async fn run(&self, context: &JobContext, dry_run: bool) -> JobResult {
+        if dry_run {
+            let records = context
+                .database
+                .get_exchanges_by_status(&[ExchangeStatus::Submitted], 0)
+                .await?;
+            return Ok(format!(
+                "{} exchange(s) would be moved to processing",
+                records.len()
+            ));
+        }
+
+        let batch = context
+            .database
+            .update_all_submitted_to_processing(context.fence)
+            .await?;
+        send_processing_batch(
+            context.http.clone(),
+            &context.config,
+            &batch,
+            context.clock.now(),
+        )
+        .await;
+
+        // A closed DM should not fail the job, so the users who missed it are only counted
+        let mut dm_failures = 0;
+        for exchange in &batch {
+            if let Err(why) = send_exchange_dm(context.http.clone(), exchange).await {
+                error!(
+                    "Error sending the exchange DM to {}: {:?}",
+                    exchange.dc_id, why
+                );
+                dm_failures += 1;
+            }
+        }
+
+        Ok(format!(
+            "{} exchange(s) moved to processing, {} DM(s) failed",
+            batch.len(),
+            dm_failures
+        ))
+    }
+}
+
+// The records are completed by the imported fulfilment results,
+// so only remind the admins of the ones still waiting for them
+pub struct ExchangeReminderJob;
+
+#[async_trait]
+impl Job for ExchangeReminderJob {
+    fn name(&self) -> &'static str {
+        "exchange-reminder"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reminds the admins of the exchanges still processing"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "0 1 0 * * 6"
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::until_success(Duration::from_secs(300))
+    }
+
+    async fn run(&self, context: &JobContext, dry_run: bool) -> JobResult {
+        let records = context
+            .database
+            .get_exchanges_by_status(&[ExchangeStatus::Processing], 0)
+            .await?;
+        if !records.is_empty() && !dry_run {
+            notify_error(
+                context.http.clone(),
+                ChannelId(context.config.admin_channel),
+                format!(
+                    "{} exchange request(s) are still processing. Please import the fulfilment results with `/exchange-admin import`.",
+                    records.len()
+                ),
+                context.clock.now(),
+            )
+            .await;
+        }
+
+        Ok(format!(
+            "{} exchange(s) are still processing",
+            records.len()
+        ))
+    }
+}
+
+// Removes the old documents on the first day of every month
+pub struct CleanupJob;
+
+#[async_trait]
+impl Job for CleanupJob {
+    fn name(&self) -> &'static str {
+        "cleanup"
+    }
+
+    fn description(&self) -> &'static str {
+        "Removes the old activity documents"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "0 0 0 1 * *"
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::until_success(Duration::from_secs(300))
+    }
+
+    async fn run(&self, context: &JobContext, dry_run: bool) -> JobResult {
+        if dry_run {
+            let count = context.database.count_old_documents().await?;
+            return Ok(format!("{} documents would be deleted", count));
+        }
+
+        let deleted = context.database.clean_documents().await?;
+
+        Ok(format!("Deleted {} documents", deleted))
+    }
+}
+
+// Draws the last round and opens the new one at 00:00 on every Monday
+pub struct LottoDrawJob;
+
+#[async_trait]
+impl Job for LottoDrawJob {
+    fn name(&self) -> &'static str {
+        "lotto-draw"
+    }
+
+    fn description(&self) -> &'static str {
+        "Draws the past lotto rounds and opens the new one"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "0 0 0 * * 2"
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::until_success(Duration::from_secs(60))
+    }
+
+    async fn run(&self, context: &JobContext, dry_run: bool) -> JobResult {
+        if dry_run {
+            let round = LottoRound::at(context.clock.now());
+            let undrawn = context.database.get_undrawn_lotto_rounds(round).await?;
+            let last = if undrawn.is_empty() {
+                "No round is waiting for its draw".to_string()
+            } else {
+                let rounds: Vec<String> = undrawn.iter().map(ToString::to_string).collect();
+                format!("{} would be drawn", rounds.join(", "))
+            };
+            let current = match context.database.get_lotto_round(round).await? {
+                Some(_) => format!("{} is already open", round),
+                None => format!("{} would be opened", round),
+            };
+            return Ok(format!("{}, {}", last, current));
+        }
+
+        draw_and_open_lotto_round(
+            &context.config,
+            context.database.as_ref(),
+            context.http.clone(),
+            context.clock.now(),
+            context.fence,
+        )
+        .await?;
+
+        Ok("Drew the past rounds and opened the new one".to_string())
+    }
+}
+
+// Pays the lotto winners at 02:58 on every Monday
+pub struct LottoPayoutJob;
+
+#[async_trait]
+impl Job for LottoPayoutJob {
+    fn name(&self) -> &'static str {
+        "lotto-payout"
+    }
+
+    fn description(&self) -> &'static str {
+        "Pays the lotto winners of the last week and sends them a DM"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "0 58 2 * * 2"
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::until_success(Duration::from_secs(60))
+    }
+
+    async fn run(&self, context: &JobContext, dry_run: bool) -> JobResult {
+        if dry_run {
+            return preview_last_week_lotto_payout(context.database.as_ref(), context.clock.now())
+                .await;
+        }
+
+        process_last_week_lotto_guesses(
+            &context.config,
+            context.database.as_ref(),
+            context.http.clone(),
+            context.clock.now(),
+            context.fence,
+        )
+        .await?;
+
+        Ok("Paid the winners of the last week".to_string())
+    }
+}
+
+// Announces the lotto results at 03:00 on every Monday
+pub struct LottoAnnouncementJob;
+
+#[async_trait]
+impl Job for LottoAnnouncementJob {
+    fn name(&self) -> &'static str {
+        "lotto-announcement"
+    }
+
+    fn description(&self) -> &'static str {
+        "Announces the lotto results of the last week"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "0 0 3 * * 2"
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::attempts(3, Duration::from_secs(60))
+    }
+
+    async fn run(&self, context: &JobContext, dry_run: bool) -> JobResult {
+        send_announcement_lotto_results(
+            &context.config,
+            context.database.as_ref(),
+            context.http.clone(),
+            context.clock.now(),
+            dry_run,
+        )
+        .await?;
+
+        if dry_run {
+            return Ok("Sent a preview of the results to the admin channel".to_string());
+        }
+        Ok("Announced the results of the last week".to_string())
+    }
+}
+
+// Reports the uptime to the admin channel every day
+pub struct DailyReportJob;
+
+#[async_trait]
+impl Job for DailyReportJob {
+    fn name(&self) -> &'static str {
+        "daily-report"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reports the uptime to the admin channel"
+    }
+
+    // At 9:00 AM (UTC + 9) every day
+    fn schedule(&self) -> &'static str {
+        "0 0 0 * * *"
+    }
+
+    fn catch_up(&self) -> CatchUp {
+        CatchUp::Skip
+    }
+
+    async fn run(&self, context: &JobContext, dry_run: bool) -> JobResult {
+        // The specific date (January 31, 2023)
+        let started_day = NaiveDate::from_ymd_opt(2023, 1, 31).unwrap();
+        // Calculate the duratioin from the started day until now
+        let days = context
+            .clock
+            .now()
+            .date_naive()
+            .signed_duration_since(started_day)
+            .num_days();
+        if dry_run {
+            return Ok(format!("{} days of uptime would be reported", days));
+        }
+
+        let message = format!(
+            "🗣️ The Points System has run for **{}** days without crashing.🥳",
+            days
+        );
+        // Send the embed message to the channel
+        ChannelId(context.config.admin_channel)
+            .send_message(&context.http, |m| {
+                m.embed(|e| {
+                    e.title("Application Uptime");
+                    e.description(&message);
+                    e.color(0x00ff00);
+                    e.timestamp(context.clock.now().to_rfc3339())
+                })
+            })
+            .await?;
+
+        Ok(format!("Reported {} days of uptime", days))
+    }
+}
+
+// Closes every past round that is not drawn yet, oldest first, e.g. when the bot was down
+// over a draw, scoring its guesses against the numbers drawn from the seed. Then opens the
+// round of this week and publishes its commitment.
+pub async fn draw_and_open_lotto_round(
+    config: &EnvConfig,
+    database: &dyn Store,
+    http: Arc<Http>,
+    now: chrono::DateTime<Utc>,
+    fence: Option<JobFence>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut rounds = database
+        .get_undrawn_lotto_rounds(LottoRound::at(now))
+        .await?;
+    // The last round is closed again in case its scoring or its jackpot was interrupted
+    let last_round = LottoRound::at(now).previous();
+    if !rounds.contains(&last_round) {
+        rounds.push(last_round);
+    }
+    for round in rounds {
+        if database.close_lotto_round(round, fence).await?.is_none() {
+            info!("[Draw Generation] No lotto round for {}", round);
+        }
+    }
+
+    let round = database.add_weekly_draw().await?;
+
+    // The digits of the new round may differ from the last one
+    if let Err(why) = Command::create_global_application_command(&http, |command| {
+        slash::lotto(command, &round.rules())
+    })
+    .await
+    {
+        error!("Error updating the lotto command: {:?}", why);
+    }
+    let message = format!(
+        "**Weekly Lotto - Week {} 🎰**
+The entry period is open! The winning numbers will be drawn from a secret seed with this commitment, and from nothing else:
+`{}`
+The seed is revealed with the results, so anyone can check that `keccak256(seed)` matches the commitment and recompute the numbers from it. 🔐",
+        round.round.week,
+        round.commitment.unwrap_or_default()
+    );
+    if let Err(why) = ChannelId(config.lotto_channel).say(&http, message).await {
+        error!("Error publishing the lotto commitment: {:?}", why);
+    }
+
+    Ok(())
+}
+
+// Posts the exchanges moved to processing as a CSV file for the fulfilment
+async fn send_processing_batch(
+    http: Arc<Http>,
+    config: &EnvConfig,
+    batch: &[Exchange],
+    now: chrono::DateTime<Utc>,
+) {
+    let admin_channel = ChannelId(config.admin_channel);
+    let today = now.format("%Y-%m-%d");
+    let content = format!(
+        "📦 **{}** exchange request(s) moved to Processing on {}. Please send the tickets to the wallets below.",
+        batch.len(),
+        today
+    );
+
+    if let Err(why) = send_exchange_csv(
+        http.clone(),
+        admin_channel,
+        batch,
+        format!("exchange-batch-{}.csv", today),
+        content,
+    )
+    .await
+    {
+        error!("Failed to send the exchange batch: {:?}", why);
+        notify_error(
+            http,
+            admin_channel,
+            format!("Failed to send the exchange batch of {}: {}", today, why),
+            now,
+        )
+        .await;
+    }
+}
+
+// Counts the winners of the last week still to pay and to send a DM, without doing either
+async fn preview_last_week_lotto_payout(
+    database: &dyn Store,
+    now: chrono::DateTime<Utc>,
+) -> JobResult {
+    let last_round = LottoRound::at(now).previous();
+    if let Some(draw) = database.get_lotto_round(last_round).await? {
+        if draw.numbers.is_empty() {
+            return Ok(format!("The lotto round {} is not drawn yet", last_round));
+        }
+    }
+
+    let winners = database.get_lotto_guesses(last_round, None).await?;
+    let unpaid = winners
+        .iter()
+        .filter(|entry| entry.paid != Some(true) && entry.dm_sent != Some(true))
+        .count();
+    let undelivered = winners
+        .iter()
+        .filter(|entry| {
+            entry.dm_sent != Some(true) && entry.dm_attempts.unwrap_or(0) < MAX_LOTTO_DM_ATTEMPTS
+        })
+        .count();
+
+    Ok(format!(
+        "{} of {} winning ticket(s) of {} would be paid and {} DM(s) would be sent",
+        unpaid,
+        winners.len(),
+        last_round,
+        undelivered
+    ))
+}
+
+// Pays the winners of the last week and then sends them a DM. The payout is recorded per guess,
+// so retries never pay twice. A DM that keeps failing is reported to the admins instead of
+// blocking the other winners.
+pub async fn process_last_week_lotto_guesses(
+    config: &EnvConfig,
+    database: &dyn Store,
+    http: Arc<Http>,
+    now: chrono::DateTime<Utc>,
+    fence: Option<JobFence>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let last_round = LottoRound::at(now).previous();
+    // A run caught up after a restart may come before the draw, which scores the guesses
+    if let Some(draw) = database.get_lotto_round(last_round).await? {
+        if draw.numbers.is_empty() {
+            return Err(format!("The lotto round {} is not drawn yet", last_round).into());
+        }
+    }
+
+    // Pay every winner of the last week first, the ones already paid are skipped
+    for entry in database.get_lotto_guesses(last_round, None).await? {
+        if let Some(id) = entry.id {
+            database.pay_lotto_prize(id, fence).await?;
+        }
+    }
+
+    // Fetch all entries from the last week with is_any_matched set to true
+    let last_week_entries = database.get_lotto_guesses(last_round, Some(false)).await?;
+
+    let attendance_channel_id = config.attendance_channel;
+    let attendance_channel = ChannelId(attendance_channel_id);
+
+    let mut retried = 0;
+    let mut undelivered = Vec::new();
+    for entry in last_week_entries {
+        let id = match entry.id {
+            Some(id) if entry.dm_attempts.unwrap_or(0) < MAX_LOTTO_DM_ATTEMPTS => id,
+            _ => continue,
+        };
+
+        // Send DM to the user based on dc_id
+        match send_dm(http.clone(), entry.clone(), attendance_channel).await {
+            // Update the dm_sent flag to true for this entry
+            Ok(_) => database.update_dm_sent_flag(id).await?,
+            Err(why) => {
+                let attempts = database.add_lotto_dm_failure(id, &why.to_string()).await?;
+                if attempts < MAX_LOTTO_DM_ATTEMPTS {
+                    retried += 1;
+                } else {
+                    undelivered.push(format!("<@{}>: {}", entry.dc_id, why));
+                }
+            }
+        }
+    }
+
+    if !undelivered.is_empty() {
+        notify_error(
+            http,
+            ChannelId(config.admin_channel),
+            format!(
+                "The lotto prizes of {} were paid, but the DM could not be sent to:\n{}",
+                last_round,
+                undelivered.join("\n")
+            ),
+            now,
+        )
+        .await;
+    }
+
+    // The scheduler retries the failed DMs
+    if retried > 0 {
+        return Err(format!("{} lotto prize DM(s) failed and will be retried", retried).into());
+    }
+
+    Ok(())
+}
+
+// Announces the results of the last week in the lotto channel
+pub async fn send_announcement_lotto_results(
+    config: &EnvConfig,
+    database: &dyn Store,
+    http: Arc<Http>,
+    now: chrono::DateTime<Utc>,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let current_round = LottoRound::at(now);
+    let last_round = current_round.previous();
+
+    let round = match database.get_lotto_round(last_round).await {
+        Ok(Some(round)) if !round.numbers.is_empty() => round,
+        Ok(_) => return Err(format!("The lotto round {} is not drawn", last_round).into()),
+        Err(e) => {
+            error!("Error fetching lotto draw numbers: {}", e);
+            return Err(e);
+        }
+    };
+
+    let lotto_guesses = match database.get_lotto_guesses(last_round, None).await {
+        Ok(guesses) => guesses,
+        Err(e) => {
+            error!("Error fetching lotto guesses: {}", e);
+            return Err(e);
+        }
+    };
+
+    // Only the winners who opted in are mentioned, the others are counted
+    let winner_ids: Vec<u64> = lotto_guesses.iter().map(|guess| guess.dc_id).collect();
+    let mentionable = match database.get_lotto_mention_users(&winner_ids).await {
+        Ok(mentionable) => mentionable,
+        Err(e) => {
+            error!("Error fetching the lotto mention settings: {}", e);
+            Default::default()
+        }
+    };
+    let current_pot = match database.get_lotto_round(current_round).await {
+        Ok(current) => current.map(|current| current.jackpot_pool()),
+        Err(e) => {
+            error!("Error fetching the current lotto round: {}", e);
+            None
+        }
+    };
+
+    let embed = build_lotto_results_embed(
+        &round,
+        &lotto_guesses,
+        &mentionable,
+        current_pot,
+        ChannelId(config.attendance_channel),
+        now,
+    );
+
+    // A dry run sends the preview to the admins only
+    let channel = if dry_run {
+        ChannelId(config.admin_channel)
+    } else {
+        ChannelId(config.lotto_channel)
+    };
+    if let Err(why) = channel
+        .send_message(&http, |m| {
+            m.set_embed(embed)
+                .allowed_mentions(|mentions| mentions.empty_parse())
+        })
+        .await
+    {
+        notify_error(
+            http,
+            ChannelId(config.admin_channel),
+            format!(
+                "Failed to announce the lotto results of {}: {}",
+                last_round, why
+            ),
+            now,
+        )
+        .await;
+        return Err(Box::new(why));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,437 @@
+use chrono::Utc;
+use cron::Schedule;
+use serenity::{async_trait, http::Http, model::id::ChannelId};
+use std::{error::Error, str::FromStr, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+use crate::{
+    clock::Clock,
+    config::EnvConfig,
+    database::{models::JobFence, store::Store},
+    util::notify_error,
+};
+
+// The summary of a successful run, or the error of a failed one
+pub type JobResult = Result<String, Box<dyn Error + Send + Sync>>;
+
+// What every job gets to do its work
+#[derive(Clone)]
+pub struct JobContext {
+    pub database: Arc<dyn Store>,
+    pub config: Arc<EnvConfig>,
+    pub http: Arc<Http>,
+    pub clock: Arc<dyn Clock>,
+    // The lease of the run, None outside of a run under the lease
+    pub fence: Option<JobFence>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    // None retries until the run succeeds
+    pub max_attempts: Option<u32>,
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn once() -> Self {
+        RetryPolicy {
+            max_attempts: Some(1),
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn attempts(max_attempts: u32, delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: Some(max_attempts),
+            delay,
+        }
+    }
+
+    pub fn until_success(delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: None,
+            delay,
+        }
+    }
+}
+
+// What to do with the runs missed while the bot was down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUp {
+    // Wait for the next scheduled time
+    Skip,
+    // Run once at the start for all the missed times
+    RunOnce,
+}
+
+#[async_trait]
+pub trait Job: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    // The cron expression with seconds, in UTC
+    fn schedule(&self) -> &'static str;
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::once()
+    }
+
+    fn catch_up(&self) -> CatchUp {
+        CatchUp::RunOnce
+    }
+
+    // A dry run changes nothing, it only tells the admins what the job would do
+    async fn run(&self, context: &JobContext, dry_run: bool) -> JobResult;
+}
+
+// How long a lease stays valid without being renewed
+const LEASE_TTL: Duration = Duration::from_secs(300);
+// How often a running job renews its lease
+const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(60);
+// How long to wait before checking again a job run by another instance
+const LEASE_BUSY_DELAY: Duration = Duration::from_secs(60);
+
+// Runs the registered jobs on their schedule. The scheduled time of the last run of every job
+// is stored in the `jobs` collection, so a restart knows which runs were missed.
+// A run takes the lease of its job in the `jobleases` collection first, so when two instances
+// overlap during a deployment only one of them runs it. The token of the lease guards the record
+// of the run, and the jobs pass it to the lotto draw, the payouts and the exchange batch, so a
+// run that lost its lease cannot write them anymore.
+pub struct JobScheduler {
+    context: JobContext,
+    jobs: Vec<Arc<dyn Job>>,
+    // Identifies this instance as the owner of the leases
+    owner: String,
+}
+
+impl JobScheduler {
+    pub fn new(context: JobContext) -> Self {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+        JobScheduler {
+            context,
+            jobs: Vec::new(),
+            owner: format!(
+                "{}-{}-{:08x}",
+                host,
+                std::process::id(),
+                rand::random::<u32>()
+            ),
+        }
+    }
+
+    pub fn register(&mut self, job: impl Job + 'static) {
+        self.jobs.push(Arc::new(job));
+    }
+
+    pub fn jobs(&self) -> &[Arc<dyn Job>] {
+        &self.jobs
+    }
+
+    pub fn job(&self, name: &str) -> Option<Arc<dyn Job>> {
+        self.jobs.iter().find(|job| job.name() == name).cloned()
+    }
+
+    // Runs the job at once for an admin, without retrying. A dry run does not take the lease
+    // and is not recorded. Returns None if another instance is running the job.
+    pub async fn run_now(&self, job: &dyn Job, dry_run: bool) -> Option<Result<String, String>> {
+        if dry_run {
+            return Some(
+                job.run(&self.context, true)
+                    .await
+                    .map_err(|e| e.to_string()),
+            );
+        }
+
+        self.run_exclusive(job, None, RetryPolicy::once()).await
+    }
+
+    // Spawns a task for every job
+    pub fn start(self: &Arc<Self>) {
+        for job in self.jobs.iter().cloned() {
+            let scheduler = Arc::clone(self);
+            tokio::spawn(async move { scheduler.run_schedule(job).await });
+        }
+    }
+
+    async fn run_schedule(&self, job: Arc<dyn Job>) {
+        let schedule = match Schedule::from_str(job.schedule()) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                error!(
+                    "[{}] Invalid schedule {}: {}",
+                    job.name(),
+                    job.schedule(),
+                    e
+                );
+                return;
+            }
+        };
+
+        loop {
+            let scheduled_at = match self.next_run(job.as_ref(), &schedule).await {
+                Some(scheduled_at) => scheduled_at,
+                None => return,
+            };
+
+            if scheduled_at > self.context.clock.now() {
+                info!(
+                    "[{}] Waiting until next scheduled event: [{}]",
+                    job.name(),
+                    scheduled_at
+                );
+                if let Err(e) = self
+                    .context
+                    .database
+                    .set_job_next_run(job.name(), scheduled_at)
+                    .await
+                {
+                    error!("[{}] Failed to store the next run: {}", job.name(), e);
+                }
+                self.context.clock.sleep_until(scheduled_at).await;
+            }
+
+            if self.is_paused(job.as_ref()).await {
+                info!(
+                    "[{}] Skipping the run of [{}], the job is paused",
+                    job.name(),
+                    scheduled_at
+                );
+                if let Err(e) = self
+                    .context
+                    .database
+                    .skip_job_run(job.name(), scheduled_at)
+                    .await
+                {
+                    error!("[{}] Failed to record the skipped run: {}", job.name(), e);
+                }
+                continue;
+            }
+
+            if self
+                .run_exclusive(job.as_ref(), Some(scheduled_at), job.retry_policy())
+                .await
+                .is_none()
+            {
+                // Check again once the other instance may have recorded its run
+                self.context
+                    .clock
+                    .sleep(chrono::Duration::from_std(LEASE_BUSY_DELAY).unwrap())
+                    .await;
+            }
+        }
+    }
+
+    async fn is_paused(&self, job: &dyn Job) -> bool {
+        match self.context.database.get_job_state(job.name()).await {
+            Ok(state) => state.is_some_and(|state| state.paused == Some(true)),
+            Err(e) => {
+                error!("[{}] Failed to read the job state: {}", job.name(), e);
+                false
+            }
+        }
+    }
+
+    // Returns the latest missed time to catch up with, or the next scheduled time
+    async fn next_run(&self, job: &dyn Job, schedule: &Schedule) -> Option<chrono::DateTime<Utc>> {
+        let now = self.context.clock.now();
+        let state = match self.context.database.get_job_state(job.name()).await {
+            Ok(state) => state,
+            Err(e) => {
+                error!("[{}] Failed to read the job state: {}", job.name(), e);
+                None
+            }
+        };
+
+        if let Some(last_scheduled_at) = state.and_then(|state| state.last_scheduled_at) {
+            let missed = schedule
+                .after(&last_scheduled_at)
+                .take_while(|time| *time <= now)
+                .last();
+            match (missed, job.catch_up()) {
+                (Some(missed), CatchUp::RunOnce) => {
+                    info!("[{}] Catching up with the run of [{}]", job.name(), missed);
+                    return Some(missed);
+                }
+                (Some(missed), CatchUp::Skip) => {
+                    info!("[{}] Skipping the missed run of [{}]", job.name(), missed);
+                }
+                (None, _) => {}
+            }
+        }
+
+        schedule.after(&now).next()
+    }
+
+    // Runs the job under its lease and records the result. A run started by an admin has no
+    // scheduled time. Returns None if another instance holds the lease or it was lost during the run.
+    async fn run_exclusive(
+        &self,
+        job: &dyn Job,
+        scheduled_at: Option<chrono::DateTime<Utc>>,
+        policy: RetryPolicy,
+    ) -> Option<Result<String, String>> {
+        let database = &self.context.database;
+        let ttl = chrono::Duration::from_std(LEASE_TTL).unwrap();
+        let token = match database
+            .acquire_job_lease(job.name(), &self.owner, ttl)
+            .await
+        {
+            Ok(Some(token)) => token,
+            Ok(None) => {
+                info!("[{}] Another instance is running the job", job.name());
+                return None;
+            }
+            Err(e) => {
+                error!("[{}] Failed to acquire the lease: {}", job.name(), e);
+                return None;
+            }
+        };
+
+        // Another instance may have run the scheduled time and released the lease since
+        // this one read the job state
+        if let Some(scheduled_at) = scheduled_at {
+            let already_run = match database.get_job_state(job.name()).await {
+                Ok(state) => state
+                    .and_then(|state| state.last_scheduled_at)
+                    .is_some_and(|last_scheduled_at| last_scheduled_at >= scheduled_at),
+                Err(e) => {
+                    error!("[{}] Failed to read the job state: {}", job.name(), e);
+                    self.release_lease(job.name(), token).await;
+                    return None;
+                }
+            };
+            if already_run {
+                info!(
+                    "[{}] The run of [{}] is already recorded",
+                    job.name(),
+                    scheduled_at
+                );
+                self.release_lease(job.name(), token).await;
+                return Some(Ok(format!(
+                    "The run of {} is already recorded",
+                    scheduled_at
+                )));
+            }
+        }
+
+        let context = JobContext {
+            fence: Some(JobFence {
+                job: job.name(),
+                token,
+            }),
+            ..self.context.clone()
+        };
+        // The run is dropped as soon as the lease is lost, another instance may have taken it
+        let result = tokio::select! {
+            result = self.run_with_retry(&context, job, policy) => result,
+            _ = self.keep_lease(job.name(), token) => {
+                error!("[{}] Lost the lease of the run, token {}", job.name(), token);
+                return None;
+            }
+        };
+
+        match database
+            .record_job_run(job.name(), token, scheduled_at, &result)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => info!("[{}] A newer run is already recorded", job.name()),
+            Err(e) => error!("[{}] Failed to record the run: {}", job.name(), e),
+        }
+        self.release_lease(job.name(), token).await;
+
+        Some(result)
+    }
+
+    async fn release_lease(&self, name: &str, token: i64) {
+        if let Err(e) = self
+            .context
+            .database
+            .release_job_lease(name, &self.owner, token)
+            .await
+        {
+            error!("[{}] Failed to release the lease: {}", name, e);
+        }
+    }
+
+    // Renews the lease until it is lost, or could not be renewed before it expired
+    async fn keep_lease(&self, name: &str, token: i64) {
+        let ttl = chrono::Duration::from_std(LEASE_TTL).unwrap();
+        let interval = chrono::Duration::from_std(LEASE_RENEW_INTERVAL).unwrap();
+        let clock = &self.context.clock;
+        let mut renewed_at = clock.now();
+        loop {
+            clock.sleep(interval).await;
+            match self
+                .context
+                .database
+                .renew_job_lease(name, &self.owner, token, ttl)
+                .await
+            {
+                Ok(true) => renewed_at = clock.now(),
+                Ok(false) => return,
+                Err(e) => {
+                    error!("[{}] Failed to renew the lease: {}", name, e);
+                    if clock.now() - renewed_at + interval >= ttl {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Runs the job following its retry policy
+    async fn run_with_retry(
+        &self,
+        context: &JobContext,
+        job: &dyn Job,
+        policy: RetryPolicy,
+    ) -> Result<String, String> {
+        let admin_channel = ChannelId(context.config.admin_channel);
+
+        let mut attempt = 1;
+        loop {
+            match job.run(context, false).await {
+                Ok(summary) => {
+                    info!("[{}] {}", job.name(), summary);
+                    return Ok(summary);
+                }
+                Err(e) => {
+                    error!("[{}] Attempt {} failed: {}", job.name(), attempt, e);
+                    if policy.max_attempts.is_some_and(|max| attempt >= max) {
+                        notify_error(
+                            context.http.clone(),
+                            admin_channel,
+                            format!(
+                                "The job `{}` failed after {} attempt(s): {}",
+                                job.name(),
+                                attempt,
+                                e
+                            ),
+                            context.clock.now(),
+                        )
+                        .await;
+                        return Err(e.to_string());
+                    }
+
+                    // Tell the admins once, the next attempts are only logged
+                    if attempt == 1 {
+                        notify_error(
+                            context.http.clone(),
+                            admin_channel,
+                            format!("The job `{}` failed and will be retried: {}", job.name(), e),
+                            context.clock.now(),
+                        )
+                        .await;
+                    }
+                    attempt += 1;
+                    context
+                        .clock
+                        .sleep(chrono::Duration::from_std(policy.delay).unwrap())
+                        .await;
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,10 @@
-use chrono::{Datelike, NaiveDate, Utc, Weekday};
+use chrono::{Datelike, Utc};
 
+use ethers::types::{Address, Signature};
+use ethers::utils::{hex, keccak256, to_checksum};
 use lazy_static::lazy_static;
-use rand::Rng;
+use rand::rngs::OsRng;
+use rand::RngCore;
 use serenity::http::Http;
 use serenity::Error as SerenityError;
 use serenity::{
@@ -9,17 +12,48 @@ use serenity::{
     model::{channel::Message, id::UserId},
     prelude::*,
 };
+use std::borrow::Cow;
 use std::collections::HashSet;
+use std::str::FromStr;
 use std::sync::Arc;
 use tracing::info;
 
-use crate::database::models::LottoGuess;
+use crate::database::models::{
+    Exchange, ExchangeStatus, FulfilmentResult, FulfilmentStatus, LottoGuess, LottoRules,
+};
 
-pub fn is_thu() -> bool {
-    let now = Utc::now();
+pub fn is_thu(now: chrono::DateTime<Utc>) -> bool {
     now.weekday() == chrono::Weekday::Thu
 }
 
+// Parses the wallet address and converts it to the checksum format
+pub fn checksum_address(address: &str) -> Option<String> {
+    Address::from_str(address.trim())
+        .ok()
+        .map(|address| to_checksum(&address, None))
+}
+
+// The message a user signs with the wallet to prove the ownership
+pub fn wallet_verification_message(user_id: UserId, address: &str) -> String {
+    format!(
+        "PlayDapp Discord wallet verification\nDiscord ID: {}\nWallet: {}",
+        user_id, address
+    )
+}
+
+// Recovers the signer of the verification message and compares it with the wallet
+pub fn verify_wallet_signature(user_id: UserId, address: &str, signature: &str) -> bool {
+    let (Ok(address), Ok(signature)) = (
+        Address::from_str(address),
+        Signature::from_str(signature.trim()),
+    ) else {
+        return false;
+    };
+    let message = wallet_verification_message(user_id, &to_checksum(&address, None));
+
+    signature.verify(message, address).is_ok()
+}
+
 pub async fn filter_guilds(ctx: &Context, ready: Ready) {
     let allowed_guilds: Vec<u64> = vec![537515978561683466, 1019782712799805440];
     let guilds = ready.guilds.clone();
@@ -34,53 +68,67 @@ pub async fn filter_guilds(ctx: &Context, ready: Ready) {
     }
 }
 
-// Returns the current ISO week number as a tuple of (year, week number)
-pub fn get_week_number() -> (i32, u32) {
-    let today = Utc::now();
-    (today.year(), today.iso_week().week())
+// Generates the secret seed of a lotto round with the operating system CSPRNG
+pub fn generate_lotto_seed() -> String {
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    hex::encode(seed)
+}
+
+// The commitment published at the week open, which the revealed seed must hash to
+pub fn lotto_commitment(seed: &str) -> String {
+    hex::encode(keccak256(seed.as_bytes()))
 }
 
-// Generates a vector of 4 random numbers between 0 and 9
-pub fn generate_numbers() -> Vec<i32> {
-    let mut rng = rand::thread_rng();
-    let numbers: Vec<i32> = (0..4).map(|_| rng.gen_range(0..10)).collect();
+// Derives the winning numbers between 0 and 9 from the seed.
+// The bytes of keccak256("lotto-numbers:" + seed) are read in order and the ones below 250
+// are taken modulo 10, so that every number is equally likely.
+pub fn draw_lotto_numbers(seed: &str, digits: u32) -> Vec<i32> {
+    let digits = digits as usize;
+    let mut numbers = Vec::new();
+    let mut hash = keccak256(format!("lotto-numbers:{}", seed).as_bytes());
+    while numbers.len() < digits {
+        for byte in hash {
+            if byte < 250 && numbers.len() < digits {
+                numbers.push((byte % 10) as i32);
+            }
+        }
+        // Rehash when not enough bytes were below 250 or more numbers are needed
+        hash = keccak256(hash);
+    }
 
     numbers
 }
 
-// Returns a NaiveDate object for the Monday of the current week
-pub fn get_monday_of_week() -> NaiveDate {
-    let today = Utc::now();
-    // Calculate the date of Monday of the current week
-    let target_date = today
-        + chrono::Duration::days(
-            Weekday::Mon.num_days_from_sunday() as i64
-                - today.weekday().num_days_from_sunday() as i64,
-        );
-
-    target_date.date_naive()
+// Picks random numbers for a ticket the same way the winning numbers are drawn
+pub fn quick_pick_lotto_numbers(digits: u32) -> Vec<i32> {
+    draw_lotto_numbers(&generate_lotto_seed(), digits)
 }
 
 // Calculate the number of points a user gets in the lotte game.
-pub fn calculate_lotto_points(user_numbers: &[i32], draw_numbers: &[i32]) -> (usize, i32) {
+pub fn calculate_lotto_points(
+    user_numbers: &[i32],
+    draw_numbers: &[i32],
+    rules: &LottoRules,
+) -> (u32, i32) {
     let matches = user_numbers
         .iter()
         .zip(draw_numbers.iter())
         .filter(|(a, b)| a == b)
-        .count();
-    let points = points_for_matches(matches);
-    (matches, points)
-}
-
-// Calculate the number of points corresponding to a certain number of matches.
-fn points_for_matches(matches: usize) -> i32 {
-    match matches {
-        1 => 400,
-        2 => 1000,
-        3 => 5000,
-        4 => 100000,
-        _ => 0,
-    }
+        .count() as u32;
+    (matches, rules.prize_for(matches))
+}
+
+// The name of the lotto option for the number at the position, e.g. "1st_number"
+pub fn lotto_option_name(position: u32) -> String {
+    let suffix = match (position % 10, position % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}_number", position, suffix)
 }
 
 pub async fn send_dm(
@@ -106,7 +154,280 @@ pub async fn send_dm(
     dm_channel.send_message(&http, |m| m.content(content)).await
 }
 
-pub async fn notify_error(http: Arc<Http>, channel_id: ChannelId, mut message: String) {
+// Notifies the user that their exchange request changed its status
+pub async fn send_exchange_dm(
+    http: Arc<Http>,
+    exchange: &Exchange,
+) -> Result<Message, SerenityError> {
+    let dm_channel = UserId(exchange.dc_id).create_dm_channel(&http).await?;
+
+    let mut content = format!(
+        "Hello {}!👋🏻 Your request of exchanging the Discord points into **{} {}(s)** is now **{}**.",
+        exchange.dc_username, exchange.quantity, exchange.item, exchange.status
+    );
+    match exchange.status {
+        ExchangeStatus::Processing => {
+            content += "\nThe items will be sent to your wallet once the batch is fulfilled. 📦"
+        }
+        ExchangeStatus::Completed => content += "\nPlease check your wallet or Tournament page! 🎁",
+        ExchangeStatus::Rejected => {
+            if let Some(reason) = &exchange.reject_reason {
+                content += &format!("\nReason: {}", reason);
+            }
+            content += "\nYour points will be refunded shortly.";
+        }
+        ExchangeStatus::Refunded => {
+            content += "\nThe request was rejected.";
+            if let Some(reason) = &exchange.reject_reason {
+                content += &format!("\nReason: {}", reason);
+            }
+            content += &format!(
+                "\n**{}** points have been refunded to your balance. 💰",
+                exchange.refund_points()
+            )
+        }
+        _ => {}
+    }
+    content += "\nFor any inquiries, please contact the Discord Admin.🙌🏻";
+
+    dm_channel.send_message(&http, |m| m.content(content)).await
+}
+
+// A row of the fulfilment CSV with the requests of a single wallet for an item
+#[derive(Default)]
+struct WalletBatch {
+    wallet: String,
+    item: String,
+    quantity: i64,
+    dc_ids: Vec<String>,
+    usernames: Vec<String>,
+    exchange_ids: Vec<String>,
+}
+
+// Builds the fulfilment CSV of the exchanges, aggregated per checksummed wallet address and item
+pub fn build_exchange_csv(records: &[Exchange]) -> String {
+    // Keep the wallets in the order of their first request
+    let mut batches: Vec<WalletBatch> = Vec::new();
+    for record in records {
+        let raw_address = record.wallet_address.clone().unwrap_or_default();
+        let wallet = match Address::from_str(&raw_address) {
+            Ok(address) => to_checksum(&address, None),
+            Err(_) => raw_address,
+        };
+
+        let index = match batches
+            .iter()
+            .position(|batch| batch.wallet == wallet && batch.item == record.item)
+        {
+            Some(index) => index,
+            None => {
+                batches.push(WalletBatch {
+                    wallet,
+                    item: record.item.clone(),
+                    ..Default::default()
+                });
+                batches.len() - 1
+            }
+        };
+        let batch = &mut batches[index];
+        batch.quantity += record.quantity;
+        let dc_id = record.dc_id.to_string();
+        if !batch.dc_ids.contains(&dc_id) {
+            batch.dc_ids.push(dc_id);
+            batch.usernames.push(record.dc_username.clone());
+        }
+        batch
+            .exchange_ids
+            .push(record.id.map(|id| id.to_hex()).unwrap_or_default());
+    }
+
+    let mut csv = String::from("walletAddress,item,quantity,dcId,username,exchangeId\n");
+    for batch in batches {
+        let fields = [
+            escape_csv(&batch.wallet),
+            escape_csv(&batch.item),
+            batch.quantity.to_string(),
+            escape_csv(&batch.dc_ids.join(";")),
+            escape_csv(&batch.usernames.join(";")),
+            escape_csv(&batch.exchange_ids.join(";")),
+        ];
+        csv += &fields.join(",");
+        csv.push('\n');
+    }
+
+    csv
+}
+
+// Quotes the field if it contains a separator, a quote or a line break. A field that starts
+// like a formula, e.g. a username such as `=HYPERLINK(...)`, or with a tab or a carriage return
+// is prefixed with `'` so the spreadsheet shows it as text instead of evaluating it.
+fn escape_csv(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+// Parses the fulfilment results from a JSON array or a CSV file with the
+// `exchangeId,status,txHash,error` columns
+pub fn parse_fulfilment_results(content: &str) -> Result<Vec<FulfilmentResult>, String> {
+    let content = content.trim_start_matches('\u{feff}').trim();
+    if content.starts_with('[') {
+        let results: Vec<FulfilmentResult> =
+            serde_json::from_str(content).map_err(|e| format!("Invalid JSON: {}", e))?;
+        // A completed exchange must point to the transaction that delivered it
+        return match results.iter().position(|result| {
+            result.status == FulfilmentStatus::Completed
+                && result.tx_hash.as_deref().unwrap_or_default().is_empty()
+        }) {
+            Some(index) => Err(format!("Missing txHash in entry {}", index + 1)),
+            None => Ok(results),
+        };
+    }
+
+    let mut results = Vec::new();
+    for (index, (line, fields)) in parse_csv(content)?.into_iter().enumerate() {
+        // Skip the header and the empty lines
+        if fields.iter().all(|field| field.is_empty()) || (index == 0 && fields[0] == "exchangeId")
+        {
+            continue;
+        }
+
+        let status = match fields.get(1).map(|status| status.to_lowercase()).as_deref() {
+            Some("completed") => FulfilmentStatus::Completed,
+            Some("failed") => FulfilmentStatus::Failed,
+            _ => return Err(format!("Invalid status on line {}", line)),
+        };
+        let optional_field = |position: usize| {
+            fields
+                .get(position)
+                .filter(|field| !field.is_empty())
+                .cloned()
+        };
+
+        // A completed exchange must point to the transaction that delivered it
+        let tx_hash = optional_field(2);
+        if status == FulfilmentStatus::Completed && tx_hash.is_none() {
+            return Err(format!("Missing txHash on line {}", line));
+        }
+
+        results.push(FulfilmentResult {
+            exchange_id: fields[0].clone(),
+            status,
+            tx_hash,
+            error: optional_field(3),
+        });
+    }
+
+    Ok(results)
+}
+
+// Splits the CSV into records of fields with the line each record starts on. A quoted field
+// may contain separators, line breaks and quotes doubled as `""`; unquoted fields are trimmed.
+fn parse_csv(content: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.trim().is_empty() && !quoted => {
+                field.clear();
+                quoted = true;
+                in_quotes = true;
+            }
+            '"' => return Err(format!("Unexpected quote on line {}", line)),
+            ',' => fields.push(take_csv_field(&mut field, &mut quoted)),
+            '\r' => {}
+            '\n' => {
+                fields.push(take_csv_field(&mut field, &mut quoted));
+                records.push((record_line, std::mem::take(&mut fields)));
+                line += 1;
+                record_line = line;
+            }
+            // Only spaces may follow the closing quote
+            _ if quoted => {
+                if !c.is_whitespace() {
+                    return Err(format!("Unexpected text after a quote on line {}", line));
+                }
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("Unclosed quote on line {}", record_line));
+    }
+    fields.push(take_csv_field(&mut field, &mut quoted));
+    records.push((record_line, fields));
+
+    Ok(records)
+}
+
+fn take_csv_field(field: &mut String, quoted: &mut bool) -> String {
+    let value = if *quoted {
+        std::mem::take(field)
+    } else {
+        std::mem::take(field).trim().to_string()
+    };
+    *quoted = false;
+    value
+}
+
+pub async fn send_exchange_csv(
+    http: Arc<Http>,
+    channel_id: ChannelId,
+    records: &[Exchange],
+    filename: String,
+    content: String,
+) -> Result<Message, SerenityError> {
+    let csv = build_exchange_csv(records);
+
+    channel_id
+        .send_message(&http, |m| {
+            m.content(content).add_file(AttachmentType::Bytes {
+                data: Cow::from(csv.into_bytes()),
+                filename,
+            })
+        })
+        .await
+}
+
+pub async fn notify_error(
+    http: Arc<Http>,
+    channel_id: ChannelId,
+    mut message: String,
+    now: chrono::DateTime<chrono::Utc>,
+) {
     // add emoji at the end of the message
     message += " :warning:"; // Add emoji using its alias in markdown format
 
@@ -117,7 +438,7 @@ pub async fn notify_error(http: Arc<Http>, channel_id: ChannelId, mut message: S
                 e.title("Errors Notification");
                 e.description(&message);
                 e.color(0xFF0000); // Red color for errors
-                e.timestamp(chrono::Utc::now().to_rfc3339())
+                e.timestamp(now.to_rfc3339())
             })
         })
         .await;
@@ -146,3 +467,82 @@ lazy_static! {
     .into_iter()
     .collect();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_csv_neutralizes_formulas() {
+        let record = Exchange {
+            dc_id: 1,
+            dc_username: "=HYPERLINK(\"http://evil\",\"x\")".to_string(),
+            wallet_address: Some("@wallet".to_string()),
+            item: "+ticket".to_string(),
+            quantity: 2,
+            ..Default::default()
+        };
+
+        let csv = build_exchange_csv(&[record]);
+        let row = csv.lines().nth(1).unwrap();
+        assert_eq!(
+            row,
+            "'@wallet,'+ticket,2,1,\"'=HYPERLINK(\"\"http://evil\"\",\"\"x\"\")\",",
+        );
+    }
+
+    #[test]
+    fn fulfilment_csv_reads_quoted_fields() {
+        let content = "exchangeId,status,txHash,error\r\n\
+            a1,completed,0xabc,\n\
+            \"b2\" , failed ,,\"Out of gas, \"\"retry\"\"\nlater\"\n\
+            \n\
+            c3,FAILED,,\n";
+
+        let results = parse_fulfilment_results(content).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].exchange_id, "a1");
+        assert_eq!(results[0].tx_hash.as_deref(), Some("0xabc"));
+        assert_eq!(results[0].error, None);
+        assert_eq!(results[1].exchange_id, "b2");
+        assert_eq!(results[1].status, FulfilmentStatus::Failed);
+        assert_eq!(
+            results[1].error.as_deref(),
+            Some("Out of gas, \"retry\"\nlater")
+        );
+        assert_eq!(results[2].exchange_id, "c3");
+    }
+
+    #[test]
+    fn fulfilment_results_require_tx_hash_when_completed() {
+        assert_eq!(
+            parse_fulfilment_results("exchangeId,status\na1,failed\nb2,completed,\n").err(),
+            Some("Missing txHash on line 3".to_string())
+        );
+        assert_eq!(
+            parse_fulfilment_results(r#"[{"exchangeId":"a1","status":"completed"}]"#).err(),
+            Some("Missing txHash in entry 1".to_string())
+        );
+        assert!(parse_fulfilment_results("a1,completed,\"0xabc").is_err());
+    }
+
+    #[test]
+    fn round_numbers_follow_from_the_committed_seed() {
+        let seed = generate_lotto_seed();
+        let numbers = draw_lotto_numbers(&seed, 6);
+
+        assert_eq!(numbers.len(), 6);
+        assert!(numbers.iter().all(|number| (0..10).contains(number)));
+        // A redraw gives the same numbers, so they can be recomputed from the revealed seed
+        assert_eq!(numbers, draw_lotto_numbers(&seed, 6));
+    }
+
+    #[test]
+    fn escape_csv_keeps_plain_fields() {
+        assert_eq!(escape_csv("ticket"), "ticket");
+        assert_eq!(escape_csv("a,b"), "\"a,b\"");
+        assert_eq!(escape_csv("-1"), "'-1");
+        assert_eq!(escape_csv("\t=1+1"), "'\t=1+1");
+        assert_eq!(escape_csv("\r=1+1"), "\"'\r=1+1\"");
+    }
+}
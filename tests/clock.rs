@@ -0,0 +1,338 @@
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use discord_playdapp_bot::clock::{Clock, FakeClock};
+use discord_playdapp_bot::database::memory::MemoryStore;
+use discord_playdapp_bot::database::models::{
+    Activity, ActivityType, LottoEntryOutcome, LottoGuess, LottoPrize, LottoRound, LottoRules,
+    PointsReason,
+};
+use discord_playdapp_bot::database::store::Store;
+use discord_playdapp_bot::util::is_thu;
+
+fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+    NaiveDate::from_ymd_opt(year, month, day)
+        .unwrap()
+        .and_hms_opt(hour, minute, 0)
+        .unwrap()
+        .and_utc()
+}
+
+fn setup(now: DateTime<Utc>) -> (Arc<FakeClock>, MemoryStore) {
+    let clock = Arc::new(FakeClock::new(now));
+    let database = MemoryStore::new(clock.clone());
+    (clock, database)
+}
+
+fn activity(clock: &FakeClock, dc_id: u64, kind: ActivityType, message_id: i64) -> Activity {
+    Activity {
+        dc_id,
+        activity: Some(kind),
+        reward: 10,
+        message_id: Some(message_id),
+        created_at: clock.now(),
+        ..Default::default()
+    }
+}
+
+fn guess(clock: &FakeClock, dc_id: u64, numbers: Vec<i32>) -> LottoGuess {
+    LottoGuess {
+        id: Some(ObjectId::new()),
+        dc_id,
+        dc_username: Some(format!("user{}", dc_id)),
+        numbers,
+        round: LottoRound::at(clock.now()),
+        paid: Some(false),
+        dm_sent: Some(false),
+        created_at: clock.now(),
+        updated_at: clock.now(),
+        ..Default::default()
+    }
+}
+
+// A single digit with a fee, so every digit can be covered and the winners are known
+fn single_digit_rules() -> LottoRules {
+    LottoRules {
+        effective_from: DateTime::<Utc>::UNIX_EPOCH,
+        fee: 100,
+        entries_per_week: 2,
+        digits: 1,
+        prizes: vec![LottoPrize {
+            matches: 1,
+            points: 1001,
+            reward: None,
+        }],
+        free_periods: Vec::new(),
+        jackpot_share: 50,
+    }
+}
+
+async fn enter(
+    database: &MemoryStore,
+    guess: LottoGuess,
+    rules: &LottoRules,
+    now: DateTime<Utc>,
+) -> LottoEntryOutcome {
+    let fee = rules.fee_at(now);
+    database
+        .add_lotto_guess(
+            guess,
+            fee,
+            rules.jackpot_contribution(fee),
+            rules.entries_per_week_at(now),
+        )
+        .await
+        .unwrap()
+}
+
+#[test]
+fn exchange_requests_are_blocked_on_thursday_only() {
+    // Monday
+    let clock = FakeClock::new(at(2026, 10, 12, 0, 0));
+
+    let mut blocked = Vec::new();
+    for _ in 0..7 * 24 {
+        if is_thu(clock.now()) {
+            blocked.push(clock.now());
+        }
+        clock.advance(Duration::hours(1));
+    }
+
+    assert_eq!(blocked.len(), 24);
+    assert_eq!(blocked.first(), Some(&at(2026, 10, 15, 0, 0)));
+    assert_eq!(blocked.last(), Some(&at(2026, 10, 15, 23, 0)));
+}
+
+#[tokio::test]
+async fn activities_are_limited_per_day() {
+    let (clock, database) = setup(at(2026, 10, 12, 9, 0));
+
+    let mut reactions = 0;
+    let mut received = 0;
+    for message_id in 0..20 {
+        let react = activity(&clock, 1, ActivityType::React, message_id);
+        if database.add_reaction_activity(react).await.unwrap() {
+            reactions += 1;
+        }
+        let receive = activity(&clock, 1, ActivityType::Receive, message_id);
+        if database.add_reaction_activity(receive).await.unwrap() {
+            received += 1;
+        }
+    }
+    assert_eq!(reactions, 5);
+    assert_eq!(received, 10);
+
+    let mut polls = 0;
+    for message_id in 0..5 {
+        let poll = activity(&clock, 1, ActivityType::Poll, message_id);
+        if database.add_react_poll_activity(poll).await.unwrap() {
+            polls += 1;
+        }
+    }
+    assert_eq!(polls, 2);
+
+    // A minute before midnight is still the same day
+    clock.set(at(2026, 10, 12, 23, 59));
+    let react = activity(&clock, 1, ActivityType::React, 100);
+    assert!(!database.add_reaction_activity(react).await.unwrap());
+
+    clock.advance(Duration::minutes(1));
+    let react = activity(&clock, 1, ActivityType::React, 100);
+    assert!(database.add_reaction_activity(react).await.unwrap());
+
+    // A poll is only counted once, even on another day
+    let poll = activity(&clock, 1, ActivityType::Poll, 0);
+    assert!(!database.add_react_poll_activity(poll).await.unwrap());
+    let poll = activity(&clock, 1, ActivityType::Poll, 5);
+    assert!(database.add_react_poll_activity(poll).await.unwrap());
+}
+
+#[tokio::test]
+async fn attendance_is_checked_once_per_day_and_builds_the_streak() {
+    let (clock, database) = setup(at(2026, 10, 12, 9, 0));
+    let streak_bonus = BTreeMap::from([(3, 300)]);
+
+    for day in 1..=3 {
+        let attend = activity(&clock, 1, ActivityType::Attend, 0);
+        let reward = database
+            .record_attendance(attend, Some("user1"), &streak_bonus)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reward.streak.current, day);
+        assert_eq!(reward.bonus, if day == 3 { 300 } else { 0 });
+
+        let attend = activity(&clock, 1, ActivityType::Attend, 0);
+        let again = database
+            .record_attendance(attend, Some("user1"), &streak_bonus)
+            .await
+            .unwrap();
+        assert!(again.is_none());
+
+        clock.advance(Duration::days(1));
+    }
+    assert_eq!(database.get_user_points("1").await.unwrap(), 3 * 10 + 300);
+
+    // Missing a day breaks the streak but keeps the best one
+    clock.advance(Duration::days(1));
+    assert_eq!(database.get_user_streak("1").await.unwrap().current, 0);
+    let attend = activity(&clock, 1, ActivityType::Attend, 0);
+    let reward = database
+        .record_attendance(attend, Some("user1"), &streak_bonus)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reward.streak.current, 1);
+    assert_eq!(reward.streak.best, 3);
+}
+
+#[tokio::test]
+async fn lotto_rounds_roll_over_with_the_iso_week() {
+    // Sunday of the week 53 of 2026
+    let (clock, database) = setup(at(2027, 1, 3, 23, 59));
+    let rules = LottoRules::default();
+    database
+        .adjust_user_points("1", Some("user1"), 10000, PointsReason::Opening, None)
+        .await
+        .unwrap();
+
+    let last = database.add_weekly_draw().await.unwrap();
+    assert_eq!(
+        last.round,
+        LottoRound {
+            year: 2026,
+            week: 53
+        }
+    );
+    for _ in 0..rules.entries_per_week {
+        let outcome = enter(
+            &database,
+            guess(&clock, 1, vec![1, 2, 3, 4]),
+            &rules,
+            clock.now(),
+        )
+        .await;
+        assert!(matches!(outcome, LottoEntryOutcome::Accepted(_)));
+    }
+    let outcome = enter(
+        &database,
+        guess(&clock, 1, vec![1, 2, 3, 4]),
+        &rules,
+        clock.now(),
+    )
+    .await;
+    assert!(matches!(outcome, LottoEntryOutcome::LimitReached));
+
+    // Monday opens the week 1 of 2027 with a new limit, while the last round waits for its draw
+    clock.advance(Duration::minutes(1));
+    let current = database.add_weekly_draw().await.unwrap();
+    assert_eq!(
+        current.round,
+        LottoRound {
+            year: 2027,
+            week: 1
+        }
+    );
+    assert_eq!(current.round.previous(), last.round);
+    assert_ne!(current.commitment, last.commitment);
+    let outcome = enter(
+        &database,
+        guess(&clock, 1, vec![1, 2, 3, 4]),
+        &rules,
+        clock.now(),
+    )
+    .await;
+    assert!(matches!(outcome, LottoEntryOutcome::Accepted(_)));
+
+    let undrawn = database
+        .get_undrawn_lotto_rounds(LottoRound::at(clock.now()))
+        .await
+        .unwrap();
+    assert_eq!(undrawn, vec![last.round]);
+
+    // Opening the week again keeps its seed
+    let again = database.add_weekly_draw().await.unwrap();
+    assert_eq!(again.commitment, current.commitment);
+}
+
+#[tokio::test]
+async fn a_lotto_week_is_entered_drawn_and_paid() {
+    // Monday
+    let (clock, database) = setup(at(2026, 10, 12, 0, 0));
+    let rules = single_digit_rules();
+    database.add_lotto_rules(rules.clone());
+    let round = database.add_weekly_draw().await.unwrap().round;
+
+    // Twenty users cover every digit twice over the week, so two of them win
+    for dc_id in 0..20 {
+        database
+            .adjust_user_points(&dc_id.to_string(), None, 1000, PointsReason::Opening, None)
+            .await
+            .unwrap();
+        let numbers = vec![(dc_id % 10) as i32];
+        let outcome = enter(
+            &database,
+            guess(&clock, dc_id, numbers),
+            &rules,
+            clock.now(),
+        )
+        .await;
+        assert!(matches!(outcome, LottoEntryOutcome::Accepted(_)));
+        clock.advance(Duration::hours(6));
+    }
+    // 20 fees of 100 with half of each in the jackpot
+    let draw = database.get_lotto_round(round).await.unwrap().unwrap();
+    assert_eq!(draw.jackpot, 1000);
+
+    // The draw job runs on the next Monday
+    clock.set(at(2026, 10, 19, 0, 0));
+    let draw = database
+        .close_lotto_round(round, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(draw.numbers.len(), 1);
+    assert_eq!(draw.drawn_at, Some(clock.now()));
+    assert_eq!(draw.jackpot_winners, Some(2));
+    // The pool of 2001 is split evenly and the remainder goes to the next round
+    assert_eq!(draw.jackpot_prize, Some(1000));
+    assert_eq!(draw.jackpot_rollover, Some(1));
+    let current = database
+        .get_lotto_round(LottoRound::at(clock.now()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(current.jackpot, 1);
+
+    // A late entry for the drawn round is refused
+    let late = LottoGuess {
+        round,
+        ..guess(&clock, 0, vec![0])
+    };
+    let outcome = enter(&database, late, &rules, clock.now()).await;
+    assert!(matches!(outcome, LottoEntryOutcome::RoundClosed));
+
+    // The payout job pays every winner once
+    let winners = database.get_lotto_guesses(round, None).await.unwrap();
+    assert_eq!(winners.len(), 2);
+    for winner in &winners {
+        assert_eq!(winner.numbers, draw.numbers);
+        let paid = database
+            .pay_lotto_prize(winner.id.unwrap(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(paid.applied, 1000);
+        assert_eq!(paid.balance, 1000 - 100 + 1000);
+        assert!(database
+            .pay_lotto_prize(winner.id.unwrap(), None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    let drifts = database.reconcile_all_points().await.unwrap();
+    assert!(drifts.is_empty());
+}
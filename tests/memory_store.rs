@@ -0,0 +1,513 @@
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serenity::http::Http;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use discord_playdapp_bot::clock::{Clock, FakeClock};
+use discord_playdapp_bot::config::EnvConfig;
+use discord_playdapp_bot::database::memory::MemoryStore;
+use discord_playdapp_bot::database::models::{
+    Activity, ActivityType, CatalogItem, Exchange, ExchangeOutcome, ExchangeStatus,
+    FulfilmentResult, FulfilmentStatus, JobFence, JobState, LottoGuess, LottoRound, PointsReason,
+    WalletUpdate, MAX_POINTS,
+};
+use discord_playdapp_bot::database::store::Store;
+use discord_playdapp_bot::scheduler::runner::{Job, JobContext, JobScheduler};
+use discord_playdapp_bot::scheduler::{
+    CleanupJob, ExchangeProcessingJob, ExchangeReminderJob, LottoDrawJob, LottoPayoutJob,
+};
+use discord_playdapp_bot::util::build_exchange_csv;
+
+const WALLET: &str = "0x52908400098527886e0f7030069857d2e4169ee7";
+const OTHER_WALLET: &str = "0x8617e340b3d01fa5f11f306f4090fd50e238070d";
+
+fn at(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+    NaiveDate::from_ymd_opt(year, month, day)
+        .unwrap()
+        .and_hms_opt(hour, 0, 0)
+        .unwrap()
+        .and_utc()
+}
+
+fn config() -> EnvConfig {
+    EnvConfig {
+        discord_token: String::new(),
+        mongo_uri: String::new(),
+        discord_guild: 1,
+        attendance_channel: 2,
+        lotto_channel: 3,
+        streak_bonus: BTreeMap::new(),
+        admin_channel: 4,
+        wallet_cooldown_days: 7,
+    }
+}
+
+// The jobs run against the memory store, without a token for Discord
+fn setup(now: DateTime<Utc>) -> (Arc<FakeClock>, Arc<MemoryStore>, JobContext) {
+    let clock = Arc::new(FakeClock::new(now));
+    let database = Arc::new(MemoryStore::new(clock.clone()));
+    let context = JobContext {
+        database: database.clone(),
+        config: Arc::new(config()),
+        http: Arc::new(Http::new("")),
+        clock: clock.clone(),
+        fence: None,
+    };
+    (clock, database, context)
+}
+
+fn ticket() -> CatalogItem {
+    CatalogItem {
+        id: "ticket".to_string(),
+        name: "Ticket".to_string(),
+        price: 1000,
+        weekly_limit: Some(2),
+        stock: Some(3),
+        ..Default::default()
+    }
+}
+
+fn exchange(clock: &FakeClock, dc_id: u64, quantity: i64) -> Exchange {
+    Exchange {
+        id: Some(ObjectId::new()),
+        dc_id,
+        dc_username: format!("user{}", dc_id),
+        wallet_address: Some(WALLET.to_string()),
+        item: "ticket".to_string(),
+        quantity,
+        status: ExchangeStatus::Submitted,
+        created_at: clock.now(),
+        updated_at: clock.now(),
+        ..Default::default()
+    }
+}
+
+async fn request(database: &MemoryStore, exchange: Exchange) -> ExchangeOutcome {
+    database.add_exchange_record(exchange).await.unwrap()
+}
+
+async fn grant(database: &MemoryStore, user_id: &str, points: i32) -> i32 {
+    database
+        .adjust_user_points(user_id, None, points, PointsReason::Opening, None)
+        .await
+        .unwrap()
+        .applied
+}
+
+// Waits for the tasks of the scheduler to get the job to the state
+async fn wait_for_job(database: &MemoryStore, name: &str, done: impl Fn(&JobState) -> bool) {
+    for _ in 0..500 {
+        let state = database.get_job_state(name).await.unwrap();
+        if state.is_some_and(|state| done(&state)) {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("The job {} did not get to the expected state", name);
+}
+
+#[tokio::test]
+async fn exchanges_follow_the_catalog_limits() {
+    // Monday
+    let (clock, database, _) = setup(at(2026, 10, 12, 9));
+    database.set_catalog_item(&ticket()).await.unwrap();
+    grant(&database, "1", 2500).await;
+
+    let outcome = request(&database, exchange(&clock, 1, 3)).await;
+    assert!(matches!(outcome, ExchangeOutcome::LimitReached(2)));
+    let outcome = request(&database, exchange(&clock, 1, 2)).await;
+    assert!(matches!(outcome, ExchangeOutcome::Accepted(adjustment) if adjustment.balance == 500));
+    let outcome = request(&database, exchange(&clock, 1, 1)).await;
+    assert!(matches!(outcome, ExchangeOutcome::LimitReached(0)));
+
+    // The weekly limit starts again on Monday, unlike the stock
+    clock.set(at(2026, 10, 19, 0));
+    let outcome = request(&database, exchange(&clock, 1, 1)).await;
+    assert!(matches!(outcome, ExchangeOutcome::NotEnoughPoints));
+    grant(&database, "1", 2000).await;
+    let outcome = request(&database, exchange(&clock, 1, 2)).await;
+    assert!(matches!(outcome, ExchangeOutcome::OutOfStock));
+    let outcome = request(&database, exchange(&clock, 1, 1)).await;
+    assert!(matches!(outcome, ExchangeOutcome::Accepted(adjustment) if adjustment.balance == 1500));
+
+    // An item outside its window cannot be exchanged
+    let item = CatalogItem {
+        stock: None,
+        ends_at: Some(clock.now()),
+        ..ticket()
+    };
+    database.set_catalog_item(&item).await.unwrap();
+    let outcome = request(&database, exchange(&clock, 1, 1)).await;
+    assert!(matches!(outcome, ExchangeOutcome::Unavailable));
+
+    assert_eq!(database.get_user_records(1).await.unwrap().len(), 2);
+    assert!(database.reconcile_all_points().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn exchanges_are_processed_fulfilled_and_refunded() {
+    let (clock, database, context) = setup(at(2026, 10, 14, 9));
+    database
+        .set_catalog_item(&CatalogItem {
+            weekly_limit: None,
+            stock: None,
+            ..ticket()
+        })
+        .await
+        .unwrap();
+    grant(&database, "1", 3000).await;
+    grant(&database, "2", 3000).await;
+    for dc_id in [1, 1, 2] {
+        let outcome = request(&database, exchange(&clock, dc_id, 1)).await;
+        assert!(matches!(outcome, ExchangeOutcome::Accepted(_)));
+    }
+
+    // The batch is moved to processing on Friday
+    clock.set(at(2026, 10, 16, 0));
+    let preview = ExchangeProcessingJob.run(&context, true).await.unwrap();
+    assert_eq!(preview, "3 exchange(s) would be moved to processing");
+    let batch = database
+        .update_all_submitted_to_processing(None)
+        .await
+        .unwrap();
+    assert_eq!(batch.len(), 3);
+
+    // The requests to the same wallet are sent together
+    let csv = build_exchange_csv(&batch);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(",3,1;2,user1;user2,"));
+
+    let completed = FulfilmentResult {
+        exchange_id: batch[0].id.unwrap().to_hex(),
+        status: FulfilmentStatus::Completed,
+        tx_hash: Some("0xabc".to_string()),
+        error: None,
+    };
+    let exchange = database
+        .apply_fulfilment_result(batch[0].id.unwrap(), &completed)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(exchange.status, ExchangeStatus::Completed);
+    assert_eq!(exchange.updated_at, clock.now());
+
+    // A failed delivery stays processing until the admins decide
+    let failed = FulfilmentResult {
+        exchange_id: batch[2].id.unwrap().to_hex(),
+        status: FulfilmentStatus::Failed,
+        tx_hash: None,
+        error: Some("Invalid wallet".to_string()),
+    };
+    let exchange = database
+        .apply_fulfilment_result(batch[2].id.unwrap(), &failed)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(exchange.status, ExchangeStatus::Processing);
+    clock.set(at(2026, 10, 17, 0));
+    let reminder = ExchangeReminderJob.run(&context, true).await.unwrap();
+    assert_eq!(reminder, "2 exchange(s) are still processing");
+
+    // Rejecting gives the points back once
+    let rejected = database
+        .update_exchange_status(
+            batch[2].id.unwrap(),
+            &[ExchangeStatus::Submitted, ExchangeStatus::Processing],
+            ExchangeStatus::Rejected,
+            Some("Invalid wallet".to_string()),
+        )
+        .await
+        .unwrap();
+    assert!(rejected.is_some());
+    let refunded = database
+        .refund_exchange(batch[2].id.unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(refunded.status, ExchangeStatus::Refunded);
+    assert!(database
+        .refund_exchange(batch[2].id.unwrap())
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(database.get_user_points("2").await.unwrap(), 3000);
+    assert_eq!(database.get_user_points("1").await.unwrap(), 1000);
+    assert!(database.reconcile_all_points().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn wallets_change_after_the_cooldown() {
+    let (clock, database, context) = setup(at(2026, 10, 12, 9));
+    let cooldown = Duration::days(context.config.wallet_cooldown_days);
+
+    let update = database
+        .set_user_wallet("1", "user1", WALLET, false, cooldown)
+        .await
+        .unwrap();
+    assert!(matches!(update, WalletUpdate::Changed(_)));
+
+    clock.advance(Duration::days(6));
+    let update = database
+        .set_user_wallet("1", "user1", OTHER_WALLET, false, cooldown)
+        .await
+        .unwrap();
+    assert!(matches!(update, WalletUpdate::Cooldown));
+    // Verifying the same wallet is allowed within the cooldown
+    let update = database
+        .set_user_wallet("1", "user1", WALLET, true, cooldown)
+        .await
+        .unwrap();
+    assert!(matches!(update, WalletUpdate::Changed(_)));
+    let update = database
+        .set_user_wallet("1", "user1", WALLET, true, cooldown)
+        .await
+        .unwrap();
+    assert!(matches!(update, WalletUpdate::Unchanged));
+
+    clock.advance(Duration::days(1));
+    let update = database
+        .set_user_wallet("1", "user1", OTHER_WALLET, false, cooldown)
+        .await
+        .unwrap();
+    match update {
+        WalletUpdate::Changed(audit) => {
+            assert_eq!(audit.previous_address.as_deref(), Some(WALLET));
+            assert_eq!(audit.created_at, clock.now());
+        }
+        _ => panic!("The wallet should be changed after the cooldown"),
+    }
+    assert_eq!(database.get_wallet_audits("1", 0).await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn points_stay_within_the_bounds() {
+    let (_, database, _) = setup(at(2026, 10, 12, 9));
+
+    assert_eq!(
+        grant(&database, "1", MAX_POINTS - 10).await,
+        MAX_POINTS - 10
+    );
+    assert_eq!(grant(&database, "1", 100).await, 10);
+    assert_eq!(grant(&database, "2", 50).await, 50);
+    // A penalty stops at zero
+    let penalty = database
+        .adjust_user_points("2", None, -80, PointsReason::BadEmoji, None)
+        .await
+        .unwrap();
+    assert_eq!(penalty.applied, -50);
+    assert_eq!(penalty.balance, 0);
+    // A spend must be covered by the balance
+    grant(&database, "3", 50).await;
+    let spend = database
+        .adjust_user_points("3", None, -80, PointsReason::LottoFee, None)
+        .await
+        .unwrap();
+    assert_eq!(spend.applied, 0);
+    assert_eq!(spend.balance, 50);
+
+    let top = database.get_top_users(10).await.unwrap();
+    let ids: Vec<&str> = top.iter().map(|user| user.dc_id.as_str()).collect();
+    assert_eq!(ids, vec!["1", "3"]);
+    assert_eq!(database.get_user_rank("3").await.unwrap(), Some((2, 50)));
+    assert_eq!(database.get_user_rank("2").await.unwrap(), None);
+    assert!(database.reconcile_all_points().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn lotto_jobs_preview_the_draw_and_the_payout() {
+    // Wednesday
+    let (clock, database, context) = setup(at(2026, 10, 14, 9));
+    let round = database.add_weekly_draw().await.unwrap().round;
+    grant(&database, "1", 1000).await;
+    let guess = LottoGuess {
+        id: Some(ObjectId::new()),
+        dc_id: 1,
+        numbers: vec![1, 2, 3, 4],
+        round,
+        created_at: clock.now(),
+        updated_at: clock.now(),
+        ..Default::default()
+    };
+    database.add_lotto_guess(guess, 200, 100, 5).await.unwrap();
+
+    let preview = LottoDrawJob.run(&context, true).await.unwrap();
+    assert_eq!(
+        preview,
+        format!(
+            "No round is waiting for its draw, {} is already open",
+            round
+        )
+    );
+
+    // A week without the bot leaves the round undrawn
+    clock.set(at(2026, 10, 26, 0));
+    let current = LottoRound::at(clock.now());
+    let preview = LottoDrawJob.run(&context, true).await.unwrap();
+    assert_eq!(
+        preview,
+        format!("{} would be drawn, {} would be opened", round, current)
+    );
+    let preview = LottoPayoutJob.run(&context, true).await.unwrap();
+    assert_eq!(
+        preview,
+        format!(
+            "0 of 0 winning ticket(s) of {} would be paid and 0 DM(s) would be sent",
+            current.previous()
+        )
+    );
+
+    database.close_lotto_round(round, None).await.unwrap();
+    assert!(database
+        .get_undrawn_lotto_rounds(current)
+        .await
+        .unwrap()
+        .is_empty());
+    let preview = LottoDrawJob.run(&context, true).await.unwrap();
+    assert_eq!(
+        preview,
+        format!(
+            "No round is waiting for its draw, {} is already open",
+            current
+        )
+    );
+}
+
+#[tokio::test]
+async fn a_live_lease_is_not_taken_again() {
+    let (clock, database, _) = setup(at(2026, 10, 12, 9));
+    let ttl = Duration::minutes(5);
+
+    let token = database
+        .acquire_job_lease("cleanup", "owner", ttl)
+        .await
+        .unwrap();
+    assert_eq!(token, Some(1));
+    // Not even by its owner, whose admin run would overlap the scheduled one
+    let again = database.acquire_job_lease("cleanup", "owner", ttl).await;
+    assert_eq!(again.unwrap(), None);
+    let other = database.acquire_job_lease("cleanup", "other", ttl).await;
+    assert_eq!(other.unwrap(), None);
+
+    // A renewed lease stays held past its first expiry
+    clock.advance(Duration::minutes(4));
+    assert!(database
+        .renew_job_lease("cleanup", "owner", 1, ttl)
+        .await
+        .unwrap());
+    clock.advance(Duration::minutes(4));
+    let again = database.acquire_job_lease("cleanup", "owner", ttl).await;
+    assert_eq!(again.unwrap(), None);
+
+    database
+        .release_job_lease("cleanup", "owner", 1)
+        .await
+        .unwrap();
+    let next = database.acquire_job_lease("cleanup", "owner", ttl).await;
+    assert_eq!(next.unwrap(), Some(2));
+}
+
+#[tokio::test]
+async fn a_run_that_lost_its_lease_cannot_move_the_batch() {
+    let (clock, database, _) = setup(at(2026, 10, 12, 9));
+    let ttl = Duration::minutes(5);
+    database.set_catalog_item(&ticket()).await.unwrap();
+    grant(&database, "1", 3000).await;
+    let outcome = request(&database, exchange(&clock, 1, 1)).await;
+    assert!(matches!(outcome, ExchangeOutcome::Accepted(_)));
+
+    let token = database
+        .acquire_job_lease("exchange-processing", "owner", ttl)
+        .await
+        .unwrap()
+        .unwrap();
+    let stale = JobFence {
+        job: "exchange-processing",
+        token,
+    };
+
+    // The run stalls past its lease, which another instance takes over
+    clock.advance(Duration::minutes(6));
+    let taken = database
+        .acquire_job_lease("exchange-processing", "other", ttl)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(database
+        .update_all_submitted_to_processing(Some(stale))
+        .await
+        .is_err());
+
+    let current = JobFence {
+        job: "exchange-processing",
+        token: taken,
+    };
+    let batch = database
+        .update_all_submitted_to_processing(Some(current))
+        .await
+        .unwrap();
+    assert_eq!(batch.len(), 1);
+}
+
+#[tokio::test]
+async fn a_job_run_is_recorded_under_its_lease() {
+    let (clock, database, context) = setup(at(2026, 10, 12, 9));
+    let old = Activity {
+        dc_id: 1,
+        activity: Some(ActivityType::React),
+        created_at: clock.now(),
+        ..Default::default()
+    };
+    database.add_reaction_activity(old).await.unwrap();
+
+    clock.advance(Duration::weeks(6));
+    let preview = CleanupJob.run(&context, true).await.unwrap();
+    assert_eq!(preview, "1 documents would be deleted");
+
+    let scheduler = JobScheduler::new(context);
+    let lease = database
+        .acquire_job_lease("cleanup", "other", Duration::minutes(5))
+        .await
+        .unwrap();
+    assert!(lease.is_some());
+    assert!(scheduler.run_now(&CleanupJob, false).await.is_none());
+
+    // The lease of the other instance expires
+    clock.advance(Duration::minutes(5));
+    let result = scheduler.run_now(&CleanupJob, false).await;
+    assert_eq!(result, Some(Ok("Deleted 1 documents".to_string())));
+    let state = database.get_job_state("cleanup").await.unwrap().unwrap();
+    assert_eq!(state.last_succeeded, Some(true));
+    assert_eq!(state.last_run_at, Some(clock.now()));
+    // A run started by an admin has no scheduled time
+    assert_eq!(state.last_scheduled_at, None);
+}
+
+#[tokio::test]
+async fn a_scheduled_job_runs_when_the_clock_reaches_its_time() {
+    let (clock, database, context) = setup(at(2026, 10, 12, 9));
+    let old = Activity {
+        dc_id: 1,
+        activity: Some(ActivityType::React),
+        created_at: clock.now() - Duration::weeks(6),
+        ..Default::default()
+    };
+    database.add_reaction_activity(old).await.unwrap();
+
+    let mut scheduler = JobScheduler::new(context);
+    scheduler.register(CleanupJob);
+    Arc::new(scheduler).start();
+
+    // The job waits for the first day of the next month
+    wait_for_job(&database, "cleanup", |state| state.next_run_at.is_some()).await;
+    let state = database.get_job_state("cleanup").await.unwrap().unwrap();
+    assert_eq!(state.next_run_at, Some(at(2026, 11, 1, 0)));
+    assert_eq!(state.last_run_at, None);
+
+    clock.set(at(2026, 11, 1, 0));
+    wait_for_job(&database, "cleanup", |state| state.last_run_at.is_some()).await;
+    let state = database.get_job_state("cleanup").await.unwrap().unwrap();
+    assert_eq!(state.last_scheduled_at, Some(at(2026, 11, 1, 0)));
+    assert_eq!(state.last_result.as_deref(), Some("Deleted 1 documents"));
+}